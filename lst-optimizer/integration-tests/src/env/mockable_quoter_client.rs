@@ -1,5 +1,5 @@
 use anyhow::Result;
-use quoter_lib::typedefs::{QuoterClient, SwapInstructions};
+use quoter_lib::typedefs::{Quote, QuoterClient, RouteConstraints, SwapInstructions, SwapMode};
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::instruction::Instruction;
 use solana_sdk::pubkey::Pubkey;
@@ -36,6 +36,28 @@ impl QuoterClient for MockableQuoterClient {
         &self.rpc
     }
 
+    async fn quote(
+        &self,
+        src_mint: &Pubkey,
+        dst_mint: &Pubkey,
+        amount: u64,
+        mode: SwapMode,
+    ) -> Result<Quote> {
+        // This is a mock implementation, so the swap is quoted without any loss
+        Ok(Quote {
+            src_mint: *src_mint,
+            dst_mint: *dst_mint,
+            mode,
+            in_amount: amount,
+            out_amount: amount,
+            min_out_amount: amount,
+            max_in_amount: amount,
+            slippage_bps: 0,
+            price_impact_pct: 0.0,
+            route_labels: vec![],
+        })
+    }
+
     async fn create_swap_instructions(
         &self,
         _swapper: &Pubkey,
@@ -45,13 +67,17 @@ impl QuoterClient for MockableQuoterClient {
         _amount: u64,
         _min_amount_out: u64,
         _slippage_bps: Option<u16>,
+        _route_constraints: RouteConstraints,
     ) -> Result<SwapInstructions> {
         // This is a mock implementation, so we can just return a dummy transfer instruction
         Ok(SwapInstructions {
+            compute_budget_instructions: vec![],
             setup_instructions: self.setup_instructions.clone().unwrap(),
             swap_instructions: self.swap_instructions.clone().unwrap(),
             cleanup_instructions: vec![],
+            other_instructions: vec![],
             address_lookup_tables: vec![],
+            quote: None,
         })
     }
 }
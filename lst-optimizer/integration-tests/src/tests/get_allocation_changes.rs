@@ -1,5 +1,6 @@
 use lst_optimizer_std::{
     allocator::{AllocationRatio, AllocationRatios},
+    pool::PoolAllocable,
     types::pool_allocation::MAX_ALLOCATION_BPS,
 };
 use moose_utils::result::Result;
@@ -32,9 +33,10 @@ async fn test_get_allocation_changes() -> Result<()> {
         AllocationRatio::new(&wsol_mint.to_string(), MAX_ALLOCATION_BPS),
     ]);
 
-    // get_pool_allocation_changes fetches the pool's reserves and calculates the changes via calculator
+    // get_pool_allocation_changes calculates the changes of the pool's reserves via calculator
+    let current_pool_allocations = optimizer.get_pool().get_allocation(&context).await?;
     let pool_allocation = optimizer
-        .get_pool_allocation_changes(&context, deallocation_ratios)
+        .get_pool_allocation_changes(&context, &current_pool_allocations, deallocation_ratios)
         .await?;
 
     assert_eq!(pool_allocation.assets.len(), 3);
@@ -69,10 +69,15 @@ async fn rebalance_asset_with_return(
 
     let (optimizer, context, _) = new_lst_optimizer_app_with_quoter(quoter_client);
 
-    optimizer
+    let outcome = optimizer
         .get_pool()
         .rebalance_asset(&context, &pool_asset_change)
-        .await?;
+        .await;
+    if let Some(error) = outcome.error {
+        return Err(anyhow::Error::from(error).into());
+    }
+    assert!(outcome.withdrawn > 0);
+    assert_eq!(outcome.returned, return_amount);
 
     Ok(())
 }
@@ -0,0 +1,195 @@
+use solana_sdk::{compute_budget, instruction::Instruction, pubkey::Pubkey};
+
+pub const MAX_COMPUTE_UNIT_LIMIT: u32 = 1_400_000;
+
+/// The most accounts `getRecentPrioritizationFees` takes
+pub const MAX_PRIORITIZATION_FEE_ACCOUNTS: usize = 128;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum ComputeUnitLimit {
+    /// Let the runtime apply its default limit
+    #[default]
+    Default,
+    Fixed(u32),
+    /// The units consumed by a simulation plus a margin in bps
+    Simulated {
+        margin_bps: u16,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum PriorityFee {
+    #[default]
+    None,
+    /// A fixed price in micro-lamports per compute unit
+    Fixed(u64),
+    /// A percentile of the recent prioritization fees paid for the writable accounts,
+    /// capped by a max price in micro-lamports per compute unit, the fallback price is
+    /// paid when the recent fees fail to be fetched
+    RecentPercentile {
+        percentile: u8,
+        max_micro_lamports: Option<u64>,
+        fallback_micro_lamports: u64,
+    },
+}
+
+/// Compute budget instructions prepended to the sent transactions
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ComputeBudgetOptions {
+    pub unit_limit: ComputeUnitLimit,
+    pub priority_fee: PriorityFee,
+}
+
+/// The units consumed plus the margin, capped by the max compute unit limit
+pub fn compute_unit_limit_with_margin(units_consumed: u64, margin_bps: u16) -> u32 {
+    let units = units_consumed as u128 * (10_000 + margin_bps as u128);
+    units.div_ceil(10_000).min(MAX_COMPUTE_UNIT_LIMIT as u128) as u32
+}
+
+/// The nearest-rank percentile of the fees, zero without any fee
+pub fn fee_percentile(fees: &[u64], percentile: u8) -> u64 {
+    if fees.is_empty() {
+        return 0;
+    }
+    let mut fees = fees.to_vec();
+    fees.sort_unstable();
+    let percentile = percentile.min(100) as usize;
+    let rank = (percentile * fees.len()).div_ceil(100).max(1);
+    fees[rank - 1]
+}
+
+/// The unique writable accounts of the instructions, the ones the fee markets are keyed by.
+/// Only the first ones in instruction order are kept under the max the RPC takes, the pool
+/// accounts of a rebalance come before the accounts of its swap route.
+pub fn writable_accounts(instructions: &[Instruction]) -> Vec<Pubkey> {
+    let mut accounts: Vec<Pubkey> = vec![];
+    for instruction in instructions.iter() {
+        for account in instruction.accounts.iter() {
+            if accounts.len() == MAX_PRIORITIZATION_FEE_ACCOUNTS {
+                return accounts;
+            }
+            if account.is_writable && !accounts.contains(&account.pubkey) {
+                accounts.push(account.pubkey);
+            }
+        }
+    }
+    accounts
+}
+
+/// Prepend the compute budget instructions, dropping the ones of the same kinds
+/// from the instructions as a transaction fails on duplicated compute budget instructions
+pub fn replace_compute_budget_instructions(
+    budget_instructions: Vec<Instruction>,
+    instructions: &[Instruction],
+) -> Vec<Instruction> {
+    let kind = |instruction: &Instruction| -> Option<u8> {
+        if instruction.program_id.eq(&compute_budget::id()) {
+            instruction.data.first().cloned()
+        } else {
+            None
+        }
+    };
+    let replaced_kinds: Vec<u8> = budget_instructions.iter().filter_map(kind).collect();
+    let mut replaced = budget_instructions;
+    replaced.extend(
+        instructions
+            .iter()
+            .filter(|instruction| match kind(instruction) {
+                Some(kind) => !replaced_kinds.contains(&kind),
+                None => true,
+            })
+            .cloned(),
+    );
+    replaced
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{compute_budget::ComputeBudgetInstruction, instruction::AccountMeta};
+
+    use super::*;
+
+    #[test]
+    fn test_compute_unit_limit_with_margin() {
+        assert_eq!(compute_unit_limit_with_margin(200_000, 1000), 220_000);
+        assert_eq!(compute_unit_limit_with_margin(100_001, 0), 100_001);
+        assert_eq!(
+            compute_unit_limit_with_margin(1_300_000, 2000),
+            MAX_COMPUTE_UNIT_LIMIT
+        );
+    }
+
+    #[test]
+    fn test_fee_percentile() {
+        assert_eq!(fee_percentile(&[], 50), 0);
+        let fees = [500, 0, 100, 300, 200];
+        assert_eq!(fee_percentile(&fees, 0), 0);
+        assert_eq!(fee_percentile(&fees, 50), 200);
+        assert_eq!(fee_percentile(&fees, 75), 300);
+        assert_eq!(fee_percentile(&fees, 100), 500);
+    }
+
+    #[test]
+    fn test_writable_accounts() {
+        let (a, b, c) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let instructions = vec![
+            Instruction::new_with_bytes(
+                Pubkey::new_unique(),
+                &[],
+                vec![
+                    AccountMeta::new(a, false),
+                    AccountMeta::new_readonly(b, false),
+                ],
+            ),
+            Instruction::new_with_bytes(
+                Pubkey::new_unique(),
+                &[],
+                vec![AccountMeta::new(a, false), AccountMeta::new(c, false)],
+            ),
+        ];
+        assert_eq!(writable_accounts(&instructions), vec![a, c]);
+
+        let many = Instruction::new_with_bytes(
+            Pubkey::new_unique(),
+            &[],
+            (0..200)
+                .map(|_| AccountMeta::new(Pubkey::new_unique(), false))
+                .collect(),
+        );
+        let accounts = writable_accounts(&[instructions[0].clone(), many.clone()]);
+        assert_eq!(accounts.len(), MAX_PRIORITIZATION_FEE_ACCOUNTS);
+        assert_eq!(accounts[0], a);
+        assert_eq!(accounts[1], many.accounts[0].pubkey);
+    }
+
+    #[test]
+    fn test_replace_compute_budget_instructions() {
+        let swap = Instruction::new_with_bytes(Pubkey::new_unique(), &[1], vec![]);
+        let instructions = vec![
+            ComputeBudgetInstruction::set_compute_unit_limit(1_400_000),
+            ComputeBudgetInstruction::set_compute_unit_price(1),
+            swap.clone(),
+        ];
+
+        let replaced = replace_compute_budget_instructions(
+            vec![ComputeBudgetInstruction::set_compute_unit_price(1000)],
+            &instructions,
+        );
+        assert_eq!(
+            replaced,
+            vec![
+                ComputeBudgetInstruction::set_compute_unit_price(1000),
+                ComputeBudgetInstruction::set_compute_unit_limit(1_400_000),
+                swap.clone(),
+            ]
+        );
+        assert_eq!(
+            replace_compute_budget_instructions(vec![], &instructions),
+            instructions
+        );
+    }
+}
@@ -1,27 +1,50 @@
 use anyhow::Result;
 use base64::Engine;
-use lst_optimizer_utils::logger::info;
+use lst_optimizer_utils::logger::{info, warn};
 use solana_client::{
-    nonblocking::rpc_client::RpcClient, rpc_config::RpcSimulateTransactionConfig,
+    nonblocking::rpc_client::RpcClient,
+    rpc_config::{RpcSimulateTransactionConfig, RpcTransactionConfig},
     rpc_response::RpcSimulateTransactionResult,
 };
 use solana_sdk::{
     address_lookup_table::AddressLookupTableAccount,
+    compute_budget::ComputeBudgetInstruction,
+    hash::Hash,
     instruction::Instruction,
     message::{v0::Message, VersionedMessage},
+    pubkey::Pubkey,
     signature::{Keypair, Signature},
     transaction::VersionedTransaction,
 };
 use solana_sdk::{commitment_config::CommitmentConfig, signature::Signer};
-use solana_transaction_status::UiTransactionReturnData;
+use solana_transaction_status::{UiTransactionEncoding, UiTransactionReturnData};
+
+use crate::{
+    compute_budget::{
+        compute_unit_limit_with_margin, fee_percentile, replace_compute_budget_instructions,
+        writable_accounts, ComputeBudgetOptions, ComputeUnitLimit, PriorityFee,
+    },
+    transaction::{fits_in_packet, ConfirmOptions, TransactionOutcome, TransactionReceipt},
+};
 
 pub struct ControllerClient {
     rpc_client: RpcClient,
+    compute_budget: ComputeBudgetOptions,
 }
 
 impl ControllerClient {
     pub fn new(rpc_client: RpcClient) -> Self {
-        Self { rpc_client }
+        Self {
+            rpc_client,
+            compute_budget: ComputeBudgetOptions::default(),
+        }
+    }
+
+    pub fn with_compute_budget(self, compute_budget: ComputeBudgetOptions) -> Self {
+        Self {
+            compute_budget,
+            ..self
+        }
     }
 
     pub fn rpc_client(&self) -> &RpcClient {
@@ -30,6 +53,7 @@ impl ControllerClient {
 
     // Invoke the instructions simulation on the RPC client and return the return data
 
+    /// Build the transaction to send, with the compute budget instructions prepended
     async fn build_transaction(
         &self,
         payer: &Keypair,
@@ -37,7 +61,139 @@ impl ControllerClient {
         address_lookup_table_accounts: &[AddressLookupTableAccount],
     ) -> Result<VersionedTransaction> {
         let rpc = self.rpc_client();
+        let instructions = self
+            .with_compute_budget_instructions(payer, instructions, address_lookup_table_accounts)
+            .await?;
         let recent_blockhash = rpc.get_latest_blockhash().await?;
+        self.build_transaction_with_blockhash(
+            payer,
+            &instructions,
+            address_lookup_table_accounts,
+            recent_blockhash,
+        )
+    }
+
+    /// Build the transaction to simulate, without any compute budget instruction
+    async fn build_simulation_transaction(
+        &self,
+        payer: &Keypair,
+        instructions: &[Instruction],
+        address_lookup_table_accounts: &[AddressLookupTableAccount],
+    ) -> Result<VersionedTransaction> {
+        let rpc = self.rpc_client();
+        let recent_blockhash = rpc.get_latest_blockhash().await?;
+        self.build_transaction_with_blockhash(
+            payer,
+            instructions,
+            address_lookup_table_accounts,
+            recent_blockhash,
+        )
+    }
+
+    /// Prepend the compute unit limit and price instructions of the compute budget options
+    pub async fn with_compute_budget_instructions(
+        &self,
+        payer: &Keypair,
+        instructions: &[Instruction],
+        address_lookup_table_accounts: &[AddressLookupTableAccount],
+    ) -> Result<Vec<Instruction>> {
+        let mut budget_instructions: Vec<Instruction> = vec![];
+
+        match self.compute_budget.unit_limit {
+            ComputeUnitLimit::Default => {}
+            ComputeUnitLimit::Fixed(units) => {
+                budget_instructions.push(ComputeBudgetInstruction::set_compute_unit_limit(units));
+            }
+            ComputeUnitLimit::Simulated { margin_bps } => {
+                let result = self
+                    .simulate_instructions(payer, instructions, address_lookup_table_accounts)
+                    .await?;
+                // A failing simulation does not tell the units the transaction needs
+                if let (None, Some(units_consumed)) = (result.err, result.units_consumed) {
+                    budget_instructions.push(ComputeBudgetInstruction::set_compute_unit_limit(
+                        compute_unit_limit_with_margin(units_consumed, margin_bps),
+                    ));
+                }
+            }
+        }
+
+        match self.compute_budget.priority_fee {
+            PriorityFee::None => {}
+            PriorityFee::Fixed(micro_lamports) => {
+                budget_instructions.push(ComputeBudgetInstruction::set_compute_unit_price(
+                    micro_lamports,
+                ));
+            }
+            PriorityFee::RecentPercentile {
+                percentile,
+                max_micro_lamports,
+                fallback_micro_lamports,
+            } => {
+                let accounts = writable_accounts(instructions);
+                let mut micro_lamports = match self
+                    .rpc_client()
+                    .get_recent_prioritization_fees(&accounts)
+                    .await
+                {
+                    Ok(fees) => {
+                        let fees: Vec<u64> =
+                            fees.iter().map(|fee| fee.prioritization_fee).collect();
+                        fee_percentile(&fees, percentile)
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Failed to fetch the recent prioritization fees, paying {}: {}",
+                            fallback_micro_lamports, e
+                        );
+                        fallback_micro_lamports
+                    }
+                };
+                if let Some(max_micro_lamports) = max_micro_lamports {
+                    micro_lamports = micro_lamports.min(max_micro_lamports);
+                }
+                info!("Priority fee: {} micro-lamports per CU", micro_lamports);
+                budget_instructions.push(ComputeBudgetInstruction::set_compute_unit_price(
+                    micro_lamports,
+                ));
+            }
+        }
+
+        Ok(replace_compute_budget_instructions(
+            budget_instructions,
+            instructions,
+        ))
+    }
+
+    /// Whether the instructions fit in a single transaction once the compute budget
+    /// instructions are prepended
+    pub fn fits_in_transaction(
+        &self,
+        payer: &Pubkey,
+        instructions: &[Instruction],
+        address_lookup_table_accounts: &[AddressLookupTableAccount],
+    ) -> Result<bool> {
+        // Placeholders with the size of the instructions prepended when sending
+        let mut budget_instructions: Vec<Instruction> = vec![];
+        if self.compute_budget.unit_limit != ComputeUnitLimit::Default {
+            budget_instructions.push(ComputeBudgetInstruction::set_compute_unit_limit(0));
+        }
+        if self.compute_budget.priority_fee != PriorityFee::None {
+            budget_instructions.push(ComputeBudgetInstruction::set_compute_unit_price(0));
+        }
+        fits_in_packet(
+            payer,
+            &replace_compute_budget_instructions(budget_instructions, instructions),
+            address_lookup_table_accounts,
+        )
+    }
+
+    fn build_transaction_with_blockhash(
+        &self,
+        payer: &Keypair,
+        instructions: &[Instruction],
+        address_lookup_table_accounts: &[AddressLookupTableAccount],
+        recent_blockhash: Hash,
+    ) -> Result<VersionedTransaction> {
         let compiled_message = Message::try_compile(
             &payer.pubkey(),
             &instructions,
@@ -68,6 +224,169 @@ impl ControllerClient {
         Ok(ret)
     }
 
+    /// Send the instructions and wait for them to land at the commitment, an expired
+    /// transaction is signed again with a fresh blockhash and resent
+    pub async fn send_and_confirm_instructions(
+        &self,
+        payer: &Keypair,
+        instructions: &[Instruction],
+        address_lookup_table_accounts: &[AddressLookupTableAccount],
+        options: &ConfirmOptions,
+    ) -> Result<TransactionOutcome> {
+        self.send_and_confirm_instructions_with_hook(
+            payer,
+            instructions,
+            address_lookup_table_accounts,
+            options,
+            &|_, _| Ok(()),
+        )
+        .await
+    }
+
+    /// Send and confirm the instructions as `send_and_confirm_instructions`, the hook is
+    /// given the signature and last valid block height of every attempt before it is sent,
+    /// so the attempt is recorded even when the process dies before it lands
+    pub async fn send_and_confirm_instructions_with_hook(
+        &self,
+        payer: &Keypair,
+        instructions: &[Instruction],
+        address_lookup_table_accounts: &[AddressLookupTableAccount],
+        options: &ConfirmOptions,
+        on_signed: &(dyn Fn(&Signature, u64) -> Result<()> + Sync),
+    ) -> Result<TransactionOutcome> {
+        let rpc = self.rpc_client();
+        let mut signature = Signature::default();
+        for attempt in 0..=options.max_resends {
+            let budgeted_instructions = self
+                .with_compute_budget_instructions(
+                    payer,
+                    instructions,
+                    address_lookup_table_accounts,
+                )
+                .await?;
+            let (recent_blockhash, last_valid_block_height) = rpc
+                .get_latest_blockhash_with_commitment(options.commitment)
+                .await?;
+            let tx = self.build_transaction_with_blockhash(
+                payer,
+                &budgeted_instructions,
+                address_lookup_table_accounts,
+                recent_blockhash,
+            )?;
+            on_signed(&tx.signatures[0], last_valid_block_height)?;
+            signature = rpc.send_transaction(&tx).await?;
+            info!("Sent transaction {} (attempt {})", signature, attempt + 1);
+
+            loop {
+                if let Some(outcome) = self.get_transaction_outcome(&signature, options).await? {
+                    return Ok(outcome);
+                }
+                let block_height = rpc
+                    .get_block_height_with_commitment(options.commitment)
+                    .await?;
+                if block_height > last_valid_block_height {
+                    break;
+                }
+                tokio::time::sleep(options.poll_interval).await;
+            }
+
+            // The transaction may have landed right before the blockhash expired
+            if let Some(outcome) = self.get_transaction_outcome(&signature, options).await? {
+                return Ok(outcome);
+            }
+            info!("Transaction {} expired", signature);
+        }
+        Ok(TransactionOutcome::Expired { signature })
+    }
+
+    async fn get_transaction_outcome(
+        &self,
+        signature: &Signature,
+        options: &ConfirmOptions,
+    ) -> Result<Option<TransactionOutcome>> {
+        let statuses = self
+            .rpc_client()
+            .get_signature_statuses(&[*signature])
+            .await?
+            .value;
+        let Some(Some(status)) = statuses.first() else {
+            return Ok(None);
+        };
+        if let Some(error) = &status.err {
+            return Ok(Some(TransactionOutcome::Failed {
+                signature: *signature,
+                error: error.clone(),
+            }));
+        }
+        if status.satisfies_commitment(options.commitment) {
+            return Ok(Some(TransactionOutcome::Landed {
+                signature: *signature,
+                slot: status.slot,
+            }));
+        }
+        Ok(None)
+    }
+
+    /// The outcome of a transaction sent earlier, searched in the ledger history as well,
+    /// none while it has not landed at the commitment
+    pub async fn find_transaction_outcome(
+        &self,
+        signature: &Signature,
+        commitment: CommitmentConfig,
+    ) -> Result<Option<TransactionOutcome>> {
+        let statuses = self
+            .rpc_client()
+            .get_signature_statuses_with_history(&[*signature])
+            .await?
+            .value;
+        let Some(Some(status)) = statuses.first() else {
+            return Ok(None);
+        };
+        if let Some(error) = &status.err {
+            return Ok(Some(TransactionOutcome::Failed {
+                signature: *signature,
+                error: error.clone(),
+            }));
+        }
+        if status.satisfies_commitment(commitment) {
+            return Ok(Some(TransactionOutcome::Landed {
+                signature: *signature,
+                slot: status.slot,
+            }));
+        }
+        Ok(None)
+    }
+
+    /// The fee and token balance changes of a transaction landed at the commitment,
+    /// the ledger serves transactions from the confirmed commitment on
+    pub async fn get_transaction_receipt(
+        &self,
+        signature: &Signature,
+        commitment: CommitmentConfig,
+    ) -> Result<Option<TransactionReceipt>> {
+        let commitment = if commitment.is_at_least_confirmed() {
+            commitment
+        } else {
+            CommitmentConfig::confirmed()
+        };
+        let transaction = self
+            .rpc_client()
+            .get_transaction_with_config(
+                signature,
+                RpcTransactionConfig {
+                    encoding: Some(UiTransactionEncoding::Json),
+                    commitment: Some(commitment),
+                    max_supported_transaction_version: Some(0),
+                },
+            )
+            .await?;
+        Ok(transaction
+            .transaction
+            .meta
+            .as_ref()
+            .map(TransactionReceipt::from_meta))
+    }
+
     pub async fn simulate_instructions(
         &self,
         payer: &Keypair,
@@ -76,7 +395,7 @@ impl ControllerClient {
     ) -> Result<RpcSimulateTransactionResult> {
         let rpc = self.rpc_client();
         let tx = self
-            .build_transaction(payer, instructions, address_lookup_table_accounts)
+            .build_simulation_transaction(payer, instructions, address_lookup_table_accounts)
             .await?;
         let ret = rpc
             .simulate_transaction_with_config(
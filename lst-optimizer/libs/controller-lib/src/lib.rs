@@ -1,9 +1,11 @@
 pub mod calculator;
+pub mod compute_budget;
 pub mod controller;
 pub mod mint;
 pub mod program;
 pub mod rebalance;
 pub mod state;
+pub mod transaction;
 
 // re-export
 pub use solana_sdk::pubkey::Pubkey;
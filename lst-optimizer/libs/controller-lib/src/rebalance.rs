@@ -19,6 +19,22 @@ use crate::{
     mint::typedefs::MintWithTokenProgram, state::PoolQuery,
 };
 
+/// Bounds on the pool reserves the rebalance may start with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StartRebalanceBounds {
+    pub min_starting_src_lst: u64,
+    pub max_starting_dst_lst: u64,
+}
+
+impl Default for StartRebalanceBounds {
+    fn default() -> Self {
+        Self {
+            min_starting_src_lst: 0,
+            max_starting_dst_lst: u64::MAX,
+        }
+    }
+}
+
 #[async_trait::async_trait]
 pub trait RebalancingInstructions {
     async fn create_start_rebalance_instruction(
@@ -30,6 +46,7 @@ pub trait RebalancingInstructions {
         src_calculator_type: CalculatorType,
         dst_calculator_type: CalculatorType,
         lamports: u64,
+        bounds: StartRebalanceBounds,
     ) -> Result<Instruction>;
     async fn create_end_rebalance_instruction_from_start(
         &self,
@@ -54,6 +71,7 @@ impl RebalancingInstructions for ControllerClient {
         src_calculator_type: CalculatorType,
         dst_calculator_type: CalculatorType,
         lamports: u64,
+        bounds: StartRebalanceBounds,
     ) -> Result<Instruction> {
         let rpc = self.rpc_client();
         let pool_state_addr = self.get_pool_state_address(program_id).await;
@@ -88,8 +106,8 @@ impl RebalancingInstructions for ControllerClient {
             },
             StartRebalanceIxLstAmts {
                 amount: lamports,
-                min_starting_src_lst: 0,
-                max_starting_dst_lst: u64::MAX,
+                min_starting_src_lst: bounds.min_starting_src_lst,
+                max_starting_dst_lst: bounds.max_starting_dst_lst,
             },
             SrcDstLstSolValueCalcAccountSuffixes {
                 src_lst_calculator_accounts: &src_accs,
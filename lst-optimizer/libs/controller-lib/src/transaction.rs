@@ -0,0 +1,244 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use solana_sdk::{
+    address_lookup_table::AddressLookupTableAccount,
+    commitment_config::CommitmentConfig,
+    hash::Hash,
+    instruction::Instruction,
+    message::{v0::Message, VersionedMessage},
+    packet::PACKET_DATA_SIZE,
+    pubkey::Pubkey,
+    signature::Signature,
+    transaction::TransactionError,
+};
+use solana_transaction_status::{UiTransactionStatusMeta, UiTransactionTokenBalance};
+
+const SIGNATURE_SIZE: usize = 64;
+
+/// What happened to a sent transaction
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransactionOutcome {
+    /// The transaction landed at the commitment
+    Landed { signature: Signature, slot: u64 },
+    /// The transaction landed but its execution failed
+    Failed {
+        signature: Signature,
+        error: TransactionError,
+    },
+    /// The blockhash of every attempt expired before the transaction landed
+    Expired { signature: Signature },
+}
+
+impl TransactionOutcome {
+    pub fn signature(&self) -> &Signature {
+        match self {
+            TransactionOutcome::Landed { signature, .. } => signature,
+            TransactionOutcome::Failed { signature, .. } => signature,
+            TransactionOutcome::Expired { signature } => signature,
+        }
+    }
+
+    pub fn is_landed(&self) -> bool {
+        matches!(self, TransactionOutcome::Landed { .. })
+    }
+}
+
+/// The token balance of an account a transaction touched, before and after it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenBalanceChange {
+    pub mint: String,
+    pub owner: String,
+    pub pre: u64,
+    pub post: u64,
+}
+
+/// What a landed transaction paid and moved, read from its status meta
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TransactionReceipt {
+    pub fee: u64,
+    pub token_balances: Vec<TokenBalanceChange>,
+}
+
+impl TransactionReceipt {
+    pub fn from_meta(meta: &UiTransactionStatusMeta) -> Self {
+        let pre_balances: Vec<UiTransactionTokenBalance> =
+            Option::from(meta.pre_token_balances.clone()).unwrap_or_default();
+        let post_balances: Vec<UiTransactionTokenBalance> =
+            Option::from(meta.post_token_balances.clone()).unwrap_or_default();
+
+        // An account created or closed by the transaction only has one of the balances
+        let mut token_balances: Vec<(u8, TokenBalanceChange)> = vec![];
+        for (balance, is_post) in pre_balances
+            .iter()
+            .map(|balance| (balance, false))
+            .chain(post_balances.iter().map(|balance| (balance, true)))
+        {
+            let amount = balance.ui_token_amount.amount.parse().unwrap_or(0);
+            let index = match token_balances
+                .iter()
+                .position(|(account_index, _)| *account_index == balance.account_index)
+            {
+                Some(index) => index,
+                None => {
+                    token_balances.push((
+                        balance.account_index,
+                        TokenBalanceChange {
+                            mint: balance.mint.clone(),
+                            owner: Option::from(balance.owner.clone()).unwrap_or_default(),
+                            pre: 0,
+                            post: 0,
+                        },
+                    ));
+                    token_balances.len() - 1
+                }
+            };
+            let change = &mut token_balances[index].1;
+            if is_post {
+                change.post = amount;
+            } else {
+                change.pre = amount;
+            }
+        }
+
+        Self {
+            fee: meta.fee,
+            token_balances: token_balances
+                .into_iter()
+                .map(|(_, change)| change)
+                .collect(),
+        }
+    }
+
+    /// Net change of the tokens of the mint held by the owner
+    pub fn get_token_delta(&self, owner: &Pubkey, mint: &Pubkey) -> i128 {
+        let (owner, mint) = (owner.to_string(), mint.to_string());
+        self.token_balances
+            .iter()
+            .filter(|change| change.owner.eq(&owner) && change.mint.eq(&mint))
+            .map(|change| change.post as i128 - change.pre as i128)
+            .sum()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfirmOptions {
+    /// The commitment the transaction must reach to be landed
+    pub commitment: CommitmentConfig,
+    /// How many times an expired transaction is signed again with a fresh blockhash and resent
+    pub max_resends: u8,
+    /// Interval between the signature status polls
+    pub poll_interval: Duration,
+}
+
+impl Default for ConfirmOptions {
+    fn default() -> Self {
+        Self {
+            commitment: CommitmentConfig::confirmed(),
+            max_resends: 2,
+            poll_interval: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Serialized size of the v0 transaction of the instructions, measured before signing
+pub fn transaction_size(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    address_lookup_table_accounts: &[AddressLookupTableAccount],
+) -> Result<usize> {
+    let message = Message::try_compile(
+        payer,
+        instructions,
+        address_lookup_table_accounts,
+        Hash::default(),
+    )?;
+    let num_signatures = message.header.num_required_signatures as usize;
+    let message_size = bincode::serialized_size(&VersionedMessage::V0(message))? as usize;
+    Ok(short_vec_len(num_signatures) + num_signatures * SIGNATURE_SIZE + message_size)
+}
+
+/// Whether the transaction of the instructions fits in a packet
+pub fn fits_in_packet(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    address_lookup_table_accounts: &[AddressLookupTableAccount],
+) -> Result<bool> {
+    Ok(transaction_size(payer, instructions, address_lookup_table_accounts)? <= PACKET_DATA_SIZE)
+}
+
+fn short_vec_len(len: usize) -> usize {
+    match len {
+        0..=0x7f => 1,
+        0x80..=0x3fff => 2,
+        _ => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{
+        instruction::AccountMeta,
+        signature::{Keypair, Signer},
+        transaction::VersionedTransaction,
+    };
+
+    use super::*;
+
+    fn test_instructions(count: usize) -> Vec<Instruction> {
+        (0..count)
+            .map(|_| {
+                Instruction::new_with_bytes(
+                    Pubkey::new_unique(),
+                    &[1, 2, 3, 4],
+                    vec![
+                        AccountMeta::new(Pubkey::new_unique(), false),
+                        AccountMeta::new_readonly(Pubkey::new_unique(), false),
+                    ],
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_transaction_size_matches_signed_transaction() {
+        let payer = Keypair::new();
+        let instructions = test_instructions(3);
+        let message =
+            Message::try_compile(&payer.pubkey(), &instructions, &[], Hash::default()).unwrap();
+        let tx = VersionedTransaction::try_new(VersionedMessage::V0(message), &[&payer]).unwrap();
+        assert_eq!(
+            transaction_size(&payer.pubkey(), &instructions, &[]).unwrap(),
+            bincode::serialize(&tx).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn test_fits_in_packet() {
+        let payer = Pubkey::new_unique();
+        assert!(fits_in_packet(&payer, &test_instructions(3), &[]).unwrap());
+        // every instruction brings 3 new accounts of 32 bytes
+        assert!(!fits_in_packet(&payer, &test_instructions(15), &[]).unwrap());
+    }
+
+    #[test]
+    fn test_get_token_delta() {
+        let (owner, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let change = |owner: &Pubkey, mint: &Pubkey, pre: u64, post: u64| TokenBalanceChange {
+            mint: mint.to_string(),
+            owner: owner.to_string(),
+            pre,
+            post,
+        };
+        let receipt = TransactionReceipt {
+            fee: 5_000,
+            token_balances: vec![
+                change(&owner, &mint, 100, 40),
+                change(&owner, &Pubkey::new_unique(), 0, 70),
+                change(&Pubkey::new_unique(), &mint, 0, 60),
+            ],
+        };
+        assert_eq!(receipt.get_token_delta(&owner, &mint), -60);
+        assert_eq!(receipt.get_token_delta(&owner, &Pubkey::new_unique()), 0);
+    }
+}
@@ -0,0 +1,137 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::Deserialize;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+};
+
+/// An instruction as the swap API returns it
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiInstruction {
+    pub program_id: String,
+    pub accounts: Vec<ApiAccountMeta>,
+    /// Base64 encoded
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiAccountMeta {
+    pub pubkey: String,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+impl TryFrom<ApiInstruction> for Instruction {
+    type Error = anyhow::Error;
+
+    fn try_from(instruction: ApiInstruction) -> Result<Self> {
+        let accounts = instruction
+            .accounts
+            .into_iter()
+            .map(|meta| {
+                Ok(AccountMeta {
+                    pubkey: Pubkey::from_str(&meta.pubkey)?,
+                    is_signer: meta.is_signer,
+                    is_writable: meta.is_writable,
+                })
+            })
+            .collect::<Result<Vec<AccountMeta>>>()?;
+        Ok(Instruction {
+            program_id: Pubkey::from_str(&instruction.program_id)?,
+            accounts,
+            data: STANDARD.decode(instruction.data)?,
+        })
+    }
+}
+
+/// The response of the `/swap-instructions` endpoint
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiSwapInstructions {
+    #[serde(default)]
+    pub compute_budget_instructions: Vec<ApiInstruction>,
+    #[serde(default)]
+    pub setup_instructions: Vec<ApiInstruction>,
+    pub swap_instruction: ApiInstruction,
+    pub cleanup_instruction: Option<ApiInstruction>,
+    #[serde(default)]
+    pub other_instructions: Vec<ApiInstruction>,
+    #[serde(default)]
+    pub address_lookup_table_addresses: Vec<String>,
+    pub simulation_error: Option<serde_json::Value>,
+}
+
+pub fn to_instructions(instructions: Vec<ApiInstruction>) -> Result<Vec<Instruction>> {
+    instructions
+        .into_iter()
+        .map(Instruction::try_from)
+        .collect()
+}
+
+pub fn to_pubkeys(addresses: &[String]) -> Result<Vec<Pubkey>> {
+    Ok(addresses
+        .iter()
+        .map(|address| Pubkey::from_str(address))
+        .collect::<Result<Vec<Pubkey>, _>>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SWAP_INSTRUCTIONS: &str = r#"{
+        "computeBudgetInstructions": [{
+            "programId": "ComputeBudget111111111111111111111111111111",
+            "accounts": [],
+            "data": "AsBcFQA="
+        }],
+        "setupInstructions": [],
+        "swapInstruction": {
+            "programId": "JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4",
+            "accounts": [{
+                "pubkey": "So11111111111111111111111111111111111111112",
+                "isSigner": false,
+                "isWritable": true
+            }],
+            "data": "AQID"
+        },
+        "cleanupInstruction": null,
+        "otherInstructions": [],
+        "addressLookupTableAddresses": ["GxS6FiQ3mNnAar9HGQ6mxP7t6FcwmHkU7peSeQDUHmpN"],
+        "prioritizationFeeLamports": 0
+    }"#;
+
+    #[test]
+    fn test_parse_swap_instructions() {
+        let response: ApiSwapInstructions = serde_json::from_str(SWAP_INSTRUCTIONS).unwrap();
+        assert!(response.simulation_error.is_none());
+        assert!(response.cleanup_instruction.is_none());
+
+        let swap_instruction = Instruction::try_from(response.swap_instruction).unwrap();
+        assert_eq!(swap_instruction.data, vec![1, 2, 3]);
+        assert_eq!(
+            swap_instruction.accounts,
+            vec![AccountMeta::new(
+                Pubkey::from_str("So11111111111111111111111111111111111111112").unwrap(),
+                false
+            )]
+        );
+        assert_eq!(
+            to_instructions(response.compute_budget_instructions)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            to_pubkeys(&response.address_lookup_table_addresses)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
@@ -1 +1,3 @@
+pub mod api;
+pub mod options;
 pub mod quoter;
@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use quoter_lib::typedefs::RouteConstraints;
+
+pub const JUPITER_SWAP_API_URL: &str = "https://quote-api.jup.ag/v6";
+pub const DEFAULT_SLIPPAGE_BPS: u16 = 3000;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where and how the Jupiter swap API is queried
+#[derive(Debug, Clone, PartialEq)]
+pub struct JupiterQuoterOptions {
+    /// A self hosted instance or a local stand-in works as well
+    pub base_url: String,
+    /// Sent as the `x-api-key` header
+    pub api_key: Option<String>,
+    pub timeout: Duration,
+    /// Cap on the accounts of every route, the route constraints may lower it
+    pub max_accounts: Option<u8>,
+    /// Only route through these dexes, any when empty
+    pub dexes: Vec<String>,
+    /// Never route through these dexes
+    pub excluded_dexes: Vec<String>,
+    /// Slippage of the quotes and of the swaps not given one
+    pub default_slippage_bps: u16,
+    /// Reject the swaps whose worst fill is below the min amount out
+    pub enforce_min_amount_out: bool,
+}
+
+impl Default for JupiterQuoterOptions {
+    fn default() -> Self {
+        Self {
+            base_url: JUPITER_SWAP_API_URL.to_string(),
+            api_key: None,
+            timeout: DEFAULT_TIMEOUT,
+            max_accounts: None,
+            dexes: vec![],
+            excluded_dexes: vec![],
+            default_slippage_bps: DEFAULT_SLIPPAGE_BPS,
+            enforce_min_amount_out: true,
+        }
+    }
+}
+
+impl JupiterQuoterOptions {
+    /// The tighter of the configured and the constrained max accounts
+    pub fn max_accounts(&self, route_constraints: &RouteConstraints) -> Option<u8> {
+        match (self.max_accounts, route_constraints.max_accounts) {
+            (Some(max_accounts), Some(constrained)) => Some(max_accounts.min(constrained)),
+            (max_accounts, constrained) => max_accounts.or(constrained),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_accounts() {
+        let constrained = RouteConstraints {
+            max_accounts: Some(32),
+            only_direct_routes: false,
+        };
+        let mut options = JupiterQuoterOptions::default();
+        assert_eq!(options.max_accounts(&RouteConstraints::default()), None);
+        assert_eq!(options.max_accounts(&constrained), Some(32));
+
+        options.max_accounts = Some(40);
+        assert_eq!(options.max_accounts(&RouteConstraints::default()), Some(40));
+        assert_eq!(options.max_accounts(&constrained), Some(32));
+    }
+}
@@ -1,26 +1,159 @@
 use anyhow::Result;
-pub use jupiter_swap_api_client::swap::SwapInstructionsResponse;
 use jupiter_swap_api_client::{
-    quote::{QuoteRequest, SwapMode as JupSwapMode},
+    quote::{QuoteResponse, SwapMode as JupSwapMode},
     swap::SwapRequest,
     transaction_config::TransactionConfig,
-    JupiterSwapApiClient,
 };
-use quoter_lib::typedefs::{QuoterClient, SwapInstructions};
+use quoter_lib::typedefs::{Quote, QuoterClient, RouteConstraints, SwapInstructions, SwapMode};
+use reqwest::{Client, RequestBuilder, Response};
+use rust_decimal::prelude::ToPrimitive;
+use serde::de::DeserializeOwned;
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::pubkey::Pubkey;
+use thiserror::Error;
 
-const JUPITER_SWAP_API_URL: &str = "https://quote-api.jup.ag/v6";
+use crate::{
+    api::{to_instructions, to_pubkeys, ApiSwapInstructions},
+    options::JupiterQuoterOptions,
+};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum JupiterQuoterError {
+    #[error("Jupiter API responded {0}: {1}")]
+    RequestFailed(u16, String),
+
+    #[error("Jupiter failed to simulate the swap: {0}")]
+    SimulationFailed(String),
+
+    #[error("Jupiter min amount out {0} is below the required {1}")]
+    BelowMinAmountOut(u64, u64),
+}
 
 pub struct JupiterQuoterClient {
     rpc: RpcClient,
-    client: JupiterSwapApiClient,
+    http: Client,
+    options: JupiterQuoterOptions,
 }
 
 impl JupiterQuoterClient {
     pub fn new(rpc_url: &str) -> Self {
         JupiterQuoterClient::from_parts(RpcClient::new(rpc_url.to_string()))
     }
+
+    pub fn with_options(mut self, options: JupiterQuoterOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// The query of the `/quote` endpoint
+    pub fn quote_params(
+        &self,
+        src_mint: &Pubkey,
+        dst_mint: &Pubkey,
+        amount: u64,
+        mode: SwapMode,
+        slippage_bps: Option<u16>,
+        route_constraints: &RouteConstraints,
+    ) -> Vec<(&'static str, String)> {
+        let mut params = vec![
+            ("inputMint", src_mint.to_string()),
+            ("outputMint", dst_mint.to_string()),
+            ("amount", amount.to_string()),
+            (
+                "slippageBps",
+                slippage_bps
+                    .unwrap_or(self.options.default_slippage_bps)
+                    .to_string(),
+            ),
+            (
+                "swapMode",
+                match mode {
+                    SwapMode::ExactIn => "ExactIn",
+                    SwapMode::ExactOut => "ExactOut",
+                }
+                .to_string(),
+            ),
+        ];
+        if route_constraints.only_direct_routes {
+            params.push(("onlyDirectRoutes", true.to_string()));
+        }
+        if let Some(max_accounts) = self.options.max_accounts(route_constraints) {
+            params.push(("maxAccounts", max_accounts.to_string()));
+        }
+        if !self.options.dexes.is_empty() {
+            params.push(("dexes", self.options.dexes.join(",")));
+        }
+        if !self.options.excluded_dexes.is_empty() {
+            params.push(("excludeDexes", self.options.excluded_dexes.join(",")));
+        }
+        params
+    }
+
+    fn request(&self, request: RequestBuilder) -> RequestBuilder {
+        let request = request.timeout(self.options.timeout);
+        match &self.options.api_key {
+            Some(api_key) => request.header("x-api-key", api_key),
+            None => request,
+        }
+    }
+
+    async fn get_quote(&self, params: &[(&'static str, String)]) -> Result<QuoteResponse> {
+        let url = format!("{}/quote", self.options.base_url);
+        let response = self
+            .request(self.http.get(url).query(params))
+            .send()
+            .await?;
+        deserialize_response(response).await
+    }
+
+    async fn get_swap_instructions(&self, request: &SwapRequest) -> Result<ApiSwapInstructions> {
+        let url = format!("{}/swap-instructions", self.options.base_url);
+        let response = self
+            .request(self.http.post(url).json(request))
+            .send()
+            .await?;
+        deserialize_response(response).await
+    }
+}
+
+async fn deserialize_response<T: DeserializeOwned>(response: Response) -> Result<T> {
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(JupiterQuoterError::RequestFailed(status.as_u16(), body).into());
+    }
+    Ok(response.json::<T>().await?)
+}
+
+fn to_quote(quote_res: &QuoteResponse) -> Quote {
+    let (mode, min_out_amount, max_in_amount) = match quote_res.swap_mode {
+        JupSwapMode::ExactIn => (
+            SwapMode::ExactIn,
+            quote_res.other_amount_threshold,
+            quote_res.in_amount,
+        ),
+        JupSwapMode::ExactOut => (
+            SwapMode::ExactOut,
+            quote_res.out_amount,
+            quote_res.other_amount_threshold,
+        ),
+    };
+    Quote {
+        src_mint: quote_res.input_mint,
+        dst_mint: quote_res.output_mint,
+        mode,
+        in_amount: quote_res.in_amount,
+        out_amount: quote_res.out_amount,
+        min_out_amount,
+        max_in_amount,
+        slippage_bps: quote_res.slippage_bps,
+        price_impact_pct: quote_res.price_impact_pct.to_f64().unwrap_or_default(),
+        route_labels: quote_res
+            .route_plan
+            .iter()
+            .map(|step| step.swap_info.label.clone())
+            .collect(),
+    }
 }
 
 #[async_trait::async_trait]
@@ -28,7 +161,8 @@ impl QuoterClient for JupiterQuoterClient {
     fn from_parts(rpc: RpcClient) -> Self {
         Self {
             rpc,
-            client: JupiterSwapApiClient::new(JUPITER_SWAP_API_URL.to_string()),
+            http: Client::new(),
+            options: JupiterQuoterOptions::default(),
         }
     }
 
@@ -36,6 +170,25 @@ impl QuoterClient for JupiterQuoterClient {
         &self.rpc
     }
 
+    async fn quote(
+        &self,
+        src_mint: &Pubkey,
+        dst_mint: &Pubkey,
+        amount: u64,
+        mode: SwapMode,
+    ) -> Result<Quote> {
+        let params = self.quote_params(
+            src_mint,
+            dst_mint,
+            amount,
+            mode,
+            None,
+            &RouteConstraints::default(),
+        );
+        let quote_res = self.get_quote(&params).await?;
+        Ok(to_quote(&quote_res))
+    }
+
     async fn create_swap_instructions(
         &self,
         swapper: &Pubkey,
@@ -43,54 +196,114 @@ impl QuoterClient for JupiterQuoterClient {
         src_mint: &Pubkey,
         dst_mint: &Pubkey,
         amount: u64,
-        _min_amount_out: u64,
+        min_amount_out: u64,
         slippage_bps: Option<u16>,
+        route_constraints: RouteConstraints,
     ) -> Result<SwapInstructions> {
-        let jup_client = &self.client;
-        let quote_request = QuoteRequest {
-            input_mint: src_mint.clone(),
-            output_mint: dst_mint.clone(),
+        let params = self.quote_params(
+            src_mint,
+            dst_mint,
             amount,
-            slippage_bps: slippage_bps.unwrap_or(3000),
-            // only_direct_routes: Some(true),
-            // max_accounts: Some(32),
-            swap_mode: Some(JupSwapMode::ExactIn),
-            ..QuoteRequest::default()
-        };
-
-        let quote_res = jup_client.quote(&quote_request).await?;
-        let jup_instructions = jup_client
-            .swap_instructions(
-                &(SwapRequest {
-                    user_public_key: swapper.clone(),
-                    quote_response: quote_res,
-                    config: TransactionConfig {
-                        destination_token_account: Some(receiver_token_account.clone()),
-                        ..TransactionConfig::default()
-                    },
-                }),
+            SwapMode::ExactIn,
+            slippage_bps,
+            &route_constraints,
+        );
+        let quote_res = self.get_quote(&params).await?;
+        let quote = to_quote(&quote_res);
+        if self.options.enforce_min_amount_out && quote.min_out_amount < min_amount_out {
+            return Err(JupiterQuoterError::BelowMinAmountOut(
+                quote.min_out_amount,
+                min_amount_out,
             )
+            .into());
+        }
+
+        let jup_instructions = self
+            .get_swap_instructions(&SwapRequest {
+                user_public_key: *swapper,
+                quote_response: quote_res,
+                config: TransactionConfig {
+                    destination_token_account: Some(*receiver_token_account),
+                    // The src wSOL comes from the pool, wrapping would spend the swapper's SOL
+                    wrap_and_unwrap_sol: false,
+                    ..TransactionConfig::default()
+                },
+            })
             .await?;
 
-        if jup_instructions.simulation_error.is_some() {
-            return Err(anyhow::anyhow!(
-                "error while create jupiter swap instructions: {:?}",
-                jup_instructions.simulation_error
-            ));
+        if let Some(simulation_error) = jup_instructions.simulation_error {
+            return Err(JupiterQuoterError::SimulationFailed(simulation_error.to_string()).into());
         }
 
-        let mut ret = SwapInstructions {
-            setup_instructions: jup_instructions.setup_instructions,
-            swap_instructions: vec![jup_instructions.swap_instruction],
-            cleanup_instructions: vec![],
-            address_lookup_tables: jup_instructions.address_lookup_table_addresses,
-        };
+        Ok(SwapInstructions {
+            compute_budget_instructions: to_instructions(
+                jup_instructions.compute_budget_instructions,
+            )?,
+            setup_instructions: to_instructions(jup_instructions.setup_instructions)?,
+            swap_instructions: to_instructions(vec![jup_instructions.swap_instruction])?,
+            cleanup_instructions: to_instructions(
+                jup_instructions.cleanup_instruction.into_iter().collect(),
+            )?,
+            other_instructions: to_instructions(jup_instructions.other_instructions)?,
+            address_lookup_tables: to_pubkeys(&jup_instructions.address_lookup_table_addresses)?,
+            quote: Some(quote),
+        })
+    }
+}
 
-        if jup_instructions.cleanup_instruction.is_some() {
-            ret.cleanup_instructions
-                .push(jup_instructions.cleanup_instruction.unwrap());
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_params() {
+        let (src_mint, dst_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let client = JupiterQuoterClient::new("http://localhost:8899");
+        assert_eq!(
+            client.quote_params(
+                &src_mint,
+                &dst_mint,
+                1_000,
+                SwapMode::ExactIn,
+                None,
+                &RouteConstraints::default()
+            ),
+            vec![
+                ("inputMint", src_mint.to_string()),
+                ("outputMint", dst_mint.to_string()),
+                ("amount", "1000".to_string()),
+                ("slippageBps", "3000".to_string()),
+                ("swapMode", "ExactIn".to_string()),
+            ]
+        );
 
-        Ok(ret)
+        let client = client.with_options(JupiterQuoterOptions {
+            max_accounts: Some(40),
+            dexes: vec!["Raydium".to_string(), "Orca V2".to_string()],
+            excluded_dexes: vec!["Obric V2".to_string()],
+            ..Default::default()
+        });
+        let params = client.quote_params(
+            &src_mint,
+            &dst_mint,
+            1_000,
+            SwapMode::ExactOut,
+            Some(50),
+            &RouteConstraints {
+                max_accounts: Some(32),
+                only_direct_routes: true,
+            },
+        );
+        assert_eq!(
+            params[3..],
+            vec![
+                ("slippageBps", "50".to_string()),
+                ("swapMode", "ExactOut".to_string()),
+                ("onlyDirectRoutes", "true".to_string()),
+                ("maxAccounts", "32".to_string()),
+                ("dexes", "Raydium,Orca V2".to_string()),
+                ("excludeDexes", "Obric V2".to_string()),
+            ]
+        );
     }
 }
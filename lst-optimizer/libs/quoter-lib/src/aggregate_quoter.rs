@@ -0,0 +1,488 @@
+use std::{collections::HashSet, time::Duration};
+
+use anyhow::Result;
+use futures::future::join_all;
+use log::{info, warn};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use thiserror::Error;
+
+use crate::typedefs::{Quote, QuoterClient, RouteConstraints, SwapInstructions, SwapMode};
+
+pub const DEFAULT_QUOTE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error, PartialEq)]
+pub enum AggregateQuoterError {
+    #[error("No quoter to aggregate")]
+    NoQuoters,
+
+    #[error("Quoter {0} timed out")]
+    TimedOut(String),
+
+    #[error("The swap uses {0} accounts, more than the max {1}")]
+    TooManyAccounts(usize, u8),
+
+    #[error("Every quoter failed: {0}")]
+    AllQuotersFailed(String),
+}
+
+/// A quoter among the aggregated ones, named after its venue
+pub struct NamedQuoter {
+    pub name: String,
+    pub quoter: Box<dyn QuoterClient>,
+}
+
+/// Query the quoters concurrently and keep the best execution, the quoters failing or
+/// timing out are skipped
+pub struct AggregateQuoterClient {
+    rpc: RpcClient,
+    quoters: Vec<NamedQuoter>,
+    timeout: Duration,
+}
+
+impl AggregateQuoterClient {
+    pub fn new(rpc_url: &str) -> Self {
+        AggregateQuoterClient::from_parts(RpcClient::new(rpc_url.to_string()))
+    }
+
+    pub fn with_quoter(mut self, name: &str, quoter: Box<dyn QuoterClient>) -> Self {
+        self.quoters.push(NamedQuoter {
+            name: name.to_string(),
+            quoter,
+        });
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Send the request to every quoter concurrently, each bounded by the timeout
+    async fn query_all<'a, T, F, Fut>(&'a self, request: F) -> Vec<(&'a str, Result<T>)>
+    where
+        F: Fn(&'a dyn QuoterClient) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let requests = self.quoters.iter().map(|named| {
+            let response = request(named.quoter.as_ref());
+            async move {
+                let result = match tokio::time::timeout(self.timeout, response).await {
+                    Ok(result) => result,
+                    Err(_) => Err(AggregateQuoterError::TimedOut(named.name.clone()).into()),
+                };
+                (named.name.as_str(), result)
+            }
+        });
+        join_all(requests).await
+    }
+}
+
+/// Keep the result of the highest score, the failed ones are logged and skipped
+pub fn select_best<T>(
+    results: Vec<(&str, Result<T>)>,
+    score: impl Fn(&T) -> u64,
+) -> Result<(String, T)> {
+    if results.is_empty() {
+        return Err(AggregateQuoterError::NoQuoters.into());
+    }
+    let mut errors: Vec<String> = vec![];
+    let mut best: Option<(String, T)> = None;
+    for (name, result) in results {
+        match result {
+            Ok(value) => {
+                if best
+                    .as_ref()
+                    .is_none_or(|(_, best)| score(&value) > score(best))
+                {
+                    best = Some((name.to_string(), value));
+                }
+            }
+            Err(e) => {
+                warn!("Quoter {} failed: {}", name, e);
+                errors.push(format!("{}: {}", name, e));
+            }
+        }
+    }
+    best.ok_or(AggregateQuoterError::AllQuotersFailed(errors.join(", ")).into())
+}
+
+/// How good a quote is, the most out for an exact in and the least in for an exact out
+fn quote_score(quote: &Quote) -> u64 {
+    match quote.mode {
+        SwapMode::ExactIn => quote.out_amount,
+        SwapMode::ExactOut => u64::MAX - quote.in_amount,
+    }
+}
+
+/// How good built swap instructions are, the least out they may fill net of the fees
+/// the venue takes, as the min amount out without a quote
+fn swap_score(swap_instructions: &SwapInstructions, min_amount_out: u64) -> u64 {
+    swap_instructions
+        .quote
+        .as_ref()
+        .map(|quote| quote.min_out_amount)
+        .unwrap_or(min_amount_out)
+}
+
+/// The distinct accounts of every instruction the swap adds to the transaction, their
+/// programs included
+fn count_swap_accounts(swap_instructions: &SwapInstructions) -> usize {
+    let SwapInstructions {
+        compute_budget_instructions,
+        setup_instructions,
+        swap_instructions,
+        cleanup_instructions,
+        other_instructions,
+        ..
+    } = swap_instructions;
+    let mut accounts: HashSet<Pubkey> = HashSet::new();
+    for instruction in compute_budget_instructions
+        .iter()
+        .chain(setup_instructions)
+        .chain(swap_instructions)
+        .chain(cleanup_instructions)
+        .chain(other_instructions)
+    {
+        accounts.insert(instruction.program_id);
+        accounts.extend(instruction.accounts.iter().map(|meta| meta.pubkey));
+    }
+    accounts.len()
+}
+
+#[async_trait::async_trait]
+impl QuoterClient for AggregateQuoterClient {
+    fn from_parts(rpc: RpcClient) -> Self {
+        Self {
+            rpc,
+            quoters: vec![],
+            timeout: DEFAULT_QUOTE_TIMEOUT,
+        }
+    }
+
+    fn get_rpc_client(&self) -> &RpcClient {
+        &self.rpc
+    }
+
+    async fn quote(
+        &self,
+        src_mint: &Pubkey,
+        dst_mint: &Pubkey,
+        amount: u64,
+        mode: SwapMode,
+    ) -> Result<Quote> {
+        let results = self
+            .query_all(|quoter| quoter.quote(src_mint, dst_mint, amount, mode))
+            .await;
+        let (name, quote) = select_best(results, quote_score)?;
+        info!(
+            "{} quoted the best {} in for {} out",
+            name, quote.in_amount, quote.out_amount
+        );
+        Ok(quote)
+    }
+
+    /// Build the instructions of every quoter and keep the most min out among the swaps
+    /// within the max accounts, a swap without a quote only counts as the min amount out.
+    /// The quotes net the fees of their venue off their min out, such as the stakedex
+    /// router fee, no platform fee is requested from Jupiter.
+    async fn create_swap_instructions(
+        &self,
+        swapper: &Pubkey,
+        receiver_token_account: &Pubkey,
+        src_mint: &Pubkey,
+        dst_mint: &Pubkey,
+        amount: u64,
+        min_amount_out: u64,
+        slippage_bps: Option<u16>,
+        route_constraints: RouteConstraints,
+    ) -> Result<SwapInstructions> {
+        let results = self
+            .query_all(|quoter| async move {
+                let swap_instructions = quoter
+                    .create_swap_instructions(
+                        swapper,
+                        receiver_token_account,
+                        src_mint,
+                        dst_mint,
+                        amount,
+                        min_amount_out,
+                        slippage_bps,
+                        route_constraints,
+                    )
+                    .await?;
+                let accounts = count_swap_accounts(&swap_instructions);
+                if let Some(max_accounts) = route_constraints
+                    .max_accounts
+                    .filter(|max_accounts| accounts > *max_accounts as usize)
+                {
+                    return Err(
+                        AggregateQuoterError::TooManyAccounts(accounts, max_accounts).into(),
+                    );
+                }
+                Ok(swap_instructions)
+            })
+            .await;
+        let (name, swap_instructions) = select_best(results, |swap_instructions| {
+            swap_score(swap_instructions, min_amount_out)
+        })?;
+        info!(
+            "{} won the swap with {} min out",
+            name,
+            swap_score(&swap_instructions, min_amount_out)
+        );
+        Ok(swap_instructions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::instruction::{AccountMeta, Instruction};
+
+    use super::*;
+
+    /// Quotes a fixed out amount after the delay, or fails without one
+    struct FixedQuoterClient {
+        rpc: RpcClient,
+        out_amount: Option<u64>,
+        min_out_amount: Option<u64>,
+        delay: Duration,
+        accounts: usize,
+        setup_accounts: usize,
+    }
+
+    impl FixedQuoterClient {
+        fn new(out_amount: Option<u64>) -> Self {
+            Self {
+                rpc: RpcClient::new("http://localhost:8899".to_string()),
+                out_amount,
+                min_out_amount: None,
+                delay: Duration::ZERO,
+                accounts: 1,
+                setup_accounts: 0,
+            }
+        }
+
+        fn with_min_out_amount(mut self, min_out_amount: u64) -> Self {
+            self.min_out_amount = Some(min_out_amount);
+            self
+        }
+
+        fn with_delay(mut self, delay: Duration) -> Self {
+            self.delay = delay;
+            self
+        }
+
+        fn with_accounts(mut self, accounts: usize) -> Self {
+            self.accounts = accounts;
+            self
+        }
+
+        fn with_setup_accounts(mut self, setup_accounts: usize) -> Self {
+            self.setup_accounts = setup_accounts;
+            self
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl QuoterClient for FixedQuoterClient {
+        fn from_parts(rpc: RpcClient) -> Self {
+            Self {
+                rpc,
+                out_amount: None,
+                min_out_amount: None,
+                delay: Duration::ZERO,
+                accounts: 1,
+                setup_accounts: 0,
+            }
+        }
+
+        fn get_rpc_client(&self) -> &RpcClient {
+            &self.rpc
+        }
+
+        async fn quote(
+            &self,
+            src_mint: &Pubkey,
+            dst_mint: &Pubkey,
+            amount: u64,
+            mode: SwapMode,
+        ) -> Result<Quote> {
+            tokio::time::sleep(self.delay).await;
+            let out_amount = self.out_amount.ok_or(anyhow::anyhow!("no route"))?;
+            Ok(Quote {
+                src_mint: *src_mint,
+                dst_mint: *dst_mint,
+                mode,
+                in_amount: amount,
+                out_amount,
+                min_out_amount: self.min_out_amount.unwrap_or(out_amount),
+                max_in_amount: amount,
+                slippage_bps: 0,
+                price_impact_pct: 0.0,
+                route_labels: vec![],
+            })
+        }
+
+        async fn create_swap_instructions(
+            &self,
+            _swapper: &Pubkey,
+            _receiver_token_account: &Pubkey,
+            src_mint: &Pubkey,
+            dst_mint: &Pubkey,
+            amount: u64,
+            _min_amount_out: u64,
+            _slippage_bps: Option<u16>,
+            _route_constraints: RouteConstraints,
+        ) -> Result<SwapInstructions> {
+            let quote = self
+                .quote(src_mint, dst_mint, amount, SwapMode::ExactIn)
+                .await?;
+            let accounts = (1..self.accounts)
+                .map(|_| AccountMeta::new(Pubkey::new_unique(), false))
+                .collect();
+            let setup_instructions = (0..self.setup_accounts)
+                .map(|_| Instruction::new_with_bytes(Pubkey::new_unique(), &[], vec![]))
+                .collect();
+            Ok(SwapInstructions {
+                compute_budget_instructions: vec![],
+                setup_instructions,
+                swap_instructions: vec![Instruction::new_with_bytes(
+                    Pubkey::new_unique(),
+                    &[],
+                    accounts,
+                )],
+                cleanup_instructions: vec![],
+                other_instructions: vec![],
+                address_lookup_tables: vec![],
+                quote: Some(quote),
+            })
+        }
+    }
+
+    fn aggregate(quoters: Vec<FixedQuoterClient>) -> AggregateQuoterClient {
+        quoters.into_iter().enumerate().fold(
+            AggregateQuoterClient::new("http://localhost:8899")
+                .with_timeout(Duration::from_millis(100)),
+            |aggregate, (i, quoter)| aggregate.with_quoter(&format!("q{}", i), Box::new(quoter)),
+        )
+    }
+
+    async fn quote(aggregate: &AggregateQuoterClient) -> Result<Quote> {
+        aggregate
+            .quote(
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                1_000,
+                SwapMode::ExactIn,
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_quote_picks_most_out() {
+        let aggregate = aggregate(vec![
+            FixedQuoterClient::new(Some(900)),
+            FixedQuoterClient::new(Some(950)),
+            FixedQuoterClient::new(None),
+        ]);
+        assert_eq!(quote(&aggregate).await.unwrap().out_amount, 950);
+    }
+
+    #[tokio::test]
+    async fn test_quote_skips_timed_out() {
+        let aggregate = aggregate(vec![
+            FixedQuoterClient::new(Some(900)),
+            FixedQuoterClient::new(Some(950)).with_delay(Duration::from_secs(1)),
+        ]);
+        assert_eq!(quote(&aggregate).await.unwrap().out_amount, 900);
+    }
+
+    #[tokio::test]
+    async fn test_quote_fail_on_all_failed() {
+        let aggregate = aggregate(vec![FixedQuoterClient::new(None)]);
+        assert_eq!(
+            quote(&aggregate).await.err().unwrap().to_string(),
+            AggregateQuoterError::AllQuotersFailed("q0: no route".to_string()).to_string()
+        );
+        let empty = AggregateQuoterClient::new("http://localhost:8899");
+        assert_eq!(
+            quote(&empty).await.err().unwrap().to_string(),
+            AggregateQuoterError::NoQuoters.to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_swap_instructions_within_max_accounts() {
+        let aggregate = aggregate(vec![
+            FixedQuoterClient::new(Some(900)).with_accounts(10),
+            FixedQuoterClient::new(Some(950)).with_accounts(40),
+        ]);
+        let swap_instructions = aggregate
+            .create_swap_instructions(
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                1_000,
+                0,
+                None,
+                RouteConstraints {
+                    max_accounts: Some(32),
+                    only_direct_routes: false,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(swap_instructions.quote.unwrap().out_amount, 900);
+    }
+
+    #[tokio::test]
+    async fn test_create_swap_instructions_counts_setup_accounts() {
+        let aggregate = aggregate(vec![
+            FixedQuoterClient::new(Some(900)).with_accounts(10),
+            FixedQuoterClient::new(Some(950))
+                .with_accounts(20)
+                .with_setup_accounts(20),
+        ]);
+        let swap_instructions = aggregate
+            .create_swap_instructions(
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                1_000,
+                0,
+                None,
+                RouteConstraints {
+                    max_accounts: Some(32),
+                    only_direct_routes: false,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(swap_instructions.quote.unwrap().out_amount, 900);
+    }
+
+    #[tokio::test]
+    async fn test_create_swap_instructions_picks_most_min_out() {
+        let aggregate = aggregate(vec![
+            FixedQuoterClient::new(Some(900)),
+            FixedQuoterClient::new(Some(950)).with_min_out_amount(850),
+        ]);
+        let swap_instructions = aggregate
+            .create_swap_instructions(
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                1_000,
+                0,
+                None,
+                RouteConstraints::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(swap_instructions.quote.unwrap().min_out_amount, 900);
+    }
+}
@@ -1,2 +1,3 @@
+pub mod aggregate_quoter;
 pub mod mock_quoter;
 pub mod typedefs;
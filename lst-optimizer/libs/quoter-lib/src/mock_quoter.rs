@@ -1,6 +1,6 @@
 use solana_sdk::pubkey::Pubkey;
 
-use crate::typedefs::{QuoterClient, SwapInstructions};
+use crate::typedefs::{Quote, QuoterClient, RouteConstraints, SwapInstructions, SwapMode};
 
 pub struct MockQuoterClient {}
 
@@ -20,6 +20,16 @@ impl QuoterClient for MockQuoterClient {
         unimplemented!()
     }
 
+    async fn quote(
+        &self,
+        _src_mint: &Pubkey,
+        _dst_mint: &Pubkey,
+        _amount: u64,
+        _mode: SwapMode,
+    ) -> anyhow::Result<Quote> {
+        unimplemented!()
+    }
+
     async fn create_swap_instructions(
         &self,
         _swapper: &Pubkey,
@@ -29,6 +39,7 @@ impl QuoterClient for MockQuoterClient {
         _amount: u64,
         _min_amount_out: u64,
         _slippage_bps: Option<u16>,
+        _route_constraints: RouteConstraints,
     ) -> anyhow::Result<SwapInstructions> {
         unimplemented!()
     }
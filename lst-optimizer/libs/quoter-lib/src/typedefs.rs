@@ -6,11 +6,50 @@ use solana_sdk::{
     pubkey::Pubkey,
 };
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SwapMode {
+    /// The src amount is exact, the dst amount is quoted
+    #[default]
+    ExactIn,
+    /// The dst amount is exact, the src amount is quoted
+    ExactOut,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quote {
+    pub src_mint: Pubkey,
+    pub dst_mint: Pubkey,
+    pub mode: SwapMode,
+    pub in_amount: u64,
+    pub out_amount: u64,
+    /// The least dst amount received after slippage
+    pub min_out_amount: u64,
+    /// The most src amount spent after slippage
+    pub max_in_amount: u64,
+    pub slippage_bps: u16,
+    pub price_impact_pct: f64,
+    /// The labels of the venues the swap is routed through
+    pub route_labels: Vec<String>,
+}
+
+/// Limits on the route of a swap, keeping its instructions small enough for a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RouteConstraints {
+    pub max_accounts: Option<u8>,
+    pub only_direct_routes: bool,
+}
+
 pub struct SwapInstructions {
+    /// The compute budget the quoter suggests, replaced by the sender's own if any
+    pub compute_budget_instructions: Vec<Instruction>,
     pub setup_instructions: Vec<Instruction>,
     pub swap_instructions: Vec<Instruction>,
     pub cleanup_instructions: Vec<Instruction>,
+    /// Instructions unrelated to the swap accounts, such as tips
+    pub other_instructions: Vec<Instruction>,
     pub address_lookup_tables: Vec<Pubkey>,
+    /// The quote the instructions are built from, if any
+    pub quote: Option<Quote>,
 }
 
 #[async_trait::async_trait]
@@ -21,6 +60,16 @@ pub trait QuoterClient: Sync + Send {
 
     fn get_rpc_client(&self) -> &RpcClient;
 
+    /// Quote the swap of `amount` src tokens (exact in) or for `amount` dst tokens (exact out),
+    /// without building any instruction
+    async fn quote(
+        &self,
+        src_mint: &Pubkey,
+        dst_mint: &Pubkey,
+        amount: u64,
+        mode: SwapMode,
+    ) -> Result<Quote>;
+
     async fn create_swap_instructions(
         &self,
         swapper: &Pubkey,
@@ -30,6 +79,7 @@ pub trait QuoterClient: Sync + Send {
         amount: u64,
         min_amount_out: u64,
         slippage_bps: Option<u16>,
+        route_constraints: RouteConstraints,
     ) -> Result<SwapInstructions>;
 
     async fn resolve_address_lookup_table_accounts(
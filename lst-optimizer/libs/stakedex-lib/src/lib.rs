@@ -0,0 +1,7 @@
+pub mod quoter;
+pub mod stake_pool;
+pub mod unstake_it;
+pub mod validator_list;
+
+#[cfg(test)]
+pub(crate) mod test_utils;
@@ -0,0 +1,892 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use quoter_lib::typedefs::{Quote, QuoterClient, RouteConstraints, SwapInstructions, SwapMode};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    stake, system_program, sysvar,
+};
+use spl_associated_token_account::get_associated_token_address;
+use spl_helper::{mint::MintAccountQuery, token_account::TokenAccountQuery};
+use spl_token::native_mint;
+use stakedex_interface::{
+    StakeWrappedSolIxArgs, StakeWrappedSolIxData, StakeWrappedSolKeys, SwapViaStakeArgs,
+    SwapViaStakeIxArgs, SwapViaStakeIxData, SwapViaStakeKeys, STAKE_WRAPPED_SOL_IX_ACCOUNTS_LEN,
+    SWAP_VIA_STAKE_IX_ACCOUNTS_LEN,
+};
+use thiserror::Error;
+
+use crate::{
+    stake_pool::StakePoolState,
+    unstake_it::{
+        deserialize_account, ProtocolFee, UnstakeFee, UnstakeItState, UnstakePool, UNSTAKE_IT_POOL,
+    },
+    validator_list::{ValidatorList, ValidatorStakeInfo},
+};
+
+pub const STAKE_WRAPPED_SOL_LABEL: &str = "Stakedex StakeWrappedSol";
+pub const SWAP_VIA_STAKE_LABEL: &str = "Stakedex SwapViaStake";
+pub const INSTANT_UNSTAKE_LABEL: &str = "Stakedex InstantUnstake";
+
+/// The bridge stake seeds tried for a free bridge stake account
+const MAX_BRIDGE_STAKE_SEEDS: u32 = 16;
+
+/// The router fee assumed of a route, stakedex takes it off the out amount into the
+/// dest token fee token account
+pub const DEFAULT_ROUTER_FEE_BPS: u16 = 10;
+
+const MAX_FEE_BPS: u64 = 10_000;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum StakedexQuoterError {
+    #[error("Swap mode {0:?} is not supported by stakedex")]
+    UnsupportedSwapMode(SwapMode),
+
+    #[error("No stakedex route from {0} to {1}")]
+    NoRoute(Pubkey, Pubkey),
+
+    #[error("Stake pool {0} is not updated this epoch")]
+    StakePoolNotUpdated(Pubkey),
+
+    #[error("Stake pool {0} does not take permissionless deposits")]
+    DepositNotAllowed(Pubkey),
+
+    #[error("No validator of both stake pools has {0} lamports to withdraw")]
+    NoSharedValidator(u64),
+
+    #[error("No validator of the stake pool has {0} lamports to withdraw")]
+    NoWithdrawableValidator(u64),
+
+    #[error("Unstake.it lacks the liquidity to unstake {0} lamports")]
+    InsufficientUnstakeLiquidity(u64),
+
+    #[error("Stakedex out amount {0} is below the min amount out {1}")]
+    BelowMinAmountOut(u64, u64),
+
+    #[error("No free bridge stake account")]
+    NoFreeBridgeStake,
+}
+
+/// The SPL stake pool behind an lst
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StakedexPool {
+    pub program_id: Pubkey,
+    pub stake_pool: Pubkey,
+}
+
+/// A swap stakedex routes through the stake pools, its out amount is net of the router fee
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+pub enum StakedexRoute {
+    /// Deposit wSOL into the dst stake pool
+    StakeWrappedSol {
+        dst: StakePoolState,
+        out_amount: u64,
+    },
+    /// Withdraw a stake account from the src stake pool and deposit it into the dst one
+    SwapViaStake {
+        src: StakePoolState,
+        dst: StakePoolState,
+        validator: ValidatorStakeInfo,
+        out_amount: u64,
+    },
+    /// Withdraw a stake account from the src stake pool and unstake it for SOL with unstake.it
+    InstantUnstake {
+        src: StakePoolState,
+        validator: ValidatorStakeInfo,
+        unstake: UnstakeItState,
+        out_amount: u64,
+    },
+}
+
+impl StakedexRoute {
+    pub fn out_amount(&self) -> u64 {
+        match self {
+            StakedexRoute::StakeWrappedSol { out_amount, .. }
+            | StakedexRoute::SwapViaStake { out_amount, .. }
+            | StakedexRoute::InstantUnstake { out_amount, .. } => *out_amount,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            StakedexRoute::StakeWrappedSol { .. } => STAKE_WRAPPED_SOL_LABEL,
+            StakedexRoute::SwapViaStake { .. } => SWAP_VIA_STAKE_LABEL,
+            StakedexRoute::InstantUnstake { .. } => INSTANT_UNSTAKE_LABEL,
+        }
+    }
+
+    /// The quote of the route, exact as the stake pools charge no slippage and the router
+    /// fee is already off the out amount
+    pub fn to_quote(&self, src_mint: &Pubkey, dst_mint: &Pubkey, amount: u64) -> Quote {
+        Quote {
+            src_mint: *src_mint,
+            dst_mint: *dst_mint,
+            mode: SwapMode::ExactIn,
+            in_amount: amount,
+            out_amount: self.out_amount(),
+            min_out_amount: self.out_amount(),
+            max_in_amount: amount,
+            slippage_bps: 0,
+            price_impact_pct: 0.0,
+            route_labels: vec![self.label().to_string()],
+        }
+    }
+}
+
+/// Quote and swap lsts through their stake pools with the stakedex program,
+/// limited to the SPL stake pools it knows about
+pub struct StakedexQuoterClient {
+    rpc: RpcClient,
+    pools: HashMap<Pubkey, StakedexPool>,
+    router_fee_bps: u16,
+}
+
+impl StakedexQuoterClient {
+    pub fn new(rpc_url: &str) -> Self {
+        StakedexQuoterClient::from_parts(RpcClient::new(rpc_url.to_string()))
+    }
+
+    pub fn with_stake_pool(mut self, mint: &Pubkey, pool: StakedexPool) -> Self {
+        self.pools.insert(*mint, pool);
+        self
+    }
+
+    pub fn with_router_fee_bps(mut self, router_fee_bps: u16) -> Self {
+        self.router_fee_bps = router_fee_bps;
+        self
+    }
+
+    /// The out amount left to the user once stakedex takes its fee
+    pub fn net_of_router_fee(&self, out_amount: u64) -> u64 {
+        let fee = out_amount as u128 * self.router_fee_bps as u128 / MAX_FEE_BPS as u128;
+        out_amount - fee as u64
+    }
+
+    async fn get_stake_pool(&self, pool: &StakedexPool, epoch: u64) -> Result<StakePoolState> {
+        let account = self.rpc.get_account(&pool.stake_pool).await?;
+        let state =
+            StakePoolState::from_account_data(&pool.program_id, &pool.stake_pool, &account.data)?;
+        if !state.is_updated(epoch) {
+            return Err(StakedexQuoterError::StakePoolNotUpdated(pool.stake_pool).into());
+        }
+        Ok(state)
+    }
+
+    async fn get_validator_list(&self, pool: &StakePoolState) -> Result<ValidatorList> {
+        let account = self.rpc.get_account(&pool.validator_list).await?;
+        ValidatorList::from_account_data(&account.data)
+    }
+
+    async fn get_unstake_it(&self) -> Result<UnstakeItState> {
+        let account = self.rpc.get_account(&UNSTAKE_IT_POOL).await?;
+        let pool: UnstakePool = deserialize_account(&account.data)?;
+        let mut state = UnstakeItState {
+            pool: UNSTAKE_IT_POOL,
+            incoming_stake: pool.incoming_stake,
+            sol_reserves_lamports: 0,
+            fee: UnstakeFee::Flat {
+                ratio: Default::default(),
+            },
+            protocol_fee_destination: Pubkey::default(),
+        };
+        state.sol_reserves_lamports = self.rpc.get_balance(&state.pool_sol_reserves()).await?;
+        let account = self.rpc.get_account(&state.fee_account()).await?;
+        state.fee = deserialize_account(&account.data)?;
+        let account = self
+            .rpc
+            .get_account(&UnstakeItState::protocol_fee_account())
+            .await?;
+        let protocol_fee: ProtocolFee = deserialize_account(&account.data)?;
+        state.protocol_fee_destination = protocol_fee.destination;
+        Ok(state)
+    }
+
+    /// Withdraw the src amount as stake of the largest validator able to cover it and
+    /// unstake it instantly
+    async fn get_instant_unstake_route(
+        &self,
+        src_pool: &StakedexPool,
+        epoch: u64,
+        amount: u64,
+    ) -> Result<StakedexRoute> {
+        let src = self.get_stake_pool(src_pool, epoch).await?;
+        let lamports = src.stake_withdraw_out(amount);
+        let validator = self
+            .get_validator_list(&src)
+            .await?
+            .find_withdrawable(lamports, src.preferred_withdraw_validator.as_ref())
+            .ok_or(StakedexQuoterError::NoWithdrawableValidator(lamports))?
+            .clone();
+        let unstake = self.get_unstake_it().await?;
+        let out_amount = unstake
+            .unstake_out(lamports)
+            .ok_or(StakedexQuoterError::InsufficientUnstakeLiquidity(lamports))?;
+        let out_amount = self.net_of_router_fee(out_amount);
+        Ok(StakedexRoute::InstantUnstake {
+            src,
+            validator,
+            unstake,
+            out_amount,
+        })
+    }
+
+    /// Plan the swap of the src amount through the stake pools
+    pub async fn get_route(
+        &self,
+        src_mint: &Pubkey,
+        dst_mint: &Pubkey,
+        amount: u64,
+    ) -> Result<StakedexRoute> {
+        let no_route = StakedexQuoterError::NoRoute(*src_mint, *dst_mint);
+        if dst_mint.eq(&native_mint::ID) {
+            let Some(src_pool) = self.pools.get(src_mint) else {
+                return Err(no_route.into());
+            };
+            let epoch = self.rpc.get_epoch_info().await?.epoch;
+            return self
+                .get_instant_unstake_route(src_pool, epoch, amount)
+                .await;
+        }
+        let Some(dst_pool) = self.pools.get(dst_mint) else {
+            return Err(no_route.into());
+        };
+        let epoch = self.rpc.get_epoch_info().await?.epoch;
+
+        if src_mint.eq(&native_mint::ID) {
+            let dst = self.get_stake_pool(dst_pool, epoch).await?;
+            if !dst.accepts_sol_deposit() {
+                return Err(StakedexQuoterError::DepositNotAllowed(dst.address).into());
+            }
+            let out_amount = self.net_of_router_fee(dst.sol_deposit_out(amount));
+            return Ok(StakedexRoute::StakeWrappedSol { dst, out_amount });
+        }
+
+        let Some(src_pool) = self.pools.get(src_mint) else {
+            return Err(no_route.into());
+        };
+        let src = self.get_stake_pool(src_pool, epoch).await?;
+        let dst = self.get_stake_pool(dst_pool, epoch).await?;
+        if !dst.accepts_stake_deposit() {
+            return Err(StakedexQuoterError::DepositNotAllowed(dst.address).into());
+        }
+        let lamports = src.stake_withdraw_out(amount);
+        let src_validators = self.get_validator_list(&src).await?;
+        let dst_validators = self.get_validator_list(&dst).await?;
+        let validator = src_validators
+            .find_shared_validator(
+                &dst_validators,
+                lamports,
+                src.preferred_withdraw_validator.as_ref(),
+            )
+            .ok_or(StakedexQuoterError::NoSharedValidator(lamports))?
+            .clone();
+        let out_amount = self.net_of_router_fee(dst.stake_deposit_out(lamports));
+        Ok(StakedexRoute::SwapViaStake {
+            src,
+            dst,
+            validator,
+            out_amount,
+        })
+    }
+
+    /// A bridge stake seed of the user whose stake account does not exist, a failure to
+    /// fetch an account does not make its seed free
+    async fn get_free_bridge_stake_seed(&self, user: &Pubkey) -> Result<u32> {
+        for seed in 0..MAX_BRIDGE_STAKE_SEEDS {
+            let bridge_stake = bridge_stake_address(user, seed);
+            let account = self
+                .rpc
+                .get_account_with_commitment(&bridge_stake, self.rpc.commitment())
+                .await?
+                .value;
+            if account.is_none() {
+                return Ok(seed);
+            }
+        }
+        Err(StakedexQuoterError::NoFreeBridgeStake.into())
+    }
+}
+
+pub fn bridge_stake_address(user: &Pubkey, seed: u32) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"bridge_stake", user.as_ref(), &seed.to_le_bytes()],
+        &stakedex_interface::ID,
+    )
+    .0
+}
+
+/// The stakedex fee token account of the dst mint
+pub fn fee_token_account_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"fee", mint.as_ref()], &stakedex_interface::ID).0
+}
+
+/// Deposit the wSOL into the dst stake pool
+pub fn stake_wrapped_sol_instruction(
+    user: &Pubkey,
+    wsol_from: &Pubkey,
+    dest_token_to: &Pubkey,
+    dst: &StakePoolState,
+    lamports: u64,
+) -> Result<Instruction> {
+    let sol_bridge_out =
+        Pubkey::find_program_address(&[b"sol_bridge_out"], &stakedex_interface::ID).0;
+    let wsol_bridge_in = get_associated_token_address(&sol_bridge_out, &native_mint::ID);
+    let keys = StakeWrappedSolKeys {
+        user: *user,
+        wsol_from: *wsol_from,
+        dest_token_to: *dest_token_to,
+        wsol_bridge_in,
+        sol_bridge_out,
+        dest_token_fee_token_account: fee_token_account_address(&dst.pool_mint),
+        dest_token_mint: dst.pool_mint,
+        wsol_mint: native_mint::ID,
+        token_program: dst.token_program,
+        system_program: system_program::ID,
+    };
+    let metas: [AccountMeta; STAKE_WRAPPED_SOL_IX_ACCOUNTS_LEN] = keys.into();
+    let mut accounts = Vec::from(metas);
+    // The deposit SOL accounts of the stake pool
+    accounts.extend([
+        AccountMeta::new_readonly(dst.program_id, false),
+        AccountMeta::new(dst.address, false),
+        AccountMeta::new_readonly(dst.withdraw_authority(), false),
+        AccountMeta::new(dst.reserve_stake, false),
+        AccountMeta::new(dst.manager_fee_account, false),
+    ]);
+    let data: StakeWrappedSolIxData = StakeWrappedSolIxArgs { amount: lamports }.into();
+    Ok(Instruction {
+        program_id: stakedex_interface::ID,
+        accounts,
+        data: data.try_to_vec()?,
+    })
+}
+
+/// The deposit stake accounts of the dst stake pool
+pub fn deposit_stake_accounts(
+    dst: &StakePoolState,
+    validator: &ValidatorStakeInfo,
+) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new_readonly(dst.program_id, false),
+        AccountMeta::new(dst.address, false),
+        AccountMeta::new(dst.validator_list, false),
+        AccountMeta::new_readonly(dst.stake_deposit_authority, false),
+        AccountMeta::new_readonly(dst.withdraw_authority(), false),
+        AccountMeta::new(dst.reserve_stake, false),
+        AccountMeta::new(dst.manager_fee_account, false),
+        AccountMeta::new(
+            validator.stake_account(&dst.program_id, &dst.address),
+            false,
+        ),
+        AccountMeta::new_readonly(sysvar::clock::ID, false),
+        AccountMeta::new_readonly(sysvar::stake_history::ID, false),
+        AccountMeta::new_readonly(dst.token_program, false),
+        AccountMeta::new_readonly(stake::program::ID, false),
+    ]
+}
+
+/// Withdraw the src pool tokens as a stake account of the validator and deposit it
+/// with the deposit accounts of the dst venue, a stake pool or unstake.it
+#[allow(clippy::too_many_arguments)]
+pub fn swap_via_stake_instruction(
+    user: &Pubkey,
+    src_token_from: &Pubkey,
+    dest_token_to: &Pubkey,
+    dest_token_mint: &Pubkey,
+    bridge_stake_seed: u32,
+    src: &StakePoolState,
+    validator: &ValidatorStakeInfo,
+    deposit_accounts: Vec<AccountMeta>,
+    amount: u64,
+) -> Result<Instruction> {
+    let keys = SwapViaStakeKeys {
+        user: *user,
+        src_token_from: *src_token_from,
+        dest_token_to: *dest_token_to,
+        bridge_stake: bridge_stake_address(user, bridge_stake_seed),
+        dest_token_fee_token_account: fee_token_account_address(dest_token_mint),
+        src_token_mint: src.pool_mint,
+        dest_token_mint: *dest_token_mint,
+    };
+    let metas: [AccountMeta; SWAP_VIA_STAKE_IX_ACCOUNTS_LEN] = keys.into();
+    let mut accounts = Vec::from(metas);
+    // The withdraw stake accounts of the src stake pool
+    accounts.extend([
+        AccountMeta::new_readonly(src.program_id, false),
+        AccountMeta::new(src.address, false),
+        AccountMeta::new(src.validator_list, false),
+        AccountMeta::new_readonly(src.withdraw_authority(), false),
+        AccountMeta::new(
+            validator.stake_account(&src.program_id, &src.address),
+            false,
+        ),
+        AccountMeta::new(src.manager_fee_account, false),
+        AccountMeta::new_readonly(sysvar::clock::ID, false),
+        AccountMeta::new_readonly(src.token_program, false),
+        AccountMeta::new_readonly(stake::program::ID, false),
+    ]);
+    accounts.extend(deposit_accounts);
+    let data: SwapViaStakeIxData = SwapViaStakeIxArgs {
+        args: SwapViaStakeArgs {
+            amount,
+            bridge_stake_seed,
+        },
+    }
+    .into();
+    Ok(Instruction {
+        program_id: stakedex_interface::ID,
+        accounts,
+        data: data.try_to_vec()?,
+    })
+}
+
+#[async_trait::async_trait]
+impl QuoterClient for StakedexQuoterClient {
+    fn from_parts(rpc: RpcClient) -> Self {
+        Self {
+            rpc,
+            pools: HashMap::new(),
+            router_fee_bps: DEFAULT_ROUTER_FEE_BPS,
+        }
+    }
+
+    fn get_rpc_client(&self) -> &RpcClient {
+        &self.rpc
+    }
+
+    async fn quote(
+        &self,
+        src_mint: &Pubkey,
+        dst_mint: &Pubkey,
+        amount: u64,
+        mode: SwapMode,
+    ) -> Result<Quote> {
+        if mode != SwapMode::ExactIn {
+            return Err(StakedexQuoterError::UnsupportedSwapMode(mode).into());
+        }
+        let route = self.get_route(src_mint, dst_mint, amount).await?;
+        Ok(route.to_quote(src_mint, dst_mint, amount))
+    }
+
+    /// The route is always a single stakedex instruction, the route constraints hold as is
+    async fn create_swap_instructions(
+        &self,
+        swapper: &Pubkey,
+        receiver_token_account: &Pubkey,
+        src_mint: &Pubkey,
+        dst_mint: &Pubkey,
+        amount: u64,
+        min_amount_out: u64,
+        _slippage_bps: Option<u16>,
+        _route_constraints: RouteConstraints,
+    ) -> Result<SwapInstructions> {
+        let route = self.get_route(src_mint, dst_mint, amount).await?;
+        if route.out_amount() < min_amount_out {
+            return Err(
+                StakedexQuoterError::BelowMinAmountOut(route.out_amount(), min_amount_out).into(),
+            );
+        }
+
+        let src_token_program = src_mint.get_mint_owner(&self.rpc).await?;
+        let src_token_from = src_mint
+            .get_associated_token_account_with_program_id(swapper, &src_token_program)
+            .await?;
+        let swap_instruction = match &route {
+            StakedexRoute::StakeWrappedSol { dst, .. } => stake_wrapped_sol_instruction(
+                swapper,
+                &src_token_from,
+                receiver_token_account,
+                dst,
+                amount,
+            )?,
+            StakedexRoute::SwapViaStake {
+                src,
+                dst,
+                validator,
+                ..
+            } => {
+                let bridge_stake_seed = self.get_free_bridge_stake_seed(swapper).await?;
+                swap_via_stake_instruction(
+                    swapper,
+                    &src_token_from,
+                    receiver_token_account,
+                    &dst.pool_mint,
+                    bridge_stake_seed,
+                    src,
+                    validator,
+                    deposit_stake_accounts(dst, validator),
+                    amount,
+                )?
+            }
+            StakedexRoute::InstantUnstake {
+                src,
+                validator,
+                unstake,
+                ..
+            } => {
+                let bridge_stake_seed = self.get_free_bridge_stake_seed(swapper).await?;
+                let bridge_stake = bridge_stake_address(swapper, bridge_stake_seed);
+                swap_via_stake_instruction(
+                    swapper,
+                    &src_token_from,
+                    receiver_token_account,
+                    &native_mint::ID,
+                    bridge_stake_seed,
+                    src,
+                    validator,
+                    unstake.deposit_stake_accounts(&bridge_stake),
+                    amount,
+                )?
+            }
+        };
+
+        Ok(SwapInstructions {
+            compute_budget_instructions: vec![],
+            setup_instructions: vec![],
+            swap_instructions: vec![swap_instruction],
+            cleanup_instructions: vec![],
+            other_instructions: vec![],
+            address_lookup_tables: vec![],
+            quote: Some(route.to_quote(src_mint, dst_mint, amount)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        stake_pool::FeeRatio,
+        test_utils::{self, FixtureCluster},
+        unstake_it::Rational,
+        validator_list::StakeStatus,
+    };
+
+    fn stake_pool() -> StakePoolState {
+        let program_id = Pubkey::new_unique();
+        let address = Pubkey::new_unique();
+        StakePoolState {
+            program_id,
+            address,
+            pool_mint: Pubkey::new_unique(),
+            token_program: spl_token::ID,
+            validator_list: Pubkey::new_unique(),
+            reserve_stake: Pubkey::new_unique(),
+            manager_fee_account: Pubkey::new_unique(),
+            stake_deposit_authority: Pubkey::find_program_address(
+                &[address.as_ref(), b"deposit"],
+                &program_id,
+            )
+            .0,
+            sol_deposit_authority: None,
+            preferred_withdraw_validator: None,
+            last_update_epoch: 700,
+            total_lamports: 1_100_000_000,
+            pool_token_supply: 1_000_000_000,
+            stake_deposit_fee: FeeRatio::default(),
+            stake_withdrawal_fee: FeeRatio::default(),
+            sol_deposit_fee: FeeRatio::default(),
+        }
+    }
+
+    #[test]
+    fn test_stake_wrapped_sol_instruction() {
+        let (user, wsol_from, dest_token_to) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let dst = stake_pool();
+        let ix =
+            stake_wrapped_sol_instruction(&user, &wsol_from, &dest_token_to, &dst, 1_000).unwrap();
+        assert_eq!(ix.program_id, stakedex_interface::ID);
+        assert_eq!(ix.accounts.len(), STAKE_WRAPPED_SOL_IX_ACCOUNTS_LEN + 5);
+        assert_eq!(ix.accounts[0].pubkey, user);
+        assert_eq!(
+            ix.accounts[STAKE_WRAPPED_SOL_IX_ACCOUNTS_LEN].pubkey,
+            dst.program_id
+        );
+        assert_eq!(ix.accounts.last().unwrap().pubkey, dst.manager_fee_account);
+    }
+
+    #[test]
+    fn test_swap_via_stake_instruction() {
+        let (user, src_token_from, dest_token_to) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let (src, dst) = (stake_pool(), stake_pool());
+        let validator = ValidatorStakeInfo {
+            active_stake_lamports: 10_000_000_000,
+            transient_stake_lamports: 0,
+            last_update_epoch: 700,
+            transient_seed_suffix: 0,
+            unused: 0,
+            validator_seed_suffix: 0,
+            status: StakeStatus::Active,
+            vote_account_address: Pubkey::new_unique(),
+        };
+        let ix = swap_via_stake_instruction(
+            &user,
+            &src_token_from,
+            &dest_token_to,
+            &dst.pool_mint,
+            2,
+            &src,
+            &validator,
+            deposit_stake_accounts(&dst, &validator),
+            1_000,
+        )
+        .unwrap();
+        assert_eq!(ix.accounts.len(), SWAP_VIA_STAKE_IX_ACCOUNTS_LEN + 9 + 12);
+        assert!(ix
+            .accounts
+            .iter()
+            .any(|meta| meta.pubkey == bridge_stake_address(&user, 2)));
+        assert_eq!(
+            ix.accounts[SWAP_VIA_STAKE_IX_ACCOUNTS_LEN + 4].pubkey,
+            validator.stake_account(&src.program_id, &src.address)
+        );
+        assert_eq!(
+            ix.accounts[SWAP_VIA_STAKE_IX_ACCOUNTS_LEN + 9].pubkey,
+            dst.program_id
+        );
+    }
+
+    #[test]
+    fn test_instant_unstake_instruction() {
+        let (user, src_token_from, dest_token_to) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let src = stake_pool();
+        let validator = ValidatorStakeInfo {
+            active_stake_lamports: 10_000_000_000,
+            transient_stake_lamports: 0,
+            last_update_epoch: 700,
+            transient_seed_suffix: 0,
+            unused: 0,
+            validator_seed_suffix: 0,
+            status: StakeStatus::Active,
+            vote_account_address: Pubkey::new_unique(),
+        };
+        let unstake = UnstakeItState {
+            pool: UNSTAKE_IT_POOL,
+            incoming_stake: 0,
+            sol_reserves_lamports: 1_000_000_000,
+            fee: UnstakeFee::Flat {
+                ratio: Default::default(),
+            },
+            protocol_fee_destination: Pubkey::new_unique(),
+        };
+        let bridge_stake = bridge_stake_address(&user, 0);
+        let ix = swap_via_stake_instruction(
+            &user,
+            &src_token_from,
+            &dest_token_to,
+            &native_mint::ID,
+            0,
+            &src,
+            &validator,
+            unstake.deposit_stake_accounts(&bridge_stake),
+            1_000,
+        )
+        .unwrap();
+        assert_eq!(ix.accounts.len(), SWAP_VIA_STAKE_IX_ACCOUNTS_LEN + 9 + 10);
+        assert!(ix
+            .accounts
+            .iter()
+            .any(|meta| meta.pubkey == fee_token_account_address(&native_mint::ID)));
+        assert_eq!(
+            ix.accounts[SWAP_VIA_STAKE_IX_ACCOUNTS_LEN + 9].pubkey,
+            crate::unstake_it::UNSTAKE_IT_PROGRAM_ID
+        );
+    }
+
+    #[test]
+    fn test_net_of_router_fee() {
+        let client = StakedexQuoterClient::new("http://localhost:8899");
+        assert_eq!(client.net_of_router_fee(1_000_000), 999_000);
+        let client = client.with_router_fee_bps(0);
+        assert_eq!(client.net_of_router_fee(1_000_000), 1_000_000);
+    }
+
+    #[test]
+    fn test_route_quote() {
+        let route = StakedexRoute::StakeWrappedSol {
+            dst: stake_pool(),
+            out_amount: 900,
+        };
+        let (src_mint, dst_mint) = (native_mint::ID, Pubkey::new_unique());
+        let quote = route.to_quote(&src_mint, &dst_mint, 1_000);
+        assert_eq!(quote.out_amount, 900);
+        assert_eq!(quote.min_out_amount, 900);
+        assert_eq!(
+            quote.route_labels,
+            vec![STAKE_WRAPPED_SOL_LABEL.to_string()]
+        );
+    }
+
+    fn validator(vote_account_address: Pubkey, active_stake_lamports: u64) -> ValidatorStakeInfo {
+        ValidatorStakeInfo {
+            active_stake_lamports,
+            transient_stake_lamports: 0,
+            last_update_epoch: 700,
+            transient_seed_suffix: 0,
+            unused: 0,
+            validator_seed_suffix: 0,
+            status: StakeStatus::Active,
+            vote_account_address,
+        }
+    }
+
+    fn validator_list(validators: Vec<ValidatorStakeInfo>) -> ValidatorList {
+        ValidatorList {
+            account_type: 2,
+            max_validators: 10,
+            validators,
+        }
+    }
+
+    /// Two stake pools sharing a validator and the unstake.it pool, served from their
+    /// serialized accounts
+    struct RouteFixture {
+        src: StakePoolState,
+        dst: StakePoolState,
+        shared: Pubkey,
+        quoter: StakedexQuoterClient,
+    }
+
+    fn route_fixture() -> RouteFixture {
+        // 1.1 SOL per src token with a 10 bps withdrawal fee
+        let mut src = test_utils::stake_pool(700, 1_100_000_000_000, 1_000_000_000_000);
+        src.stake_withdrawal_fee = FeeRatio::new(1, 1000);
+        // 1.2 SOL per dst token with a 1% SOL deposit fee
+        let mut dst = test_utils::stake_pool(700, 1_200_000_000_000, 1_000_000_000_000);
+        dst.sol_deposit_fee = FeeRatio::new(1, 100);
+
+        let (shared, larger) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let src_validators = validator_list(vec![
+            validator(shared, 10_000_000_000),
+            validator(larger, 50_000_000_000),
+        ]);
+        let dst_validators = validator_list(vec![validator(shared, 10_000_000_000)]);
+        let unstake = UnstakeItState {
+            pool: UNSTAKE_IT_POOL,
+            incoming_stake: 0,
+            sol_reserves_lamports: 100_000_000_000,
+            fee: UnstakeFee::Flat {
+                ratio: Rational {
+                    num: 1,
+                    denom: 1024,
+                },
+            },
+            protocol_fee_destination: Pubkey::new_unique(),
+        };
+
+        let rpc = FixtureCluster::new(700)
+            .with_stake_pool(&src, &src_validators)
+            .with_stake_pool(&dst, &dst_validators)
+            .with_unstake_it(&unstake)
+            .rpc_client();
+        let quoter = StakedexQuoterClient::from_parts(rpc)
+            .with_stake_pool(&src.pool_mint, test_utils::stakedex_pool(&src))
+            .with_stake_pool(&dst.pool_mint, test_utils::stakedex_pool(&dst));
+        RouteFixture {
+            src,
+            dst,
+            shared,
+            quoter,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_route_stake_wrapped_sol() {
+        let fixture = route_fixture();
+        let route = fixture
+            .quoter
+            .get_route(&native_mint::ID, &fixture.dst.pool_mint, 1_200_000_000)
+            .await
+            .unwrap();
+        // 1 dst token less the 1% deposit fee and the 10 bps router fee
+        assert_eq!(
+            route,
+            StakedexRoute::StakeWrappedSol {
+                dst: fixture.dst,
+                out_amount: 989_010_000,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_route_swap_via_stake() {
+        let fixture = route_fixture();
+        let route = fixture
+            .quoter
+            .get_route(
+                &fixture.src.pool_mint,
+                &fixture.dst.pool_mint,
+                1_000_000_000,
+            )
+            .await
+            .unwrap();
+        let StakedexRoute::SwapViaStake {
+            validator,
+            out_amount,
+            ..
+        } = route
+        else {
+            panic!("Expected a swap via stake, got {:?}", route);
+        };
+        // Only the shared validator is accepted by the dst pool
+        assert_eq!(validator.vote_account_address, fixture.shared);
+        // 1.0989 SOL of stake for 0.91575 dst token, less the 10 bps router fee
+        assert_eq!(out_amount, 914_834_250);
+    }
+
+    #[tokio::test]
+    async fn test_get_route_instant_unstake() {
+        let fixture = route_fixture();
+        let route = fixture
+            .quoter
+            .get_route(&fixture.src.pool_mint, &native_mint::ID, 1_000_000_000)
+            .await
+            .unwrap();
+        let StakedexRoute::InstantUnstake {
+            validator,
+            unstake,
+            out_amount,
+            ..
+        } = route
+        else {
+            panic!("Expected an instant unstake, got {:?}", route);
+        };
+        assert_ne!(validator.vote_account_address, fixture.shared);
+        assert_eq!(unstake.sol_reserves_lamports, 100_000_000_000);
+        // 1.0989 SOL of stake less the 1/1024 unstake fee and the 10 bps router fee
+        assert_eq!(out_amount, 1_096_729_029);
+    }
+
+    #[tokio::test]
+    async fn test_get_route_fail_on_stale_stake_pool() {
+        let mut fixture = route_fixture();
+        fixture.quoter.rpc = FixtureCluster::new(701)
+            .with_stake_pool(&fixture.dst, &validator_list(vec![]))
+            .rpc_client();
+        assert_eq!(
+            fixture
+                .quoter
+                .get_route(&native_mint::ID, &fixture.dst.pool_mint, 1_200_000_000)
+                .await
+                .err()
+                .unwrap()
+                .to_string(),
+            StakedexQuoterError::StakePoolNotUpdated(fixture.dst.address).to_string()
+        );
+    }
+}
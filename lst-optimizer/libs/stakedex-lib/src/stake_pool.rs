@@ -0,0 +1,286 @@
+use anyhow::Result;
+use borsh::BorshDeserialize;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    stake, sysvar,
+};
+use spl_calculator_interface::SplStakePool;
+
+use crate::validator_list::ValidatorStakeInfo;
+
+/// The index of the SPL stake pool WithdrawStake instruction
+const WITHDRAW_STAKE_IX_INDEX: u8 = 10;
+
+/// A fee charged as a ratio of the amount
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FeeRatio {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+impl FeeRatio {
+    pub fn new(numerator: u64, denominator: u64) -> Self {
+        Self {
+            numerator,
+            denominator,
+        }
+    }
+
+    /// The fee charged on the amount
+    pub fn apply(&self, amount: u64) -> u64 {
+        if self.denominator == 0 {
+            return 0;
+        }
+        (amount as u128 * self.numerator as u128 / self.denominator as u128) as u64
+    }
+}
+
+/// The state of an SPL stake pool the stakedex routes go through
+#[derive(Debug, Clone, PartialEq)]
+pub struct StakePoolState {
+    pub program_id: Pubkey,
+    pub address: Pubkey,
+    pub pool_mint: Pubkey,
+    pub token_program: Pubkey,
+    pub validator_list: Pubkey,
+    pub reserve_stake: Pubkey,
+    pub manager_fee_account: Pubkey,
+    pub stake_deposit_authority: Pubkey,
+    pub sol_deposit_authority: Option<Pubkey>,
+    /// The validator withdrawals must come from while it has stake
+    pub preferred_withdraw_validator: Option<Pubkey>,
+    pub last_update_epoch: u64,
+    pub total_lamports: u64,
+    pub pool_token_supply: u64,
+    pub stake_deposit_fee: FeeRatio,
+    pub stake_withdrawal_fee: FeeRatio,
+    pub sol_deposit_fee: FeeRatio,
+}
+
+impl StakePoolState {
+    pub fn from_account(program_id: &Pubkey, address: &Pubkey, state: &SplStakePool) -> Self {
+        Self {
+            program_id: *program_id,
+            address: *address,
+            pool_mint: state.pool_mint,
+            token_program: state.token_program_id,
+            validator_list: state.validator_list,
+            reserve_stake: state.reserve_stake,
+            manager_fee_account: state.manager_fee_account,
+            stake_deposit_authority: state.stake_deposit_authority,
+            sol_deposit_authority: state.sol_deposit_authority,
+            preferred_withdraw_validator: state.preferred_withdraw_validator_vote_address,
+            last_update_epoch: state.last_update_epoch,
+            total_lamports: state.total_lamports,
+            pool_token_supply: state.pool_token_supply,
+            stake_deposit_fee: FeeRatio::new(
+                state.stake_deposit_fee.numerator,
+                state.stake_deposit_fee.denominator,
+            ),
+            stake_withdrawal_fee: FeeRatio::new(
+                state.stake_withdrawal_fee.numerator,
+                state.stake_withdrawal_fee.denominator,
+            ),
+            sol_deposit_fee: FeeRatio::new(
+                state.sol_deposit_fee.numerator,
+                state.sol_deposit_fee.denominator,
+            ),
+        }
+    }
+
+    pub fn from_account_data(program_id: &Pubkey, address: &Pubkey, data: &[u8]) -> Result<Self> {
+        let state = SplStakePool::deserialize(&mut &data[..])?;
+        Ok(Self::from_account(program_id, address, &state))
+    }
+
+    pub fn withdraw_authority(&self) -> Pubkey {
+        Pubkey::find_program_address(&[self.address.as_ref(), b"withdraw"], &self.program_id).0
+    }
+
+    /// The deposit authority of a pool taking permissionless stake deposits
+    pub fn default_deposit_authority(&self) -> Pubkey {
+        Pubkey::find_program_address(&[self.address.as_ref(), b"deposit"], &self.program_id).0
+    }
+
+    /// A pool not updated this epoch rejects deposits and withdrawals
+    pub fn is_updated(&self, epoch: u64) -> bool {
+        self.last_update_epoch >= epoch
+    }
+
+    pub fn accepts_sol_deposit(&self) -> bool {
+        self.sol_deposit_authority.is_none()
+    }
+
+    pub fn accepts_stake_deposit(&self) -> bool {
+        self.stake_deposit_authority == self.default_deposit_authority()
+    }
+
+    /// The pool tokens worth the lamports
+    pub fn pool_tokens_for(&self, lamports: u64) -> u64 {
+        if self.total_lamports == 0 || self.pool_token_supply == 0 {
+            return lamports;
+        }
+        (lamports as u128 * self.pool_token_supply as u128 / self.total_lamports as u128) as u64
+    }
+
+    /// The lamports worth the pool tokens
+    pub fn lamports_for(&self, pool_tokens: u64) -> u64 {
+        if self.total_lamports == 0 || self.pool_token_supply == 0 {
+            return pool_tokens;
+        }
+        (pool_tokens as u128 * self.total_lamports as u128 / self.pool_token_supply as u128) as u64
+    }
+
+    /// The pool tokens received for depositing the lamports
+    pub fn sol_deposit_out(&self, lamports: u64) -> u64 {
+        let minted = self.pool_tokens_for(lamports);
+        minted - self.sol_deposit_fee.apply(minted)
+    }
+
+    /// The pool tokens received for depositing a stake account of the lamports,
+    /// treating its rent exempt reserve as stake
+    pub fn stake_deposit_out(&self, lamports: u64) -> u64 {
+        let minted = self.pool_tokens_for(lamports);
+        minted - self.stake_deposit_fee.apply(minted)
+    }
+
+    /// The lamports of the stake account split off for burning the pool tokens
+    pub fn stake_withdraw_out(&self, pool_tokens: u64) -> u64 {
+        self.lamports_for(pool_tokens - self.stake_withdrawal_fee.apply(pool_tokens))
+    }
+
+    /// Burn the pool tokens for a stake account split off the validator stake account,
+    /// the stake to receive is allocated to the stake program and left uninitialized
+    pub fn withdraw_stake_instruction(
+        &self,
+        validator: &ValidatorStakeInfo,
+        stake_to_receive: &Pubkey,
+        user: &Pubkey,
+        pool_tokens_from: &Pubkey,
+        pool_tokens: u64,
+    ) -> Instruction {
+        let mut data = vec![WITHDRAW_STAKE_IX_INDEX];
+        data.extend(pool_tokens.to_le_bytes());
+        Instruction {
+            program_id: self.program_id,
+            accounts: vec![
+                AccountMeta::new(self.address, false),
+                AccountMeta::new(self.validator_list, false),
+                AccountMeta::new_readonly(self.withdraw_authority(), false),
+                AccountMeta::new(
+                    validator.stake_account(&self.program_id, &self.address),
+                    false,
+                ),
+                AccountMeta::new(*stake_to_receive, false),
+                // The user is both the new stake authority and the pool tokens owner
+                AccountMeta::new_readonly(*user, false),
+                AccountMeta::new_readonly(*user, true),
+                AccountMeta::new(*pool_tokens_from, false),
+                AccountMeta::new(self.manager_fee_account, false),
+                AccountMeta::new(self.pool_mint, false),
+                AccountMeta::new_readonly(sysvar::clock::ID, false),
+                AccountMeta::new_readonly(self.token_program, false),
+                AccountMeta::new_readonly(stake::program::ID, false),
+            ],
+            data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1.1 SOL per pool token
+    fn stake_pool() -> StakePoolState {
+        let program_id = Pubkey::new_unique();
+        let address = Pubkey::new_unique();
+        let stake_deposit_authority =
+            Pubkey::find_program_address(&[address.as_ref(), b"deposit"], &program_id).0;
+        StakePoolState {
+            program_id,
+            address,
+            pool_mint: Pubkey::new_unique(),
+            token_program: Pubkey::new_unique(),
+            validator_list: Pubkey::new_unique(),
+            reserve_stake: Pubkey::new_unique(),
+            manager_fee_account: Pubkey::new_unique(),
+            stake_deposit_authority,
+            sol_deposit_authority: None,
+            preferred_withdraw_validator: None,
+            last_update_epoch: 700,
+            total_lamports: 1_100_000,
+            pool_token_supply: 1_000_000,
+            stake_deposit_fee: FeeRatio::default(),
+            stake_withdrawal_fee: FeeRatio::new(1, 1000),
+            sol_deposit_fee: FeeRatio::new(1, 100),
+        }
+    }
+
+    #[test]
+    fn test_deposit_out() {
+        let pool = stake_pool();
+        assert_eq!(pool.sol_deposit_out(1_100_000), 990_000);
+        assert_eq!(pool.stake_deposit_out(1_100_000), 1_000_000);
+    }
+
+    #[test]
+    fn test_stake_withdraw_out() {
+        let pool = stake_pool();
+        assert_eq!(pool.stake_withdraw_out(1_000_000), 1_098_900);
+        assert_eq!(FeeRatio::new(1, 0).apply(1_000), 0);
+    }
+
+    #[test]
+    fn test_withdraw_stake_instruction() {
+        let pool = stake_pool();
+        let validator = ValidatorStakeInfo {
+            active_stake_lamports: 10_000_000_000,
+            transient_stake_lamports: 0,
+            last_update_epoch: 700,
+            transient_seed_suffix: 0,
+            unused: 0,
+            validator_seed_suffix: 0,
+            status: crate::validator_list::StakeStatus::Active,
+            vote_account_address: Pubkey::new_unique(),
+        };
+        let (stake_to_receive, user, pool_tokens_from) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let ix = pool.withdraw_stake_instruction(
+            &validator,
+            &stake_to_receive,
+            &user,
+            &pool_tokens_from,
+            1_000,
+        );
+        assert_eq!(ix.program_id, pool.program_id);
+        assert_eq!(ix.accounts.len(), 13);
+        assert_eq!(
+            ix.accounts[3].pubkey,
+            validator.stake_account(&pool.program_id, &pool.address)
+        );
+        assert!(ix.accounts[6].is_signer);
+        assert_eq!(
+            ix.data,
+            [vec![10], 1_000u64.to_le_bytes().to_vec()].concat()
+        );
+    }
+
+    #[test]
+    fn test_accepts_deposits() {
+        let mut pool = stake_pool();
+        assert!(pool.accepts_sol_deposit());
+        assert!(pool.accepts_stake_deposit());
+        assert!(pool.is_updated(700));
+        assert!(!pool.is_updated(701));
+
+        pool.sol_deposit_authority = Some(Pubkey::new_unique());
+        pool.stake_deposit_authority = Pubkey::new_unique();
+        assert!(!pool.accepts_sol_deposit());
+        assert!(!pool.accepts_stake_deposit());
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use borsh::BorshSerialize;
+use serde_json::{json, Value};
+use solana_client::{
+    client_error::Result as ClientResult,
+    nonblocking::rpc_client::RpcClient,
+    rpc_client::RpcClientConfig,
+    rpc_request::{RpcError, RpcRequest},
+    rpc_sender::{RpcSender, RpcTransportStats},
+};
+use solana_sdk::{account::Account, commitment_config::CommitmentConfig, pubkey::Pubkey};
+
+use crate::{
+    quoter::StakedexPool,
+    stake_pool::{FeeRatio, StakePoolState},
+    unstake_it::{ProtocolFee, UnstakeItState, UnstakePool, UNSTAKE_IT_PROGRAM_ID},
+    validator_list::ValidatorList,
+};
+
+/// The accounts of a cluster at an epoch, served by address like a node would
+pub struct FixtureCluster {
+    pub epoch: u64,
+    pub accounts: HashMap<Pubkey, Account>,
+}
+
+impl FixtureCluster {
+    pub fn new(epoch: u64) -> Self {
+        Self {
+            epoch,
+            accounts: HashMap::new(),
+        }
+    }
+
+    pub fn with_account(mut self, address: &Pubkey, owner: &Pubkey, data: Vec<u8>) -> Self {
+        self.accounts.insert(
+            *address,
+            Account {
+                lamports: 1_000_000_000,
+                data,
+                owner: *owner,
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        self
+    }
+
+    pub fn with_lamports(mut self, address: &Pubkey, lamports: u64) -> Self {
+        self.accounts.insert(
+            *address,
+            Account {
+                lamports,
+                ..Default::default()
+            },
+        );
+        self
+    }
+
+    /// The stake pool and its validator list serialized as the SPL stake pool program does
+    pub fn with_stake_pool(self, pool: &StakePoolState, validators: &ValidatorList) -> Self {
+        self.with_account(&pool.address, &pool.program_id, stake_pool_data(pool))
+            .with_account(
+                &pool.validator_list,
+                &pool.program_id,
+                validators.try_to_vec().unwrap(),
+            )
+    }
+
+    /// The unstake.it pool, its fee and protocol fee accounts and its SOL reserves
+    pub fn with_unstake_it(self, state: &UnstakeItState) -> Self {
+        let pool = UnstakePool {
+            fee_authority: Pubkey::new_unique(),
+            lp_mint: Pubkey::new_unique(),
+            incoming_stake: state.incoming_stake,
+        };
+        let protocol_fee = ProtocolFee {
+            destination: state.protocol_fee_destination,
+            authority: Pubkey::new_unique(),
+            fee_ratio: Default::default(),
+            referrer_fee_ratio: Default::default(),
+        };
+        self.with_account(&state.pool, &UNSTAKE_IT_PROGRAM_ID, anchor_data(&pool))
+            .with_account(
+                &state.fee_account(),
+                &UNSTAKE_IT_PROGRAM_ID,
+                anchor_data(&state.fee),
+            )
+            .with_account(
+                &UnstakeItState::protocol_fee_account(),
+                &UNSTAKE_IT_PROGRAM_ID,
+                anchor_data(&protocol_fee),
+            )
+            .with_lamports(&state.pool_sol_reserves(), state.sol_reserves_lamports)
+    }
+
+    pub fn rpc_client(self) -> RpcClient {
+        RpcClient::new_sender(
+            self,
+            RpcClientConfig::with_commitment(CommitmentConfig::confirmed()),
+        )
+    }
+}
+
+#[async_trait::async_trait]
+impl RpcSender for FixtureCluster {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let account = params[0]
+            .as_str()
+            .and_then(|address| address.parse::<Pubkey>().ok())
+            .and_then(|address| self.accounts.get(&address));
+        let context = json!({ "slot": 1 });
+        let result = match request {
+            RpcRequest::GetVersion => json!({ "solana-core": "2.0.0" }),
+            RpcRequest::GetEpochInfo => json!({
+                "absoluteSlot": 1,
+                "blockHeight": 1,
+                "epoch": self.epoch,
+                "slotIndex": 0,
+                "slotsInEpoch": 432_000,
+            }),
+            RpcRequest::GetAccountInfo => json!({
+                "context": context,
+                "value": account.map(|account| json!({
+                    "lamports": account.lamports,
+                    "data": [STANDARD.encode(&account.data), "base64"],
+                    "owner": account.owner.to_string(),
+                    "executable": account.executable,
+                    "rentEpoch": account.rent_epoch,
+                    "space": account.data.len(),
+                })),
+            }),
+            RpcRequest::GetBalance => json!({
+                "context": context,
+                "value": account.map_or(0, |account| account.lamports),
+            }),
+            _ => {
+                return Err(
+                    RpcError::ForUser(format!("No fixture for the {} request", request)).into(),
+                )
+            }
+        };
+        Ok(result)
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        RpcTransportStats::default()
+    }
+
+    fn url(&self) -> String {
+        "fixture".to_string()
+    }
+}
+
+/// An SPL stake pool with the exchange rate of `total_lamports` per `pool_token_supply`
+pub fn stake_pool(epoch: u64, total_lamports: u64, pool_token_supply: u64) -> StakePoolState {
+    let program_id = Pubkey::new_unique();
+    let address = Pubkey::new_unique();
+    StakePoolState {
+        program_id,
+        address,
+        pool_mint: Pubkey::new_unique(),
+        token_program: spl_token::ID,
+        validator_list: Pubkey::new_unique(),
+        reserve_stake: Pubkey::new_unique(),
+        manager_fee_account: Pubkey::new_unique(),
+        stake_deposit_authority: Pubkey::find_program_address(
+            &[address.as_ref(), b"deposit"],
+            &program_id,
+        )
+        .0,
+        sol_deposit_authority: None,
+        preferred_withdraw_validator: None,
+        last_update_epoch: epoch,
+        total_lamports,
+        pool_token_supply,
+        stake_deposit_fee: FeeRatio::default(),
+        stake_withdrawal_fee: FeeRatio::default(),
+        sol_deposit_fee: FeeRatio::default(),
+    }
+}
+
+pub fn stakedex_pool(pool: &StakePoolState) -> StakedexPool {
+    StakedexPool {
+        program_id: pool.program_id,
+        stake_pool: pool.address,
+    }
+}
+
+/// The stake pool account data laid out as the SPL stake pool program does
+pub fn stake_pool_data(pool: &StakePoolState) -> Vec<u8> {
+    fn fee(data: &mut Vec<u8>, fee: &FeeRatio) {
+        data.extend(fee.denominator.to_le_bytes());
+        data.extend(fee.numerator.to_le_bytes());
+    }
+    fn option(data: &mut Vec<u8>, key: &Option<Pubkey>) {
+        match key {
+            Some(key) => {
+                data.push(1);
+                data.extend(key.as_ref());
+            }
+            None => data.push(0),
+        }
+    }
+
+    // The account type, manager and staker
+    let mut data = vec![1];
+    data.extend(Pubkey::new_unique().as_ref());
+    data.extend(Pubkey::new_unique().as_ref());
+    data.extend(pool.stake_deposit_authority.as_ref());
+    // The stake withdraw bump seed
+    data.push(255);
+    for key in [
+        pool.validator_list,
+        pool.reserve_stake,
+        pool.pool_mint,
+        pool.manager_fee_account,
+        pool.token_program,
+    ] {
+        data.extend(key.as_ref());
+    }
+    data.extend(pool.total_lamports.to_le_bytes());
+    data.extend(pool.pool_token_supply.to_le_bytes());
+    data.extend(pool.last_update_epoch.to_le_bytes());
+    // The lockup
+    data.extend(0i64.to_le_bytes());
+    data.extend(0u64.to_le_bytes());
+    data.extend(Pubkey::default().as_ref());
+    // The epoch fee, no next epoch fee
+    fee(&mut data, &FeeRatio::default());
+    data.push(0);
+    // The preferred deposit and withdraw validators
+    option(&mut data, &None);
+    option(&mut data, &pool.preferred_withdraw_validator);
+    fee(&mut data, &pool.stake_deposit_fee);
+    fee(&mut data, &pool.stake_withdrawal_fee);
+    // No next stake withdrawal fee, the stake referral fee
+    data.push(0);
+    data.push(0);
+    option(&mut data, &pool.sol_deposit_authority);
+    fee(&mut data, &pool.sol_deposit_fee);
+    // The sol referral fee, sol withdraw authority and fees
+    data.push(0);
+    option(&mut data, &None);
+    fee(&mut data, &FeeRatio::default());
+    data.push(0);
+    // The last epoch pool token supply and total lamports
+    data.extend(pool.pool_token_supply.to_le_bytes());
+    data.extend(pool.total_lamports.to_le_bytes());
+    data
+}
+
+/// The account data of an anchor account, past its discriminator
+pub fn anchor_data<T: BorshSerialize>(account: &T) -> Vec<u8> {
+    let mut data = vec![0u8; 8];
+    data.extend(account.try_to_vec().unwrap());
+    data
+}
@@ -0,0 +1,187 @@
+use anyhow::Result;
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_sdk::{instruction::AccountMeta, pubkey, pubkey::Pubkey, stake, system_program, sysvar};
+
+pub const UNSTAKE_IT_PROGRAM_ID: Pubkey = pubkey!("unpXTU2Ndrc7WWNyEhQWe4udTzSibLPi25SXv2xbCHQ");
+pub const UNSTAKE_IT_POOL: Pubkey = pubkey!("FypPtwbY3FUfzJUtXHSyVRokVKG2jKtH29FmK4ebxRSd");
+
+/// The anchor discriminator prefixing the unstake.it accounts
+const DISCRIMINATOR_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct Rational {
+    pub num: u64,
+    pub denom: u64,
+}
+
+impl Rational {
+    pub fn to_f64(&self) -> f64 {
+        if self.denom == 0 {
+            return 0.0;
+        }
+        self.num as f64 / self.denom as f64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct UnstakePool {
+    pub fee_authority: Pubkey,
+    pub lp_mint: Pubkey,
+    pub incoming_stake: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct ProtocolFee {
+    pub destination: Pubkey,
+    pub authority: Pubkey,
+    pub fee_ratio: Rational,
+    pub referrer_fee_ratio: Rational,
+}
+
+/// The fee of an instant unstake, part of it goes to the protocol
+#[derive(Debug, Clone, PartialEq, BorshDeserialize, BorshSerialize)]
+pub enum UnstakeFee {
+    Flat {
+        ratio: Rational,
+    },
+    /// Grows linearly as the unstake drains the SOL reserves
+    LiquidityLinear {
+        max_liq_remaining: Rational,
+        zero_liq_remaining: Rational,
+    },
+}
+
+/// Deserialize an unstake.it account past its discriminator
+pub fn deserialize_account<T: BorshDeserialize>(data: &[u8]) -> Result<T> {
+    Ok(T::deserialize(
+        &mut data.get(DISCRIMINATOR_LEN..).unwrap_or_default(),
+    )?)
+}
+
+/// The unstake.it pool taking the stake withdrawn from a stake pool for SOL
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnstakeItState {
+    pub pool: Pubkey,
+    pub incoming_stake: u64,
+    pub sol_reserves_lamports: u64,
+    pub fee: UnstakeFee,
+    pub protocol_fee_destination: Pubkey,
+}
+
+impl UnstakeItState {
+    pub fn pool_sol_reserves(&self) -> Pubkey {
+        Pubkey::find_program_address(&[self.pool.as_ref()], &UNSTAKE_IT_PROGRAM_ID).0
+    }
+
+    pub fn fee_account(&self) -> Pubkey {
+        Pubkey::find_program_address(&[self.pool.as_ref(), b"fee"], &UNSTAKE_IT_PROGRAM_ID).0
+    }
+
+    pub fn protocol_fee_account() -> Pubkey {
+        Pubkey::find_program_address(&[b"protocol-fee"], &UNSTAKE_IT_PROGRAM_ID).0
+    }
+
+    pub fn stake_account_record(&self, stake_account: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(
+            &[self.pool.as_ref(), stake_account.as_ref()],
+            &UNSTAKE_IT_PROGRAM_ID,
+        )
+        .0
+    }
+
+    /// The SOL paid for a stake account of the lamports, none when the reserves fall short
+    pub fn unstake_out(&self, lamports: u64) -> Option<u64> {
+        if lamports > self.sol_reserves_lamports {
+            return None;
+        }
+        let fee_ratio = match &self.fee {
+            UnstakeFee::Flat { ratio } => ratio.to_f64(),
+            UnstakeFee::LiquidityLinear {
+                max_liq_remaining,
+                zero_liq_remaining,
+            } => {
+                let owned_lamports = self.sol_reserves_lamports + self.incoming_stake;
+                let liq_remaining =
+                    (self.sol_reserves_lamports - lamports) as f64 / owned_lamports as f64;
+                let (max_liq, zero_liq) = (max_liq_remaining.to_f64(), zero_liq_remaining.to_f64());
+                zero_liq - (zero_liq - max_liq) * liq_remaining
+            }
+        };
+        let fee = (lamports as f64 * fee_ratio).ceil() as u64;
+        Some(lamports.saturating_sub(fee))
+    }
+
+    /// The deposit stake accounts stakedex passes to unstake.it
+    pub fn deposit_stake_accounts(&self, stake_account: &Pubkey) -> Vec<AccountMeta> {
+        vec![
+            AccountMeta::new_readonly(UNSTAKE_IT_PROGRAM_ID, false),
+            AccountMeta::new(self.pool, false),
+            AccountMeta::new(self.pool_sol_reserves(), false),
+            AccountMeta::new_readonly(self.fee_account(), false),
+            AccountMeta::new(self.stake_account_record(stake_account), false),
+            AccountMeta::new_readonly(Self::protocol_fee_account(), false),
+            AccountMeta::new(self.protocol_fee_destination, false),
+            AccountMeta::new_readonly(sysvar::clock::ID, false),
+            AccountMeta::new_readonly(stake::program::ID, false),
+            AccountMeta::new_readonly(system_program::ID, false),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unstake_it(fee: UnstakeFee) -> UnstakeItState {
+        UnstakeItState {
+            pool: UNSTAKE_IT_POOL,
+            incoming_stake: 0,
+            sol_reserves_lamports: 1_000_000,
+            fee,
+            protocol_fee_destination: Pubkey::new_unique(),
+        }
+    }
+
+    #[test]
+    fn test_unstake_out_flat() {
+        let state = unstake_it(UnstakeFee::Flat {
+            ratio: Rational { num: 1, denom: 128 },
+        });
+        assert_eq!(state.unstake_out(100_000), Some(99_218));
+        assert_eq!(state.unstake_out(1_000_001), None);
+    }
+
+    #[test]
+    fn test_unstake_out_liquidity_linear() {
+        let state = unstake_it(UnstakeFee::LiquidityLinear {
+            max_liq_remaining: Rational {
+                num: 1,
+                denom: 1024,
+            },
+            zero_liq_remaining: Rational { num: 1, denom: 128 },
+        });
+        // Half the liquidity remains, the fee is halfway between both ratios
+        assert_eq!(state.unstake_out(500_000), Some(497_802));
+        // No liquidity remains
+        assert_eq!(state.unstake_out(1_000_000), Some(992_187));
+    }
+
+    #[test]
+    fn test_deserialize_account() {
+        let pool = UnstakePool {
+            fee_authority: Pubkey::new_unique(),
+            lp_mint: Pubkey::new_unique(),
+            incoming_stake: 42,
+        };
+        let mut data = vec![0u8; DISCRIMINATOR_LEN];
+        data.extend(pool.try_to_vec().unwrap());
+        assert_eq!(deserialize_account::<UnstakePool>(&data).unwrap(), pool);
+
+        let fee = UnstakeFee::Flat {
+            ratio: Rational { num: 1, denom: 100 },
+        };
+        let mut data = vec![0u8; DISCRIMINATOR_LEN];
+        data.extend(fee.try_to_vec().unwrap());
+        assert_eq!(deserialize_account::<UnstakeFee>(&data).unwrap(), fee);
+    }
+}
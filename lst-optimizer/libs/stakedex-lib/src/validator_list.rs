@@ -0,0 +1,256 @@
+use anyhow::Result;
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_sdk::pubkey::Pubkey;
+
+/// The rent exempt reserve and the minimum active stake a validator stake account keeps
+/// after a withdrawal
+pub const MIN_VALIDATOR_STAKE_LAMPORTS: u64 = 2_282_880 + 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, BorshDeserialize, BorshSerialize)]
+pub enum StakeStatus {
+    Active,
+    DeactivatingTransient,
+    ReadyForRemoval,
+    DeactivatingValidator,
+    DeactivatingAll,
+}
+
+/// A validator of an SPL stake pool, laid out as in its validator list account
+#[derive(Debug, Clone, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct ValidatorStakeInfo {
+    pub active_stake_lamports: u64,
+    pub transient_stake_lamports: u64,
+    pub last_update_epoch: u64,
+    pub transient_seed_suffix: u64,
+    pub unused: u32,
+    pub validator_seed_suffix: u32,
+    pub status: StakeStatus,
+    pub vote_account_address: Pubkey,
+}
+
+impl ValidatorStakeInfo {
+    /// The stake account the pool delegates to the validator
+    pub fn stake_account(&self, program_id: &Pubkey, stake_pool: &Pubkey) -> Pubkey {
+        let suffix = self.validator_seed_suffix.to_le_bytes();
+        let mut seeds: Vec<&[u8]> = vec![self.vote_account_address.as_ref(), stake_pool.as_ref()];
+        if self.validator_seed_suffix != 0 {
+            seeds.push(&suffix);
+        }
+        Pubkey::find_program_address(&seeds, program_id).0
+    }
+
+    /// The lamports that may be withdrawn from the validator stake account
+    pub fn withdrawable_lamports(&self) -> u64 {
+        if self.status != StakeStatus::Active {
+            return 0;
+        }
+        self.active_stake_lamports
+            .saturating_sub(MIN_VALIDATOR_STAKE_LAMPORTS)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct ValidatorList {
+    pub account_type: u8,
+    pub max_validators: u32,
+    pub validators: Vec<ValidatorStakeInfo>,
+}
+
+impl ValidatorList {
+    /// Deserialize the account data, ignoring the space left for validators to come
+    pub fn from_account_data(data: &[u8]) -> Result<Self> {
+        Ok(Self::deserialize(&mut &data[..])?)
+    }
+
+    pub fn find(&self, vote_account: &Pubkey) -> Option<&ValidatorStakeInfo> {
+        self.validators
+            .iter()
+            .find(|validator| validator.vote_account_address.eq(vote_account))
+    }
+
+    /// The preferred withdraw validator while it has stake to withdraw, the stake pool
+    /// rejects withdrawals from the other validators until it is drained
+    fn find_preferred(&self, preferred: Option<&Pubkey>) -> Option<&ValidatorStakeInfo> {
+        self.find(preferred?)
+            .filter(|validator| validator.withdrawable_lamports() > 0)
+    }
+
+    /// The validator whose stake covers the lamports, the preferred withdraw validator
+    /// while it has stake, otherwise the largest first
+    pub fn find_withdrawable(
+        &self,
+        lamports: u64,
+        preferred: Option<&Pubkey>,
+    ) -> Option<&ValidatorStakeInfo> {
+        if let Some(validator) = self.find_preferred(preferred) {
+            return Some(validator).filter(|v| v.withdrawable_lamports() >= lamports);
+        }
+        self.validators
+            .iter()
+            .filter(|validator| validator.withdrawable_lamports() >= lamports)
+            .max_by_key(|validator| validator.active_stake_lamports)
+    }
+
+    /// The validator with the most stake to withdraw, the preferred withdraw validator
+    /// while it has stake, none when no stake may be withdrawn
+    pub fn find_largest_withdrawable(
+        &self,
+        preferred: Option<&Pubkey>,
+    ) -> Option<&ValidatorStakeInfo> {
+        if let Some(validator) = self.find_preferred(preferred) {
+            return Some(validator);
+        }
+        self.validators
+            .iter()
+            .filter(|validator| validator.withdrawable_lamports() > 0)
+            .max_by_key(|validator| validator.withdrawable_lamports())
+    }
+
+    /// The validator of both pools whose stake in this pool covers the lamports, the
+    /// preferred withdraw validator while it has stake, otherwise the largest first.
+    /// Stake withdrawn from this pool is then accepted as a deposit into the other.
+    pub fn find_shared_validator(
+        &self,
+        other: &ValidatorList,
+        lamports: u64,
+        preferred: Option<&Pubkey>,
+    ) -> Option<&ValidatorStakeInfo> {
+        let is_shared = |validator: &&ValidatorStakeInfo| {
+            validator.withdrawable_lamports() >= lamports
+                && other
+                    .find(&validator.vote_account_address)
+                    .is_some_and(|other| other.status == StakeStatus::Active)
+        };
+        if let Some(validator) = self.find_preferred(preferred) {
+            return Some(validator).filter(is_shared);
+        }
+        self.validators
+            .iter()
+            .filter(is_shared)
+            .max_by_key(|validator| validator.active_stake_lamports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validator(vote: Pubkey, active_stake_lamports: u64) -> ValidatorStakeInfo {
+        ValidatorStakeInfo {
+            active_stake_lamports,
+            transient_stake_lamports: 0,
+            last_update_epoch: 700,
+            transient_seed_suffix: 0,
+            unused: 0,
+            validator_seed_suffix: 0,
+            status: StakeStatus::Active,
+            vote_account_address: vote,
+        }
+    }
+
+    fn validator_list(validators: Vec<ValidatorStakeInfo>) -> ValidatorList {
+        ValidatorList {
+            account_type: 2,
+            max_validators: 10,
+            validators,
+        }
+    }
+
+    #[test]
+    fn test_from_account_data() {
+        let list = validator_list(vec![validator(Pubkey::new_unique(), 5_000_000_000)]);
+        let mut data = list.try_to_vec().unwrap();
+        assert_eq!(data.len(), 1 + 4 + 4 + 73);
+        // The account is allocated for the max validators
+        data.resize(1 + 4 + 4 + 73 * 10, 0);
+        assert_eq!(ValidatorList::from_account_data(&data).unwrap(), list);
+    }
+
+    #[test]
+    fn test_find_shared_validator() {
+        let (shared, larger, other) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let src = validator_list(vec![
+            validator(shared, 5_000_000_000),
+            validator(larger, 9_000_000_000),
+            validator(other, 20_000_000_000),
+        ]);
+        let mut dst = validator_list(vec![
+            validator(shared, 1_000_000_000),
+            validator(larger, 1_000_000_000),
+        ]);
+
+        let found = src
+            .find_shared_validator(&dst, 4_000_000_000, None)
+            .unwrap();
+        assert_eq!(found.vote_account_address, larger);
+        assert!(src
+            .find_shared_validator(&dst, 9_000_000_000, None)
+            .is_none());
+
+        dst.validators[1].status = StakeStatus::DeactivatingValidator;
+        let found = src
+            .find_shared_validator(&dst, 4_000_000_000, None)
+            .unwrap();
+        assert_eq!(found.vote_account_address, shared);
+
+        let found = src.find_withdrawable(4_000_000_000, None).unwrap();
+        assert_eq!(found.vote_account_address, other);
+        assert!(src.find_withdrawable(30_000_000_000, None).is_none());
+        let found = src.find_largest_withdrawable(None).unwrap();
+        assert_eq!(found.vote_account_address, other);
+    }
+
+    #[test]
+    fn test_find_preferred_validator() {
+        let (preferred, larger) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut src = validator_list(vec![
+            validator(preferred, 5_000_000_000),
+            validator(larger, 9_000_000_000),
+        ]);
+        let dst = validator_list(vec![validator(larger, 1_000_000_000)]);
+
+        let found = src
+            .find_withdrawable(1_000_000_000, Some(&preferred))
+            .unwrap();
+        assert_eq!(found.vote_account_address, preferred);
+        let found = src.find_largest_withdrawable(Some(&preferred)).unwrap();
+        assert_eq!(found.vote_account_address, preferred);
+        // The pool rejects the withdrawal from the others while the preferred has stake
+        assert!(src
+            .find_withdrawable(6_000_000_000, Some(&preferred))
+            .is_none());
+        assert!(src
+            .find_shared_validator(&dst, 1_000_000_000, Some(&preferred))
+            .is_none());
+
+        // A drained preferred validator no longer holds the withdrawals
+        src.validators[0].active_stake_lamports = MIN_VALIDATOR_STAKE_LAMPORTS;
+        let found = src
+            .find_withdrawable(6_000_000_000, Some(&preferred))
+            .unwrap();
+        assert_eq!(found.vote_account_address, larger);
+        let found = src
+            .find_shared_validator(&dst, 1_000_000_000, Some(&preferred))
+            .unwrap();
+        assert_eq!(found.vote_account_address, larger);
+    }
+
+    #[test]
+    fn test_stake_account() {
+        let (program_id, stake_pool) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut info = validator(Pubkey::new_unique(), 0);
+        let expected = Pubkey::find_program_address(
+            &[info.vote_account_address.as_ref(), stake_pool.as_ref()],
+            &program_id,
+        )
+        .0;
+        assert_eq!(info.stake_account(&program_id, &stake_pool), expected);
+
+        info.validator_seed_suffix = 3;
+        assert_ne!(info.stake_account(&program_id, &stake_pool), expected);
+    }
+}
@@ -3,9 +3,9 @@ use log::debug;
 use lst_optimizer_std::{
     allocator::{AllocationRatio, AllocationRatios, Allocator},
     fetcher::apy::Apy,
-    types::{datapoint::SymbolData, pool_allocation::MAX_ALLOCATION_BPS},
+    types::{datapoint::SymbolData, pool_allocation::PoolAllocations},
 };
-use rust_decimal::Decimal;
+use rust_decimal::{prelude::FromPrimitive, Decimal};
 use ta::{indicators::ExponentialMovingAverage, Next};
 use thiserror::Error;
 
@@ -22,12 +22,32 @@ pub enum EmaError {
 
     #[error("Failed to divide max allocation bps")]
     FailedToDivideMaxAllocationBps,
+
+    #[error("Softmax temperature must be greater than zero, got {0}")]
+    InvalidTemperature(f64),
+
+    #[error("Failed to convert EMA score {0} to decimal")]
+    FailedToConvertScore(f64),
+}
+
+/// How the bps are split between the selected top LSTs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmaAllocationMode {
+    /// Split the bps equally
+    Equal,
+    /// Split the bps proportionally to the EMA APY in excess of the baseline (0 if not set),
+    /// LSTs at or below the baseline receive nothing
+    Proportional { baseline: Option<f64> },
+    /// Split the bps by the softmax of the EMA APYs, lower temperatures favor the best LSTs
+    Softmax { temperature: f64 },
 }
 
 #[derive(Debug, Clone)]
 pub struct EmaAllocator {
     allocation_limit: Option<usize>,
     period: Option<usize>,
+    mode: EmaAllocationMode,
+    hysteresis_margin: Option<f64>,
 }
 
 impl EmaAllocator {
@@ -35,6 +55,21 @@ impl EmaAllocator {
         Self {
             allocation_limit,
             period,
+            mode: EmaAllocationMode::Equal,
+            hysteresis_margin: None,
+        }
+    }
+
+    pub fn with_mode(self, mode: EmaAllocationMode) -> Self {
+        Self { mode, ..self }
+    }
+
+    /// Only replace an incumbent LST (currently held by the pool) by a challenger
+    /// whose EMA APY beats the incumbent's by more than the margin
+    pub fn with_hysteresis_margin(self, hysteresis_margin: Option<f64>) -> Self {
+        Self {
+            hysteresis_margin,
+            ..self
         }
     }
 
@@ -59,27 +94,41 @@ impl EmaAllocator {
         emas.into_iter().take(limit).collect()
     }
 
-    fn allocate_equal(&self, emas: Vec<Ema>) -> Result<Vec<AllocationRatio>> {
-        let mut ratios: Vec<AllocationRatio> = vec![];
-        let number_of_symbol = Decimal::from(emas.len());
-        let max_allocation_bps = Decimal::from(MAX_ALLOCATION_BPS);
-        let bps_per_symbol = max_allocation_bps
-            .checked_div(number_of_symbol)
-            .context(EmaError::FailedToDivideMaxAllocationBps)?;
+    /// Select the top emas, keeping the incumbents that fell out of the top
+    /// unless the weakest challenger beats them by the margin
+    fn select_emas_with_hysteresis(
+        &self,
+        sorted_emas: Vec<Ema>,
+        incumbents: &[String],
+        limit: usize,
+        margin: f64,
+    ) -> Vec<Ema> {
+        let mut selected = sorted_emas;
+        let rest = selected.split_off(limit.min(selected.len()));
+        let dropped_incumbents = rest
+            .into_iter()
+            .filter(|ema| incumbents.contains(&ema.mint));
 
-        for ema in emas {
-            let ratio = AllocationRatio {
-                bps: bps_per_symbol,
-                mint: ema.mint,
-            };
-            ratios.push(ratio);
+        // Best dropped incumbents against the weakest challengers first
+        for incumbent in dropped_incumbents {
+            let weakest_challenger = selected
+                .iter()
+                .rposition(|ema| !incumbents.contains(&ema.mint));
+            match weakest_challenger {
+                Some(pos) if selected[pos].ema <= incumbent.ema + margin => {
+                    debug!(
+                        "Keep incumbent {} ({}) over challenger {} ({})",
+                        incumbent.mint, incumbent.ema, selected[pos].mint, selected[pos].ema
+                    );
+                    selected[pos] = incumbent;
+                }
+                _ => break,
+            }
         }
-        Ok(ratios)
+        self.sort_emas_desc(selected)
     }
-}
 
-impl Allocator<Apy> for EmaAllocator {
-    fn allocate(&self, symbol_datas: Vec<SymbolData<Apy>>) -> Result<AllocationRatios> {
+    fn calculate_latest_emas(&self, symbol_datas: Vec<SymbolData<Apy>>) -> Result<Vec<Ema>> {
         let mut latest_emas: Vec<Ema> = Vec::new();
         for symbol_data in symbol_datas {
             let datapoints = &symbol_data.datapoints;
@@ -89,18 +138,115 @@ impl Allocator<Apy> for EmaAllocator {
                 ema: emas.last().unwrap().to_owned(),
             });
         }
+        Ok(latest_emas)
+    }
+
+    fn allocate_emas(&self, emas: Vec<Ema>) -> Result<AllocationRatios> {
+        let ratios = match self.mode {
+            EmaAllocationMode::Equal => self.allocate_equal(emas)?,
+            EmaAllocationMode::Proportional { baseline } => {
+                self.allocate_proportional(emas, baseline)?
+            }
+            EmaAllocationMode::Softmax { temperature } => {
+                self.allocate_softmax(emas, temperature)?
+            }
+        };
+        Ok(AllocationRatios::new(ratios))
+    }
+
+    fn allocate_equal(&self, emas: Vec<Ema>) -> Result<Vec<AllocationRatio>> {
+        let ratios = AllocationRatios::from_weights(
+            emas.into_iter()
+                .map(|ema| (ema.mint, Decimal::ONE))
+                .collect(),
+        )
+        .context(EmaError::FailedToDivideMaxAllocationBps)?;
+        Ok(ratios.asset_alloc_ratios)
+    }
+
+    fn allocate_proportional(
+        &self,
+        emas: Vec<Ema>,
+        baseline: Option<f64>,
+    ) -> Result<Vec<AllocationRatio>> {
+        let baseline = baseline.unwrap_or(0.0);
+        let scores: Vec<f64> = emas
+            .iter()
+            .map(|ema| (ema.ema - baseline).max(0.0))
+            .collect();
+        // Nothing beats the baseline, there is no preference between the LSTs
+        if scores.iter().all(|score| *score <= 0.0) {
+            return self.allocate_equal(emas);
+        }
+        self.allocate_by_scores(emas, scores)
+    }
+
+    fn allocate_softmax(&self, emas: Vec<Ema>, temperature: f64) -> Result<Vec<AllocationRatio>> {
+        if temperature.is_nan() || temperature <= 0.0 {
+            return Err(EmaError::InvalidTemperature(temperature).into());
+        }
+        // Shift by the max EMA for numerical stability, softmax is shift invariant
+        let max_ema = emas
+            .iter()
+            .map(|ema| ema.ema)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let scores: Vec<f64> = emas
+            .iter()
+            .map(|ema| ((ema.ema - max_ema) / temperature).exp())
+            .collect();
+        self.allocate_by_scores(emas, scores)
+    }
+
+    fn allocate_by_scores(&self, emas: Vec<Ema>, scores: Vec<f64>) -> Result<Vec<AllocationRatio>> {
+        let mut weights: Vec<(String, Decimal)> = vec![];
+        for (ema, score) in emas.into_iter().zip(scores) {
+            let weight = Decimal::from_f64(score).ok_or(EmaError::FailedToConvertScore(score))?;
+            weights.push((ema.mint, weight));
+        }
+        let ratios = AllocationRatios::from_weights(weights)
+            .context(EmaError::FailedToDivideMaxAllocationBps)?;
+        Ok(ratios.asset_alloc_ratios)
+    }
+}
+
+impl Allocator<Apy> for EmaAllocator {
+    fn allocate(&self, symbol_datas: Vec<SymbolData<Apy>>) -> Result<AllocationRatios> {
+        let latest_emas = self.calculate_latest_emas(symbol_datas)?;
         let mut emas = self.sort_emas_desc(latest_emas);
         debug!("Sorted EMAs: {:?}", emas);
         if self.allocation_limit.is_some() {
             emas = self.truncate_emas(emas, self.allocation_limit.unwrap());
         }
-        let ratios = self.allocate_equal(emas)?;
-        Ok(AllocationRatios::new(ratios))
+        self.allocate_emas(emas)
+    }
+
+    fn allocate_with_current(
+        &self,
+        symbol_datas: Vec<SymbolData<Apy>>,
+        current_allocations: &PoolAllocations,
+    ) -> Result<AllocationRatios> {
+        let (Some(limit), Some(margin)) = (self.allocation_limit, self.hysteresis_margin) else {
+            return self.allocate(symbol_datas);
+        };
+
+        let incumbents: Vec<String> = current_allocations
+            .assets
+            .iter()
+            .filter(|asset| asset.lamports > 0)
+            .map(|asset| asset.mint.clone())
+            .collect();
+        let latest_emas = self.calculate_latest_emas(symbol_datas)?;
+        let emas = self.sort_emas_desc(latest_emas);
+        debug!("Sorted EMAs: {:?}, incumbents: {:?}", emas, incumbents);
+        let emas = self.select_emas_with_hysteresis(emas, &incumbents, limit, margin);
+        self.allocate_emas(emas)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use lst_optimizer_std::types::pool_asset::PoolAsset;
+
     use super::*;
 
     fn test_datapoints_asc() -> Vec<Apy> {
@@ -268,6 +414,195 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_allocate_equal_success_on_repeating_decimals() {
+        let emas: Vec<Ema> = test_emas().into_iter().take(3).collect();
+        let ratios = EmaAllocator::new(None, None).allocate_equal(emas).unwrap();
+        let bps: Vec<Decimal> = ratios.iter().map(|ratio| ratio.bps).collect();
+        assert_eq!(
+            bps,
+            vec![
+                Decimal::from(3334),
+                Decimal::from(3333),
+                Decimal::from(3333)
+            ]
+        );
+        assert!(AllocationRatios::new(ratios).validate().is_ok());
+    }
+
+    fn test_mint_emas(emas: &[(&str, f64)]) -> Vec<Ema> {
+        emas.iter()
+            .map(|(mint, ema)| Ema {
+                mint: mint.to_string(),
+                ema: *ema,
+            })
+            .collect()
+    }
+
+    fn bps_of(ratios: &[AllocationRatio]) -> Vec<Decimal> {
+        ratios.iter().map(|ratio| ratio.bps).collect()
+    }
+
+    #[test]
+    fn test_allocate_proportional_success() {
+        let emas = test_mint_emas(&[("jupsol", 9.0), ("inf", 7.1)]);
+        let ratios = EmaAllocator::new(None, None)
+            .allocate_proportional(emas, None)
+            .unwrap();
+        // 9.0 / 16.1 and 7.1 / 16.1
+        assert_eq!(
+            bps_of(&ratios),
+            vec![Decimal::from(5590), Decimal::from(4410)]
+        );
+    }
+
+    #[test]
+    fn test_allocate_proportional_excess_over_baseline() {
+        let emas = test_mint_emas(&[("jupsol", 9.0), ("inf", 7.0), ("msol", 6.0)]);
+        let ratios = EmaAllocator::new(None, None)
+            .allocate_proportional(emas, Some(6.0))
+            .unwrap();
+        assert_eq!(
+            bps_of(&ratios),
+            vec![Decimal::from(7500), Decimal::from(2500), Decimal::from(0)]
+        );
+    }
+
+    #[test]
+    fn test_allocate_proportional_equal_when_nothing_beats_baseline() {
+        let emas = test_mint_emas(&[("jupsol", 5.0), ("inf", 4.0)]);
+        let ratios = EmaAllocator::new(None, None)
+            .allocate_proportional(emas, Some(6.0))
+            .unwrap();
+        assert_eq!(
+            bps_of(&ratios),
+            vec![Decimal::from(5000), Decimal::from(5000)]
+        );
+    }
+
+    #[test]
+    fn test_allocate_softmax_success() {
+        let emas = test_mint_emas(&[("jupsol", 9.0), ("inf", 7.1), ("msol", 7.1)]);
+        let ratios = EmaAllocator::new(None, None)
+            .allocate_softmax(emas.clone(), 1.0)
+            .unwrap();
+        assert!(AllocationRatios::new(ratios.clone()).validate().is_ok());
+        assert!(ratios[0].bps > ratios[1].bps);
+        assert!(ratios[1].bps - ratios[2].bps <= Decimal::ONE);
+
+        // Lower temperature concentrates the allocation on the best LST
+        let sharper = EmaAllocator::new(None, None)
+            .allocate_softmax(emas, 0.1)
+            .unwrap();
+        assert!(sharper[0].bps > ratios[0].bps);
+    }
+
+    #[test]
+    fn test_allocate_softmax_success_on_fractional_apys() {
+        let emas = test_mint_emas(&[("jupsol", 0.08), ("inf", 0.07), ("msol", 0.065)]);
+        let ratios = EmaAllocator::new(None, None)
+            .allocate_softmax(emas.clone(), 0.01)
+            .unwrap();
+        assert!(AllocationRatios::new(ratios.clone()).validate().is_ok());
+        assert!(ratios[0].bps > Decimal::from(6000));
+        assert!(ratios[2].bps > Decimal::from(1000));
+
+        // A temperature far above the APY spread allocates almost equally
+        let flat = EmaAllocator::new(None, None)
+            .allocate_softmax(emas, 1.0)
+            .unwrap();
+        assert!(flat
+            .iter()
+            .all(|ratio| ratio.bps > Decimal::from(3300) && ratio.bps < Decimal::from(3400)));
+    }
+
+    #[test]
+    fn test_allocate_softmax_fail_on_invalid_temperature() {
+        let emas = test_mint_emas(&[("jupsol", 9.0)]);
+        let ratios = EmaAllocator::new(None, None).allocate_softmax(emas, 0.0);
+        assert_eq!(
+            ratios.err().unwrap().to_string(),
+            EmaError::InvalidTemperature(0.0).to_string()
+        );
+    }
+
+    #[test]
+    fn test_allocate_with_mode_respects_allocation_limit() {
+        let symbol_datas = [("jupsol", 9.0), ("inf", 7.0), ("msol", 6.0)]
+            .iter()
+            .map(|(mint, apy)| SymbolData {
+                mint: mint.to_string(),
+                symbol: mint.to_string(),
+                datapoints: vec![Apy {
+                    mint: mint.to_string(),
+                    apy: *apy,
+                }],
+            })
+            .collect();
+        let allocations = EmaAllocator::new(Some(2), Some(5))
+            .with_mode(EmaAllocationMode::Proportional { baseline: None })
+            .allocate(symbol_datas)
+            .unwrap();
+        assert_eq!(
+            allocations.asset_alloc_ratios,
+            vec![
+                AllocationRatio::new("jupsol", 5625),
+                AllocationRatio::new("inf", 4375),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_select_emas_with_hysteresis_keeps_incumbent_within_margin() {
+        let emas = test_mint_emas(&[("jupsol", 7.05), ("inf", 7.0), ("msol", 6.0)]);
+        let incumbents = vec!["inf".to_string(), "msol".to_string()];
+        let selected =
+            EmaAllocator::new(None, None).select_emas_with_hysteresis(emas, &incumbents, 2, 0.1);
+        assert_eq!(selected, test_mint_emas(&[("inf", 7.0), ("msol", 6.0)]));
+    }
+
+    #[test]
+    fn test_select_emas_with_hysteresis_replaces_incumbent_beyond_margin() {
+        let emas = test_mint_emas(&[("jupsol", 9.0), ("inf", 7.0), ("msol", 6.95)]);
+        let incumbents = vec!["inf".to_string(), "msol".to_string()];
+        let selected =
+            EmaAllocator::new(None, None).select_emas_with_hysteresis(emas, &incumbents, 2, 0.1);
+        assert_eq!(selected, test_mint_emas(&[("jupsol", 9.0), ("inf", 7.0)]));
+    }
+
+    #[test]
+    fn test_allocate_with_current_hysteresis() {
+        let symbol_datas = [("jupsol", 7.05), ("inf", 7.0), ("msol", 6.0)]
+            .iter()
+            .map(|(mint, apy)| SymbolData {
+                mint: mint.to_string(),
+                symbol: mint.to_string(),
+                datapoints: vec![Apy {
+                    mint: mint.to_string(),
+                    apy: *apy,
+                }],
+            })
+            .collect::<Vec<SymbolData<Apy>>>();
+        let current_allocations = PoolAllocations {
+            assets: vec![
+                PoolAsset::new("jupsol", 0, 0),
+                PoolAsset::new("inf", 500, 0),
+                PoolAsset::new("msol", 500, 0),
+            ],
+        };
+        let allocations = EmaAllocator::new(Some(2), Some(5))
+            .with_hysteresis_margin(Some(0.1))
+            .allocate_with_current(symbol_datas, &current_allocations)
+            .unwrap();
+        assert_eq!(
+            allocations.asset_alloc_ratios,
+            vec![
+                AllocationRatio::new("inf", 5000),
+                AllocationRatio::new("msol", 5000),
+            ]
+        );
+    }
+
     #[test]
     fn test_allocate_equal_fail_on_empty() {
         let emas = Vec::<Ema>::new();
@@ -0,0 +1,73 @@
+use anyhow::{Context, Result};
+use lst_optimizer_std::{
+    allocator::{AllocationRatios, Allocator},
+    fetcher::apy::Apy,
+    types::datapoint::SymbolData,
+};
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum EqualError {
+    #[error("Failed to divide max allocation bps")]
+    FailedToDivideMaxAllocationBps,
+}
+
+/// Equally allocate to every symbol regardless of its datapoints
+#[derive(Debug, Clone, Default)]
+pub struct EqualAllocator {}
+
+impl EqualAllocator {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Allocator<Apy> for EqualAllocator {
+    fn allocate(&self, symbol_datas: Vec<SymbolData<Apy>>) -> Result<AllocationRatios> {
+        AllocationRatios::from_weights(
+            symbol_datas
+                .into_iter()
+                .map(|symbol_data| (symbol_data.mint, Decimal::ONE))
+                .collect(),
+        )
+        .context(EqualError::FailedToDivideMaxAllocationBps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lst_optimizer_std::allocator::AllocationRatio;
+
+    use crate::allocator::test_utils::test_symbol_data;
+
+    use super::*;
+
+    #[test]
+    fn test_allocate_equal_all() {
+        let allocations = EqualAllocator::new()
+            .allocate(vec![
+                test_symbol_data("jupsol", &[]),
+                test_symbol_data("inf", &[]),
+                test_symbol_data("jitosol", &[]),
+            ])
+            .unwrap();
+        assert_eq!(
+            allocations.asset_alloc_ratios,
+            vec![
+                AllocationRatio::new("jupsol", 3334),
+                AllocationRatio::new("inf", 3333),
+                AllocationRatio::new("jitosol", 3333),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_allocate_fail_on_empty() {
+        let allocations = EqualAllocator::new().allocate(vec![]);
+        assert_eq!(
+            allocations.err().unwrap().to_string(),
+            EqualError::FailedToDivideMaxAllocationBps.to_string()
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use anyhow::{Context, Result};
+use log::debug;
+use lst_optimizer_std::{
+    allocator::{AllocationRatio, AllocationRatios, Allocator},
+    fetcher::apy::Apy,
+    types::datapoint::SymbolData,
+};
+use rust_decimal::Decimal;
+use ta::{indicators::SimpleMovingAverage, Next};
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ma {
+    pub mint: String,
+    pub ma: f64,
+}
+
+#[derive(Debug, Error)]
+pub enum MaError {
+    #[error("Failed to calculate MA")]
+    FailedToCalculateMa,
+
+    #[error("Failed to divide max allocation bps")]
+    FailedToDivideMaxAllocationBps,
+}
+
+/// Equally allocate to the top LSTs ranked by the simple moving average APY,
+/// symbols with fewer datapoints than the period are skipped
+#[derive(Debug, Clone)]
+pub struct MaAllocator {
+    allocation_limit: Option<usize>,
+    period: Option<usize>,
+}
+
+impl MaAllocator {
+    pub fn new(allocation_limit: Option<usize>, period: Option<usize>) -> Self {
+        Self {
+            allocation_limit,
+            period,
+        }
+    }
+
+    /// The moving averages of the full windows, the first period - 1 datapoints only
+    /// give partial means so they have none
+    fn calculate_mas(&self, datapoints: &Vec<Apy>, period: usize) -> Result<Vec<f64>> {
+        let mut ma = SimpleMovingAverage::new(period).context(MaError::FailedToCalculateMa)?;
+        let mut mas: Vec<f64> = Vec::new();
+        for (index, datapoint) in datapoints.iter().enumerate() {
+            let value = ma.next(datapoint.apy);
+            if index + 1 >= period {
+                mas.push(value);
+            }
+        }
+        Ok(mas)
+    }
+
+    fn sort_mas_desc(&self, mas: Vec<Ma>) -> Vec<Ma> {
+        let mut mas = mas;
+        mas.sort_by(|a, b| b.ma.partial_cmp(&a.ma).unwrap());
+        mas
+    }
+
+    fn truncate_mas(&self, mas: Vec<Ma>, limit: usize) -> Vec<Ma> {
+        mas.into_iter().take(limit).collect()
+    }
+
+    fn allocate_equal(&self, mas: Vec<Ma>) -> Result<Vec<AllocationRatio>> {
+        let ratios = AllocationRatios::from_weights(
+            mas.into_iter().map(|ma| (ma.mint, Decimal::ONE)).collect(),
+        )
+        .context(MaError::FailedToDivideMaxAllocationBps)?;
+        Ok(ratios.asset_alloc_ratios)
+    }
+}
+
+impl Allocator<Apy> for MaAllocator {
+    fn allocate(&self, symbol_datas: Vec<SymbolData<Apy>>) -> Result<AllocationRatios> {
+        let mut latest_mas: Vec<Ma> = Vec::new();
+        for symbol_data in symbol_datas {
+            let datapoints = &symbol_data.datapoints;
+            let mas = self.calculate_mas(datapoints, self.period.unwrap_or(5))?;
+            if let Some(ma) = mas.last() {
+                latest_mas.push(Ma {
+                    mint: symbol_data.mint.clone(),
+                    ma: ma.to_owned(),
+                });
+            }
+        }
+        let mut mas = self.sort_mas_desc(latest_mas);
+        debug!("Sorted MAs: {:?}", mas);
+        if let Some(limit) = self.allocation_limit {
+            mas = self.truncate_mas(mas, limit);
+        }
+        let ratios = self.allocate_equal(mas)?;
+        Ok(AllocationRatios::new(ratios))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::test_utils::test_symbol_data;
+
+    use super::*;
+
+    fn test_datapoints_asc() -> Vec<Apy> {
+        (1..=5)
+            .map(|apy| Apy {
+                mint: "".to_string(),
+                apy: apy as f64,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_calculate_mas_success() {
+        let datapoints = test_datapoints_asc();
+        let ma_values = MaAllocator::new(None, None).calculate_mas(&datapoints, 2);
+        assert_eq!(ma_values.unwrap(), vec![1.5, 2.5, 3.5, 4.5]);
+    }
+
+    #[test]
+    fn test_calculate_mas_success_shorter_than_period() {
+        let datapoints = test_datapoints_asc();
+        let ma_values = MaAllocator::new(None, None).calculate_mas(&datapoints, 6);
+        assert!(ma_values.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_calculate_mas_fail_by_zero_period() {
+        let datapoints = test_datapoints_asc();
+        let ma_values = MaAllocator::new(None, None).calculate_mas(&datapoints, 0);
+        assert_eq!(
+            ma_values.err().unwrap().to_string(),
+            MaError::FailedToCalculateMa.to_string()
+        );
+    }
+
+    #[test]
+    fn test_allocate_top_n_skipping_empty_series() {
+        let symbol_datas = vec![
+            test_symbol_data("low", &[1.0, 1.0, 1.0]),
+            test_symbol_data("empty", &[]),
+            test_symbol_data("high", &[9.0, 9.0, 9.0]),
+            test_symbol_data("mid", &[1.0, 5.0, 9.0]),
+        ];
+        let allocations = MaAllocator::new(Some(2), Some(2))
+            .allocate(symbol_datas)
+            .unwrap();
+        assert_eq!(
+            allocations.asset_alloc_ratios,
+            vec![
+                AllocationRatio::new("high", 5000),
+                AllocationRatio::new("mid", 5000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_allocate_skipping_series_shorter_than_period() {
+        // A partial mean of the single datapoint would rank "short" first
+        let symbol_datas = vec![
+            test_symbol_data("short", &[9.0]),
+            test_symbol_data("low", &[1.0, 2.0, 3.0]),
+            test_symbol_data("high", &[4.0, 5.0, 6.0]),
+        ];
+        let allocations = MaAllocator::new(Some(1), Some(3))
+            .allocate(symbol_datas)
+            .unwrap();
+        assert_eq!(
+            allocations.asset_alloc_ratios,
+            vec![AllocationRatio::new("high", 10000)]
+        );
+    }
+
+    #[test]
+    fn test_allocate_fail_on_empty() {
+        let allocations = MaAllocator::new(None, None).allocate(vec![]);
+        assert_eq!(
+            allocations.err().unwrap().to_string(),
+            MaError::FailedToDivideMaxAllocationBps.to_string()
+        );
+    }
+}
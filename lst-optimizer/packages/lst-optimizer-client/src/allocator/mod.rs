@@ -1 +1,23 @@
 pub mod ema;
+pub mod equal;
+pub mod ma;
+pub mod single;
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use lst_optimizer_std::{fetcher::apy::Apy, types::datapoint::SymbolData};
+
+    pub fn test_symbol_data(mint: &str, apys: &[f64]) -> SymbolData<Apy> {
+        SymbolData {
+            mint: mint.to_string(),
+            symbol: mint.to_string(),
+            datapoints: apys
+                .iter()
+                .map(|apy| Apy {
+                    mint: mint.to_string(),
+                    apy: *apy,
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::Result;
+use lst_optimizer_std::{
+    allocator::{is_same_mint, AllocationRatio, AllocationRatios, Allocator},
+    fetcher::apy::Apy,
+    types::{datapoint::SymbolData, pool_allocation::MAX_ALLOCATION_BPS},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SingleError {
+    #[error("Mint {0} not found in the symbol datas")]
+    MintNotFound(String),
+
+    #[error("No symbol with datapoints to allocate")]
+    NoSymbolToAllocate,
+}
+
+/// Allocate the whole pool to a single LST, either the configured mint
+/// or the LST with the highest latest APY, symbols without datapoints are skipped.
+///
+/// Unlike the backtesting allocator, which requires the symbol, the fallback to the best
+/// latest APY is intentional so the allocator runs without a configured mint.
+#[derive(Debug, Clone)]
+pub struct SingleAllocator {
+    mint: Option<String>,
+}
+
+impl SingleAllocator {
+    pub fn new(mint: Option<String>) -> Self {
+        Self { mint }
+    }
+
+    fn select_mint(&self, symbol_datas: &Vec<SymbolData<Apy>>) -> Result<String> {
+        if let Some(mint) = &self.mint {
+            return symbol_datas
+                .iter()
+                .find(|s| is_same_mint(&s.mint, mint))
+                .map(|s| s.mint.clone())
+                .ok_or_else(|| SingleError::MintNotFound(mint.clone()).into());
+        }
+
+        let mut best: Option<(&String, f64)> = None;
+        for symbol_data in symbol_datas {
+            if let Some(latest) = symbol_data.datapoints.last() {
+                if best.is_none() || latest.apy > best.unwrap().1 {
+                    best = Some((&symbol_data.mint, latest.apy));
+                }
+            }
+        }
+        match best {
+            Some((mint, _)) => Ok(mint.clone()),
+            None => Err(SingleError::NoSymbolToAllocate.into()),
+        }
+    }
+}
+
+impl Allocator<Apy> for SingleAllocator {
+    fn allocate(&self, symbol_datas: Vec<SymbolData<Apy>>) -> Result<AllocationRatios> {
+        let mint = self.select_mint(&symbol_datas)?;
+        Ok(AllocationRatios::new(vec![AllocationRatio::new(
+            &mint,
+            MAX_ALLOCATION_BPS,
+        )]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::test_utils::test_symbol_data;
+
+    use super::*;
+
+    fn test_symbol_datas() -> Vec<SymbolData<Apy>> {
+        vec![
+            test_symbol_data("jupsol", &[9.0, 7.0]),
+            test_symbol_data("empty", &[]),
+            test_symbol_data("inf", &[6.0, 8.0]),
+        ]
+    }
+
+    #[test]
+    fn test_allocate_configured_mint() {
+        let allocations = SingleAllocator::new(Some("jupsol".to_string()))
+            .allocate(test_symbol_datas())
+            .unwrap();
+        assert_eq!(
+            allocations.asset_alloc_ratios,
+            vec![AllocationRatio::new("jupsol", MAX_ALLOCATION_BPS)]
+        );
+    }
+
+    #[test]
+    fn test_allocate_configured_mint_case() {
+        let allocations = SingleAllocator::new(Some("JUPSOL".to_string()))
+            .allocate(test_symbol_datas())
+            .unwrap();
+        assert_eq!(
+            allocations.asset_alloc_ratios,
+            vec![AllocationRatio::new("jupsol", MAX_ALLOCATION_BPS)]
+        );
+    }
+
+    #[test]
+    fn test_allocate_best_latest_apy() {
+        let allocations = SingleAllocator::new(None)
+            .allocate(test_symbol_datas())
+            .unwrap();
+        assert_eq!(
+            allocations.asset_alloc_ratios,
+            vec![AllocationRatio::new("inf", MAX_ALLOCATION_BPS)]
+        );
+    }
+
+    #[test]
+    fn test_allocate_fail_on_unknown_mint() {
+        let allocations =
+            SingleAllocator::new(Some("sol".to_string())).allocate(test_symbol_datas());
+        assert_eq!(
+            allocations.err().unwrap().to_string(),
+            SingleError::MintNotFound("sol".to_string()).to_string()
+        );
+    }
+}
@@ -1,35 +1,80 @@
 use core::time;
+use std::collections::HashMap;
 
 use anyhow::Result;
 
 use log::{error, info};
 use lst_optimizer_std::{
-    allocator::{AllocationRatios, Allocator},
-    fetcher::fetcher::Fetcher,
+    allocator::{
+        processor::{default_processors, AllocationProcessor},
+        AllocationRatios, Allocator,
+    },
+    fetcher::{apy::Apy, fetcher::Fetcher},
     pool::{PoolAllocable, PoolRebalancable},
     types::{
-        amount_change::AmountChange,
         context::Context,
         datapoint::SymbolData,
-        pool_allocation_changes::{PoolAllocationChanges, PoolAssetChange},
+        netted_change::{NettedChange, NettedChanges},
+        pool_allocation::PoolAllocations,
+        pool_allocation_changes::PoolAllocationChanges,
+        rebalance_outcome::{RebalanceCycleReport, RebalanceFailure, RebalanceOutcome},
+        rebalance_plan::{RebalancePlan, RebalancePlanAsset, RebalancePlanSwap, SimulationResult},
     },
 };
+use solana_sdk::{address_lookup_table::AddressLookupTableAccount, instruction::Instruction};
 
 use crate::{
-    allocator::ema::EmaAllocator, fetcher::apy::SanctumHistoricalApyFetcher, pool::pool::MaxPool,
+    allocator::ema::EmaAllocator,
+    fetcher::apy::SanctumHistoricalApyFetcher,
+    pool::{
+        helper::{journal::StepStatus, pool_asset_change_route::NettedChangeRouter},
+        pool::MaxPool,
+    },
 };
 
 pub struct OptimizerApp {
     pool: MaxPool,
+    fetcher: Box<dyn Fetcher<Apy>>,
+    allocator: Box<dyn Allocator<Apy>>,
+    processors: Vec<Box<dyn AllocationProcessor>>,
 }
 
 impl OptimizerApp {
+    /// Create the app with the default strategy, historical APYs from the Sanctum API
+    /// allocated by the EMA allocator and adjusted by the known asset weights
     pub fn new(pool: MaxPool) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            fetcher: Box::new(SanctumHistoricalApyFetcher::new()),
+            allocator: Box::new(EmaAllocator::new(Some(10), Some(5))),
+            processors: default_processors(),
+        }
+    }
+
+    pub fn with_fetcher(self, fetcher: Box<dyn Fetcher<Apy>>) -> Self {
+        Self { fetcher, ..self }
+    }
+
+    pub fn with_allocator(self, allocator: Box<dyn Allocator<Apy>>) -> Self {
+        Self { allocator, ..self }
+    }
+
+    pub fn with_processors(self, processors: Vec<Box<dyn AllocationProcessor>>) -> Self {
+        Self { processors, ..self }
+    }
+
+    pub fn with_processor(self, processor: Box<dyn AllocationProcessor>) -> Self {
+        let mut processors = self.processors;
+        processors.push(processor);
+        Self { processors, ..self }
     }
 
     pub async fn keep_rebalance(&self, context: Context, interval: time::Duration) -> Result<()> {
         loop {
+            // The delayed unstakes move on whether or not the rebalance goes through
+            if let Err(e) = self.pool.advance_delayed_unstakes(&context).await {
+                error!("Failed to advance the delayed unstakes: {:?}", e);
+            }
             let res = self.rebalance(&context).await;
             if let Err(e) = res {
                 error!("Failed to rebalance: {:?}", e);
@@ -47,92 +92,350 @@ impl OptimizerApp {
     pub async fn get_pool_allocation_changes(
         &self,
         context: &Context,
+        current_pool_allocations: &PoolAllocations,
         allocations: AllocationRatios,
     ) -> Result<PoolAllocationChanges> {
         let assets = context.get_kwown_assets();
         let pool = &self.pool;
 
-        let current_pool_allocations = pool.get_allocation(context).await?;
         current_pool_allocations.assert_pool_allocations_are_defined(&assets)?;
         info!("{}", current_pool_allocations);
 
         let pool_allocation_lamports_changes = pool
-            .get_allocation_lamports_changes(context, &current_pool_allocations, &allocations)
+            .get_allocation_lamports_changes(context, current_pool_allocations, &allocations)
             .await?;
         info!("{}", pool_allocation_lamports_changes);
 
         let pool_allocation_changes = pool
-            .get_allocation_changes(context, &current_pool_allocations, &allocations)
+            .get_allocation_changes(context, current_pool_allocations, &allocations)
             .await?;
         info!("{}", pool_allocation_changes);
 
         Ok(pool_allocation_changes)
     }
 
-    /// Retry rebalance pool asset change
-    ///
-    pub async fn try_rebalance_pool_asset_change(
+    /// Rebalance the netted change and wait for it to land, returns its outcome,
+    /// the change is journaled as the step of the cycle and fails if it can't be
+    pub async fn try_rebalance_netted_change(
         &self,
         context: &Context,
-        pool_asset_change: &PoolAssetChange,
-    ) -> Result<()> {
-        let ret = self.pool.rebalance_asset(context, pool_asset_change).await;
-        if let Err(e) = ret {
-            error!("Failed to rebalance pool asset change: {:?}", e);
+        netted_change: &NettedChange,
+        step: usize,
+    ) -> RebalanceOutcome {
+        let journal = self.pool.journal();
+        if let Some(journal) = &journal {
+            if let Err(e) = journal.begin_step(step) {
+                error!("Failed to journal netted change {}: {:?}", netted_change, e);
+                return RebalanceOutcome::failed(RebalanceFailure::JournalFailed(e.to_string()));
+            }
+        }
+        let outcome = self
+            .pool
+            .rebalance_netted_change(context, netted_change)
+            .await;
+        let status = match &outcome.error {
+            None => StepStatus::Landed,
+            Some(error) => StepStatus::Failed {
+                error: error.to_string(),
+            },
+        };
+        // The change already ran, the journal is reconciled against the chain on restart
+        if let Some(journal) = &journal {
+            if let Err(e) = journal.finish_step(step, status) {
+                error!("Failed to journal netted change {}: {:?}", netted_change, e);
+            }
+        }
+        if let Some(error) = &outcome.error {
+            error!("Failed to rebalance netted change: {}", error);
         }
 
-        tokio::time::sleep(time::Duration::from_secs(5)).await;
-
-        Ok(())
+        outcome
     }
 
-    pub async fn rebalance(&self, context: &Context) -> Result<()> {
+    /// Fetch the datapoints of the known assets with the configured fetcher
+    pub async fn fetch_symbol_datas(&self, context: &Context) -> Result<Vec<SymbolData<Apy>>> {
         let assets = context.get_kwown_assets();
 
-        // Fetch historical APY data from the Sanctum API
-        let fetcher = SanctumHistoricalApyFetcher::new();
         let mut symbol_datas = vec![];
         for asset in &assets {
-            let datapoints = fetcher.fetch(&asset).await?;
+            let datapoints = self.fetcher.fetch(asset).await?;
             symbol_datas.push(SymbolData {
                 mint: asset.mint.clone(),
                 symbol: asset.symbol.clone(),
                 datapoints,
             });
         }
+        Ok(symbol_datas)
+    }
+
+    /// Fetch the datapoints of the known assets and allocate them with the configured allocator,
+    /// then run the allocations through the post-processing steps
+    pub async fn allocate(&self, context: &Context) -> Result<AllocationRatios> {
+        let symbol_datas = self.fetch_symbol_datas(context).await?;
+        let current_allocations = self.pool.get_allocation(context).await?;
+        self.allocate_symbol_datas(context, &current_allocations, symbol_datas)
+            .await
+    }
+
+    /// The current allocations let the allocator and processors account for turnover
+    async fn allocate_symbol_datas(
+        &self,
+        context: &Context,
+        current_allocations: &PoolAllocations,
+        symbol_datas: Vec<SymbolData<Apy>>,
+    ) -> Result<AllocationRatios> {
+        let mut allocations = self
+            .allocator
+            .allocate_with_current(symbol_datas, current_allocations)?;
+        for processor in self.processors.iter() {
+            allocations = processor.process(context, current_allocations, allocations)?;
+        }
+
+        Ok(allocations)
+    }
+
+    /// Drop or shrink the netted changes not paying for their swap cost when the pool enables
+    /// it, the latest APY of every asset is the expected APY over the holding horizon
+    pub async fn filter_netted_changes_by_cost(
+        &self,
+        context: &Context,
+        current_pool_allocations: &PoolAllocations,
+        apys: &HashMap<String, f64>,
+        allocations: &AllocationRatios,
+        netted_changes: NettedChanges,
+    ) -> Result<NettedChanges> {
+        let Some(options) = &self.pool.pool_options().rebalance_cost else {
+            return Ok(netted_changes);
+        };
 
-        let allocator = EmaAllocator::new(Some(10), Some(5));
-        let mut allocations = allocator.allocate(symbol_datas)?;
-        allocations.validate()?;
-        allocations.apply_weights(&assets);
-        allocations.validate()?;
+        let (netted_changes, decisions) = self
+            .pool
+            .filter_changes_by_cost(
+                context,
+                current_pool_allocations,
+                allocations,
+                apys,
+                netted_changes,
+                options,
+            )
+            .await?;
+        info!("{}", decisions);
+
+        Ok(netted_changes)
+    }
+
+    /// Fetch, allocate and net the swaps a rebalance would send, against a single snapshot
+    /// of the pool allocations
+    pub async fn prepare_rebalance(
+        &self,
+        context: &Context,
+    ) -> Result<(PoolAllocations, AllocationRatios, NettedChanges)> {
+        let symbol_datas = self.fetch_symbol_datas(context).await?;
+        let apys = latest_apys(&symbol_datas);
+        let current_pool_allocations = self.pool.get_allocation(context).await?;
+        let allocations = self
+            .allocate_symbol_datas(context, &current_pool_allocations, symbol_datas)
+            .await?;
 
         let pool_allocation_changes = self
-            .get_pool_allocation_changes(context, allocations)
+            .get_pool_allocation_changes(context, &current_pool_allocations, allocations.clone())
+            .await?;
+        // The swaps are netted once, the cost filter decides on the ones the rebalance sends
+        let netted_changes = NettedChanges::net(&pool_allocation_changes);
+        let netted_changes = self
+            .filter_netted_changes_by_cost(
+                context,
+                &current_pool_allocations,
+                &apys,
+                &allocations,
+                netted_changes,
+            )
             .await?;
+        info!("{}", netted_changes);
+
+        Ok((current_pool_allocations, allocations, netted_changes))
+    }
 
-        // Reducing first
-        for pool_asset_change in &pool_allocation_changes.assets {
-            match pool_asset_change.amount {
-                AmountChange::Increase { .. } => {}
-                AmountChange::Decrease { .. } => {
-                    self.try_rebalance_pool_asset_change(context, pool_asset_change)
-                        .await?;
-                }
+    /// Plan the rebalance without sending anything. The changes are netted, routed and
+    /// built the way the rebalance sends them, the prep transaction of a swap is simulated
+    /// before its rebalance one. Simulations do not chain, so a rebalance relying on the
+    /// accounts of its prep or on the swaps before it may fail to simulate until they land
+    pub async fn plan(&self, context: &Context) -> Result<RebalancePlan> {
+        let (current_pool_allocations, allocations, netted_changes) =
+            self.prepare_rebalance(context).await?;
+        let pool_allocation_changes = netted_changes.to_pool_allocation_changes();
+
+        let target_lamports_per_mint = self.pool.calculate_lamports_from_allocation_ratios(
+            current_pool_allocations.get_total_lamports(),
+            &allocations,
+        )?;
+
+        let mut mints: Vec<String> = current_pool_allocations
+            .assets
+            .iter()
+            .map(|asset| asset.mint.clone())
+            .collect();
+        for ratio in allocations.asset_alloc_ratios.iter() {
+            if !mints.contains(&ratio.mint) {
+                mints.push(ratio.mint.clone());
             }
         }
 
-        // Increasing
-        for pool_asset_change in &pool_allocation_changes.assets {
-            match pool_asset_change.amount {
-                AmountChange::Increase { .. } => {
-                    self.try_rebalance_pool_asset_change(context, pool_asset_change)
-                        .await?;
-                }
-                AmountChange::Decrease { .. } => {}
+        let assets: Vec<RebalancePlanAsset> = mints
+            .iter()
+            .map(|mint| RebalancePlanAsset {
+                mint: mint.clone(),
+                symbol: context
+                    .get_known_asset_from_mint(mint)
+                    .map(|asset| asset.symbol)
+                    .unwrap_or_default(),
+                current_bps: current_pool_allocations.get_asset_bps(mint),
+                target_bps: allocations
+                    .asset_alloc_ratios
+                    .iter()
+                    .find(|ratio| ratio.mint.eq(mint))
+                    .map(|ratio| ratio.bps)
+                    .unwrap_or_default(),
+                current_lamports: current_pool_allocations
+                    .get_pool_asset(mint)
+                    .map(|asset| asset.lamports)
+                    .unwrap_or(0),
+                target_lamports: target_lamports_per_mint.get(mint).cloned().unwrap_or(0),
+                change: pool_allocation_changes
+                    .get_asset_changes(mint)
+                    .map(|change| change.amount.clone()),
+            })
+            .collect();
+
+        let mut swaps: Vec<RebalancePlanSwap> = vec![];
+        for netted_change in netted_changes.changes.iter() {
+            swaps.push(self.plan_netted_change(context, netted_change).await);
+        }
+
+        Ok(RebalancePlan::new(assets, swaps))
+    }
+
+    /// Build the transactions of the netted change along its route and simulate them in the
+    /// order they are sent, the rebalance one is skipped when its prep fails
+    async fn plan_netted_change(
+        &self,
+        context: &Context,
+        netted_change: &NettedChange,
+    ) -> RebalancePlanSwap {
+        let tx = match netted_change.get_route(context) {
+            Ok(route) => self.pool.build_route_transaction(context, &route).await,
+            Err(e) => Err(e),
+        };
+        let (quoted_out, prep_simulation, simulation) = match tx {
+            Ok(Some(tx)) => {
+                let prep_simulation = self
+                    .simulate_instructions(
+                        context,
+                        &tx.prep_instructions,
+                        &tx.address_lookup_table_accounts,
+                    )
+                    .await;
+                let simulation = match prep_simulation {
+                    SimulationResult::Failure(_) => SimulationResult::Skipped,
+                    _ => {
+                        self.simulate_instructions(
+                            context,
+                            &tx.instructions,
+                            &tx.address_lookup_table_accounts,
+                        )
+                        .await
+                    }
+                };
+                (
+                    tx.quote.map(|quote| quote.out_amount),
+                    prep_simulation,
+                    simulation,
+                )
             }
+            Ok(None) => (None, SimulationResult::Skipped, SimulationResult::Skipped),
+            Err(e) => (
+                None,
+                SimulationResult::Skipped,
+                SimulationResult::Failure(e.to_string()),
+            ),
+        };
+
+        RebalancePlanSwap {
+            change: netted_change.to_string(),
+            quoted_out,
+            prep_simulation,
+            simulation,
         }
+    }
 
-        Ok(())
+    async fn simulate_instructions(
+        &self,
+        context: &Context,
+        instructions: &[Instruction],
+        address_lookup_table_accounts: &[AddressLookupTableAccount],
+    ) -> SimulationResult {
+        if instructions.is_empty() {
+            return SimulationResult::Skipped;
+        }
+        match self
+            .pool
+            .controller_client()
+            .simulate_instructions(
+                context.get_payer(),
+                instructions,
+                address_lookup_table_accounts,
+            )
+            .await
+        {
+            Ok(result) => match result.err {
+                Some(err) => SimulationResult::Failure(format!("{:?}", err)),
+                None => SimulationResult::Success {
+                    units_consumed: result.units_consumed,
+                },
+            },
+            Err(e) => SimulationResult::Failure(e.to_string()),
+        }
     }
+
+    /// Rebalance the pool to the allocations, every netted change is reported with
+    /// its outcome
+    pub async fn rebalance(&self, context: &Context) -> Result<RebalanceCycleReport> {
+        // An interrupted cycle is finished or abandoned before the next one is planned
+        self.pool.reconcile_journal(context).await?;
+        let (_, _, netted_changes) = self.prepare_rebalance(context).await?;
+
+        // Direct swaps first, then reducing into wSOL before increasing out of it,
+        // each change is confirmed before the next one is sent
+        if let Some(journal) = self.pool.journal() {
+            let changes: Vec<String> = netted_changes
+                .changes
+                .iter()
+                .map(|netted_change| netted_change.to_string())
+                .collect();
+            journal.begin_cycle(&changes)?;
+        }
+        let mut report = RebalanceCycleReport::new();
+        for (step, netted_change) in netted_changes.changes.iter().enumerate() {
+            let outcome = self
+                .try_rebalance_netted_change(context, netted_change, step)
+                .await;
+            report.add(&netted_change.to_string(), outcome);
+        }
+
+        info!("{}", report);
+        Ok(report)
+    }
+}
+
+/// The latest APY of every symbol with datapoints
+fn latest_apys(symbol_datas: &[SymbolData<Apy>]) -> HashMap<String, f64> {
+    symbol_datas
+        .iter()
+        .filter_map(|symbol_data| {
+            symbol_data
+                .datapoints
+                .last()
+                .map(|datapoint| (symbol_data.mint.clone(), datapoint.apy))
+        })
+        .collect()
 }
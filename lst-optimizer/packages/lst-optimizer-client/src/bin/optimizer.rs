@@ -1,9 +1,11 @@
 use clap::Parser;
-use jupiter_lib::quoter::JupiterQuoterClient;
 use lst_optimizer_client::{
     app::OptimizerApp,
     pool::{pool::MaxPool, typedefs::MaxPoolOptions},
-    utils::{args::AppArgs, path::get_registry_file},
+    utils::{
+        args::{AppArgs, Command},
+        path::get_registry_file,
+    },
 };
 use lst_optimizer_std::{helper::config::asset_repository_from_toml, types::context::Context};
 use lst_optimizer_utils::{logger::setup_global_logger, path::get_deps_configs};
@@ -25,26 +27,42 @@ async fn main() {
     let context = Context::default();
     let program_id = controller_lib::program::mainnet::ID;
 
-    let rpc_url = args.url;
-    let jupiter_quoter_client = JupiterQuoterClient::new(&rpc_url);
+    let rpc_url = args.url.clone();
+    let quoter_client = args
+        .quoter_client(&asset_repository)
+        .expect("Failed to build the quoter client");
     let pool = MaxPool::new(
         program_id,
-        Box::new(jupiter_quoter_client),
+        quoter_client,
         MaxPoolOptions {
             rpc_url,
             minimum_rebalance_lamports: args.minimum_rebalance_lamports,
+            rebalance_cost: args.rebalance_cost(),
+            max_loss_bps: args.max_loss_bps,
+            confirm: args.confirm_options(),
+            compute_budget: args.compute_budget(),
+            tranche: args.tranche().expect("Invalid tranche options"),
+            delayed_unstake: args.delayed_unstake(),
+            journal_path: args.journal_path.clone(),
             ..Default::default()
         },
     );
 
-    let err = OptimizerApp::new(pool)
-        .keep_rebalance(
-            context
-                .with_asset_repository(asset_repository)
-                .with_payer(payer),
-            std::time::Duration::from_secs(args.interval),
-        )
-        .await;
+    let app = OptimizerApp::new(pool)
+        .with_fetcher(args.fetcher())
+        .with_allocator(args.allocator())
+        .with_processors(args.processors());
+    let context = context
+        .with_asset_repository(asset_repository)
+        .with_payer(payer);
+
+    let err = match args.command.unwrap_or(Command::Run) {
+        Command::Run => {
+            app.keep_rebalance(context, std::time::Duration::from_secs(args.interval))
+                .await
+        }
+        Command::Plan => app.plan(&context).await.map(|plan| println!("{}", plan)),
+    };
     if let Err(err) = err {
         eprintln!("{:?}", err);
     }
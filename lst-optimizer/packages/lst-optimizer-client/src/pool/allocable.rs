@@ -62,13 +62,8 @@ impl PoolAllocable for MaxPool {
     ) -> Result<PoolAllocationLamportsChanges> {
         let total_lamports = pool_allocations.get_total_lamports();
 
-        let mut target_lamports_per_symbol: HashMap<String, u64> = HashMap::new();
-        for symbol_ratio in new_allocation_ratios.asset_alloc_ratios.iter() {
-            target_lamports_per_symbol.insert(
-                symbol_ratio.mint.to_owned(),
-                self.calculate_lamports_from_bps(total_lamports, symbol_ratio.bps)?,
-            );
-        }
+        let target_lamports_per_symbol: HashMap<String, u64> =
+            self.calculate_lamports_from_allocation_ratios(total_lamports, new_allocation_ratios)?;
 
         // Calculate allocation changes
         let mut changes: HashMap<String, LamportsChange> = HashMap::new();
@@ -0,0 +1,233 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::utils::path::write_atomically;
+
+use super::journal::JournalTransaction;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum DelayedUnstakeError {
+    #[error("Delayed unstake is not enabled")]
+    Disabled,
+
+    #[error("Asset {0} has no SPL stake pool to unstake from")]
+    NoStakePool(String),
+
+    #[error("Stake pool {0} is not updated this epoch")]
+    StakePoolNotUpdated(String),
+
+    #[error("No validator of stake pool {0} has {1} lamports to withdraw")]
+    NoWithdrawableStake(String, u64),
+
+    #[error("The payer holds {0} wSOL, short of the {1} fronted to the pool")]
+    InsufficientWsol(u64, u64),
+
+    #[error("The payer already fronts {0} lamports to the pool, at its max of {1}")]
+    FrontedLimitReached(u64, u64),
+}
+
+/// Unstake the decreases of the opted in assets over an epoch when the loss guard rejects
+/// selling them, the state of the unstakes in progress is persisted in the file.
+///
+/// The controller requires a rebalance to repay the pool in the same transaction, so the
+/// stake account cannot stay with the pool while it deactivates. The payer fronts the pool
+/// its own wSOL for the withdrawn lst instead and is repaid by the unstaked SOL an epoch
+/// later, so it must hold wSOL worth the exit it unstakes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DelayedUnstakeOptions {
+    pub state_path: PathBuf,
+    /// The most wSOL the payer fronts across the unstakes in progress
+    pub max_fronted_lamports: u64,
+}
+
+/// Where a delayed unstake is at, advanced once per rebalance cycle
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum DelayedUnstakeStage {
+    /// Recorded before the withdrawal from the pool is sent, reconciled against its
+    /// transactions when the process stopped before knowing whether it landed
+    Withdrawing,
+    /// The lst left the pool for the wSOL the payer fronted, the payer holds it
+    Withdrawn,
+    /// The stake account split off the stake pool is deactivating since the epoch
+    Deactivating { epoch: u64 },
+}
+
+/// An lst leaving the pool through a stake account the payer unstakes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DelayedUnstake {
+    pub mint: String,
+    /// The lst withdrawn from the pool
+    pub lst_amount: u64,
+    /// The wSOL the payer fronted to the pool, rounded up from the calculator values
+    pub fronted_lamports: u64,
+    /// The seed the stake account is derived from the payer with
+    pub stake_seed: String,
+    pub stake_account: String,
+    pub stage: DelayedUnstakeStage,
+    /// The signed withdrawals from the pool, recorded before they are sent
+    #[serde(default)]
+    pub transactions: Vec<JournalTransaction>,
+}
+
+impl DelayedUnstake {
+    /// Stake deactivated in an earlier epoch is inactive and may be withdrawn
+    pub fn is_withdrawable(&self, epoch: u64) -> bool {
+        match self.stage {
+            DelayedUnstakeStage::Withdrawing | DelayedUnstakeStage::Withdrawn => false,
+            DelayedUnstakeStage::Deactivating { epoch: deactivated } => epoch > deactivated,
+        }
+    }
+}
+
+/// The seed of a delayed unstake stake account, within the 32 bytes a seed may take
+pub fn stake_seed(nonce: u64) -> String {
+    format!("unstake-{}", nonce)
+}
+
+/// The delayed unstakes in progress, kept as JSON so they survive restarts
+#[derive(Debug, Clone)]
+pub struct DelayedUnstakeStore {
+    path: PathBuf,
+}
+
+impl DelayedUnstakeStore {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// The persisted delayed unstakes, none before the file is first written
+    pub fn load(&self) -> Result<Vec<DelayedUnstake>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+        let json = fs::read_to_string(&self.path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// The wSOL the payer fronts to the pool across the unstakes in progress
+    pub fn fronted_lamports(&self) -> Result<u64> {
+        Ok(self
+            .load()?
+            .iter()
+            .map(|unstake| unstake.fronted_lamports)
+            .sum())
+    }
+
+    pub fn save(&self, unstakes: &[DelayedUnstake]) -> Result<()> {
+        write_atomically(&self.path, &serde_json::to_string_pretty(unstakes)?)
+    }
+
+    /// Insert the delayed unstake or replace the one of the same stake account
+    pub fn upsert(&self, unstake: &DelayedUnstake) -> Result<()> {
+        let mut unstakes = self.load()?;
+        match unstakes
+            .iter_mut()
+            .find(|existing| existing.stake_account.eq(&unstake.stake_account))
+        {
+            Some(existing) => *existing = unstake.clone(),
+            None => unstakes.push(unstake.clone()),
+        }
+        self.save(&unstakes)
+    }
+
+    /// Record the signed transaction in the delayed unstake of the stake account
+    pub fn record_transaction(
+        &self,
+        stake_account: &str,
+        transaction: JournalTransaction,
+    ) -> Result<()> {
+        let mut unstakes = self.load()?;
+        if let Some(unstake) = unstakes
+            .iter_mut()
+            .find(|unstake| unstake.stake_account.eq(stake_account))
+        {
+            unstake.transactions.push(transaction);
+        }
+        self.save(&unstakes)
+    }
+
+    pub fn remove(&self, stake_account: &str) -> Result<()> {
+        let mut unstakes = self.load()?;
+        unstakes.retain(|unstake| !unstake.stake_account.eq(stake_account));
+        self.save(&unstakes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pool::helper::journal::TransactionKind;
+
+    use super::*;
+
+    fn unstake(stake_account: &str, stage: DelayedUnstakeStage) -> DelayedUnstake {
+        DelayedUnstake {
+            mint: "mint".to_string(),
+            lst_amount: 1_000,
+            fronted_lamports: 1_100,
+            stake_seed: stake_seed(42),
+            stake_account: stake_account.to_string(),
+            stage,
+            transactions: vec![],
+        }
+    }
+
+    #[test]
+    fn test_is_withdrawable() {
+        assert!(!unstake("a", DelayedUnstakeStage::Withdrawing).is_withdrawable(700));
+        assert!(!unstake("a", DelayedUnstakeStage::Withdrawn).is_withdrawable(700));
+        let deactivating = unstake("a", DelayedUnstakeStage::Deactivating { epoch: 700 });
+        assert!(!deactivating.is_withdrawable(700));
+        assert!(deactivating.is_withdrawable(701));
+    }
+
+    #[test]
+    fn test_stake_seed() {
+        assert_eq!(stake_seed(42), "unstake-42");
+        assert!(stake_seed(u64::MAX).len() <= 32);
+    }
+
+    #[test]
+    fn test_store() {
+        let path =
+            std::env::temp_dir().join(format!("delayed-unstake-{}.json", std::process::id()));
+        let store = DelayedUnstakeStore::new(&path);
+        assert_eq!(store.load().unwrap(), vec![]);
+
+        let withdrawn = unstake("a", DelayedUnstakeStage::Withdrawn);
+        store.upsert(&withdrawn).unwrap();
+        store
+            .upsert(&unstake("b", DelayedUnstakeStage::Withdrawn))
+            .unwrap();
+        let deactivating = DelayedUnstake {
+            stage: DelayedUnstakeStage::Deactivating { epoch: 700 },
+            ..withdrawn
+        };
+        store.upsert(&deactivating).unwrap();
+        let unstakes = store.load().unwrap();
+        assert_eq!(unstakes.len(), 2);
+        assert_eq!(unstakes[0], deactivating);
+        assert_eq!(store.fronted_lamports().unwrap(), 2_200);
+
+        let transaction = JournalTransaction {
+            kind: TransactionKind::Rebalance,
+            signature: "signature".to_string(),
+            last_valid_block_height: 100,
+        };
+        store.record_transaction("b", transaction.clone()).unwrap();
+        assert_eq!(store.load().unwrap()[1].transactions, vec![transaction]);
+
+        store.remove("b").unwrap();
+        assert_eq!(store.load().unwrap(), vec![deactivating]);
+        assert_eq!(store.fronted_lamports().unwrap(), 1_100);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,316 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{instruction::Instruction, signature::Signature};
+use thiserror::Error;
+
+use crate::utils::path::write_atomically;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum JournalError {
+    #[error("The cycle started at {0} is unfinished, it must be reconciled first")]
+    UnfinishedCycle(u64),
+
+    #[error("No cycle in the journal")]
+    NoCycle,
+
+    #[error("No step {0} in the journal cycle")]
+    UnknownStep(usize),
+}
+
+/// What a transaction of a step does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionKind {
+    /// Sets up the accounts of the rebalance
+    Prep,
+    /// Withdraws from and returns to the pool, the step happened once it lands
+    Rebalance,
+    /// Closes the accounts left by the rebalance
+    Cleanup,
+    /// Moves the stake of a delayed unstake
+    Unstake,
+}
+
+/// A signed transaction, recorded before it is sent
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalTransaction {
+    pub kind: TransactionKind,
+    pub signature: String,
+    /// The transaction can no longer land past this block height
+    pub last_valid_block_height: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum StepStatus {
+    Pending,
+    InProgress,
+    Landed,
+    Failed { error: String },
+    Abandoned { reason: String },
+}
+
+impl StepStatus {
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, StepStatus::Pending | StepStatus::InProgress)
+    }
+}
+
+/// A change of the cycle plan and the transactions sent for it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalStep {
+    pub change: String,
+    pub status: StepStatus,
+    pub transactions: Vec<JournalTransaction>,
+    /// The cleanup of a landed rebalance, kept until it is sent so it can be finished
+    #[serde(default)]
+    pub pending_cleanup: Vec<Instruction>,
+}
+
+impl JournalStep {
+    pub fn new(change: &str) -> Self {
+        Self {
+            change: change.to_string(),
+            status: StepStatus::Pending,
+            transactions: vec![],
+            pending_cleanup: vec![],
+        }
+    }
+
+    pub fn transactions_of(&self, kind: TransactionKind) -> Vec<&JournalTransaction> {
+        self.transactions
+            .iter()
+            .filter(|transaction| transaction.kind == kind)
+            .collect()
+    }
+}
+
+/// The plan of a rebalance cycle, its steps run one at a time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalCycle {
+    /// Unix timestamp in seconds
+    pub started_at: u64,
+    pub steps: Vec<JournalStep>,
+    /// The step the transactions sent are recorded in
+    pub active_step: Option<usize>,
+}
+
+impl JournalCycle {
+    pub fn is_finished(&self) -> bool {
+        self.steps.iter().all(|step| step.status.is_finished())
+    }
+
+    fn step_mut(&mut self, index: usize) -> Result<&mut JournalStep> {
+        Ok(self
+            .steps
+            .get_mut(index)
+            .ok_or(JournalError::UnknownStep(index))?)
+    }
+}
+
+/// Write-ahead journal of the rebalance cycle, every step and transaction is recorded
+/// before it is sent, so a restarted process reconciles the cycle against the chain
+/// instead of running its steps again
+#[derive(Debug, Clone)]
+pub struct RebalanceJournal {
+    path: PathBuf,
+}
+
+impl RebalanceJournal {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// The last cycle, none before the journal is first written
+    pub fn load(&self) -> Result<Option<JournalCycle>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let json = fs::read_to_string(&self.path)?;
+        Ok(Some(serde_json::from_str(&json)?))
+    }
+
+    pub fn save(&self, cycle: &JournalCycle) -> Result<()> {
+        write_atomically(&self.path, &serde_json::to_string_pretty(cycle)?)
+    }
+
+    /// The last cycle when it did not finish
+    pub fn load_unfinished(&self) -> Result<Option<JournalCycle>> {
+        Ok(self.load()?.filter(|cycle| !cycle.is_finished()))
+    }
+
+    fn update<F>(&self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut JournalCycle) -> Result<()>,
+    {
+        let mut cycle = self.load()?.ok_or(JournalError::NoCycle)?;
+        f(&mut cycle)?;
+        self.save(&cycle)
+    }
+
+    /// Record the plan of a new cycle, the previous one must be finished
+    pub fn begin_cycle(&self, changes: &[String]) -> Result<()> {
+        if let Some(cycle) = self.load_unfinished()? {
+            return Err(JournalError::UnfinishedCycle(cycle.started_at).into());
+        }
+        self.save(&JournalCycle {
+            started_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            steps: changes
+                .iter()
+                .map(|change| JournalStep::new(change))
+                .collect(),
+            active_step: None,
+        })
+    }
+
+    pub fn begin_step(&self, index: usize) -> Result<()> {
+        self.update(|cycle| {
+            cycle.step_mut(index)?.status = StepStatus::InProgress;
+            cycle.active_step = Some(index);
+            Ok(())
+        })
+    }
+
+    /// Record the transaction in the active step, nothing is recorded outside of a step
+    pub fn record_transaction(
+        &self,
+        kind: TransactionKind,
+        signature: &Signature,
+        last_valid_block_height: u64,
+    ) -> Result<()> {
+        let Some(mut cycle) = self.load()? else {
+            return Ok(());
+        };
+        let Some(index) = cycle.active_step else {
+            return Ok(());
+        };
+        cycle
+            .step_mut(index)?
+            .transactions
+            .push(JournalTransaction {
+                kind,
+                signature: signature.to_string(),
+                last_valid_block_height,
+            });
+        self.save(&cycle)
+    }
+
+    /// Keep the cleanup of the active step until it is sent, empty once it is
+    pub fn record_pending_cleanup(&self, instructions: &[Instruction]) -> Result<()> {
+        let Some(mut cycle) = self.load()? else {
+            return Ok(());
+        };
+        let Some(index) = cycle.active_step else {
+            return Ok(());
+        };
+        cycle.step_mut(index)?.pending_cleanup = instructions.to_vec();
+        self.save(&cycle)
+    }
+
+    pub fn finish_step(&self, index: usize, status: StepStatus) -> Result<()> {
+        self.update(|cycle| {
+            let step = cycle.step_mut(index)?;
+            step.status = status;
+            step.pending_cleanup = vec![];
+            if cycle.active_step == Some(index) {
+                cycle.active_step = None;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{instruction::AccountMeta, pubkey::Pubkey};
+
+    use super::*;
+
+    fn journal(name: &str) -> (RebalanceJournal, PathBuf) {
+        let path =
+            std::env::temp_dir().join(format!("journal-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        (RebalanceJournal::new(&path), path)
+    }
+
+    #[test]
+    fn test_cycle() {
+        let (journal, path) = journal("cycle");
+        assert_eq!(journal.load().unwrap(), None);
+        let changes = vec!["a -> b".to_string(), "c -> wSOL".to_string()];
+        journal.begin_cycle(&changes).unwrap();
+
+        // Nothing is recorded outside of a step
+        journal
+            .record_transaction(TransactionKind::Prep, &Signature::new_unique(), 100)
+            .unwrap();
+        journal.begin_step(0).unwrap();
+        let signature = Signature::new_unique();
+        journal
+            .record_transaction(TransactionKind::Rebalance, &signature, 100)
+            .unwrap();
+        let cleanup = vec![Instruction::new_with_bytes(
+            Pubkey::new_unique(),
+            &[1, 2],
+            vec![AccountMeta::new(Pubkey::new_unique(), true)],
+        )];
+        journal.record_pending_cleanup(&cleanup).unwrap();
+
+        let cycle = journal.load_unfinished().unwrap().unwrap();
+        assert_eq!(cycle.active_step, Some(0));
+        assert_eq!(cycle.steps[0].status, StepStatus::InProgress);
+        assert_eq!(cycle.steps[0].pending_cleanup, cleanup);
+        let rebalances = cycle.steps[0].transactions_of(TransactionKind::Rebalance);
+        assert_eq!(rebalances.len(), 1);
+        assert_eq!(rebalances[0].signature, signature.to_string());
+        assert!(cycle.steps[0]
+            .transactions_of(TransactionKind::Prep)
+            .is_empty());
+
+        journal.finish_step(0, StepStatus::Landed).unwrap();
+        assert_eq!(
+            journal.begin_cycle(&changes).err().unwrap().to_string(),
+            JournalError::UnfinishedCycle(cycle.started_at).to_string()
+        );
+        journal
+            .finish_step(
+                1,
+                StepStatus::Failed {
+                    error: "slippage".to_string(),
+                },
+            )
+            .unwrap();
+        let cycle = journal.load().unwrap().unwrap();
+        assert!(cycle.is_finished());
+        assert_eq!(cycle.active_step, None);
+        assert!(cycle.steps[0].pending_cleanup.is_empty());
+        assert_eq!(journal.load_unfinished().unwrap(), None);
+
+        journal.begin_cycle(&changes[..1]).unwrap();
+        assert_eq!(journal.load().unwrap().unwrap().steps.len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_unknown_step() {
+        let (journal, path) = journal("unknown-step");
+        assert_eq!(
+            journal.begin_step(0).err().unwrap().to_string(),
+            JournalError::NoCycle.to_string()
+        );
+        journal.begin_cycle(&["a -> b".to_string()]).unwrap();
+        assert_eq!(
+            journal.begin_step(1).err().unwrap().to_string(),
+            JournalError::UnknownStep(1).to_string()
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,2 +1,7 @@
+pub mod delayed_unstake;
+pub mod journal;
 pub mod pool_asset_change_route;
+pub mod swap_loss_guard;
+pub mod tranche;
+pub mod transaction_assembler;
 pub mod transaction_err;
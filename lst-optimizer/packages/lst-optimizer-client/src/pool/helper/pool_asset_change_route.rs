@@ -1,7 +1,8 @@
 use anyhow::Result;
 use controller_lib::{calculator::typedefs::CalculatorType, Pubkey};
 use lst_optimizer_std::types::{
-    amount_change::AmountChange, asset::Asset, pool_allocation_changes::PoolAssetChange,
+    amount_change::AmountChange, asset::Asset, context::Context, netted_change::NettedChange,
+    pool_allocation_changes::PoolAssetChange,
 };
 use spl_token::native_mint;
 
@@ -53,3 +54,32 @@ impl PoolAssetChangeRouter for PoolAssetChange {
         })
     }
 }
+
+pub trait NettedChangeRouter {
+    fn get_route(&self, context: &Context) -> Result<PoolAssetChangeRoute>;
+}
+
+impl NettedChangeRouter for NettedChange {
+    /// A direct route swaps the decreasing lst into the increasing one,
+    /// the lst amount of the decrease being the exact in amount
+    fn get_route(&self, context: &Context) -> Result<PoolAssetChangeRoute> {
+        match (&self.decrease, &self.increase) {
+            (Some(decrease), Some(increase)) => {
+                let src_asset = context.get_known_asset_from_mint(&decrease.mint)?;
+                let dst_asset = context.get_known_asset_from_mint(&increase.mint)?;
+                Ok(PoolAssetChangeRoute {
+                    src_mint: decrease.mint.parse()?,
+                    dst_mint: increase.mint.parse()?,
+                    src_cal: pool_to_calculator_type(&src_asset)?,
+                    dst_cal: pool_to_calculator_type(&dst_asset)?,
+                    amount: decrease.amount.get_lst_amount(),
+                })
+            }
+            (Some(change), None) | (None, Some(change)) => {
+                let asset = context.get_known_asset_from_mint(&change.mint)?;
+                change.get_route(&asset)
+            }
+            (None, None) => Err(anyhow::anyhow!("The netted change has no side")),
+        }
+    }
+}
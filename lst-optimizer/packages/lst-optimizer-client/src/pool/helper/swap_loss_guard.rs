@@ -0,0 +1,126 @@
+use anyhow::Result;
+use thiserror::Error;
+
+const MAX_BPS: u64 = 10_000;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SwapLossError {
+    #[error("Max loss bps {0} must not be greater than 10000")]
+    InvalidMaxLossBps(u16),
+
+    #[error("Quoted out amount {0} is {1} bps below the fair value {2}, the max loss is {3} bps")]
+    MaxLossExceeded(u64, u64, u64, u16),
+}
+
+/// Bound the loss of a swap against the fair value given by the calculators
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SwapLossGuard {
+    max_loss_bps: u16,
+}
+
+impl SwapLossGuard {
+    pub fn new(max_loss_bps: u16) -> Result<Self> {
+        if max_loss_bps as u64 > MAX_BPS {
+            return Err(SwapLossError::InvalidMaxLossBps(max_loss_bps).into());
+        }
+        Ok(Self { max_loss_bps })
+    }
+
+    pub fn max_loss_bps(&self) -> u16 {
+        self.max_loss_bps
+    }
+
+    /// Loss of the quoted out amount below the fair value in bps, rounded up
+    pub fn loss_bps(fair_out: u64, quoted_out: u64) -> u64 {
+        if fair_out == 0 {
+            return 0;
+        }
+        let loss = fair_out.saturating_sub(quoted_out) as u128;
+        ((loss * MAX_BPS as u128).div_ceil(fair_out as u128)) as u64
+    }
+
+    pub fn check(&self, fair_out: u64, quoted_out: u64) -> Result<()> {
+        let loss_bps = Self::loss_bps(fair_out, quoted_out);
+        if loss_bps > self.max_loss_bps as u64 {
+            return Err(SwapLossError::MaxLossExceeded(
+                quoted_out,
+                loss_bps,
+                fair_out,
+                self.max_loss_bps,
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// The least out amount accepted for the fair value
+    pub fn min_out_amount(&self, fair_out: u64) -> u64 {
+        (fair_out as u128 * (MAX_BPS - self.max_loss_bps as u64) as u128).div_ceil(MAX_BPS as u128)
+            as u64
+    }
+
+    /// The slippage of the quoted out amount that still lands above the min out amount
+    pub fn slippage_bps(&self, fair_out: u64, quoted_out: u64) -> u16 {
+        let min_out = self.min_out_amount(fair_out);
+        if quoted_out == 0 || quoted_out <= min_out {
+            return 0;
+        }
+        ((quoted_out - min_out) as u128 * MAX_BPS as u128 / quoted_out as u128) as u16
+    }
+
+    /// The least src reserves the rebalance may start with
+    pub fn min_starting_src_lst(&self, src_reserves: u64) -> u64 {
+        (src_reserves as u128 * (MAX_BPS - self.max_loss_bps as u64) as u128 / MAX_BPS as u128)
+            as u64
+    }
+
+    /// The most dst reserves the rebalance may start with
+    pub fn max_starting_dst_lst(&self, dst_reserves: u64) -> u64 {
+        (dst_reserves as u128 * (MAX_BPS + self.max_loss_bps as u64) as u128)
+            .div_ceil(MAX_BPS as u128)
+            .min(u64::MAX as u128) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_max_loss() {
+        let guard = SwapLossGuard::new(50).unwrap();
+        assert!(guard.check(1_000_000, 995_000).is_ok());
+        assert!(guard.check(1_000_000, 1_010_000).is_ok());
+        assert_eq!(
+            guard.check(1_000_000, 994_999).err().unwrap().to_string(),
+            SwapLossError::MaxLossExceeded(994_999, 51, 1_000_000, 50).to_string()
+        );
+    }
+
+    #[test]
+    fn test_min_out_and_slippage() {
+        let guard = SwapLossGuard::new(50).unwrap();
+        assert_eq!(guard.min_out_amount(1_000_000), 995_000);
+        // the quote is 0.3% above the min out amount
+        let slippage_bps = guard.slippage_bps(1_000_000, 998_000);
+        assert_eq!(slippage_bps, 30);
+        assert!(998_000 * (10_000 - slippage_bps as u64) / 10_000 >= 995_000);
+        assert_eq!(guard.slippage_bps(1_000_000, 990_000), 0);
+    }
+
+    #[test]
+    fn test_start_rebalance_bounds() {
+        let guard = SwapLossGuard::new(100).unwrap();
+        assert_eq!(guard.min_starting_src_lst(1_000_000), 990_000);
+        assert_eq!(guard.max_starting_dst_lst(1_000_000), 1_010_000);
+        assert_eq!(guard.max_starting_dst_lst(u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn test_new_fail_on_invalid_max_loss_bps() {
+        assert_eq!(
+            SwapLossGuard::new(10_001).err().unwrap().to_string(),
+            SwapLossError::InvalidMaxLossBps(10_001).to_string()
+        );
+    }
+}
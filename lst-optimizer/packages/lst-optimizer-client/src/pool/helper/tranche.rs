@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum TrancheError {
+    #[error("Max tranches must be greater than 0")]
+    InvalidMaxTranches,
+
+    #[error("Max price impact {0} must be a fraction in (0, 1]")]
+    InvalidMaxPriceImpact(f64),
+
+    #[error("Stopped after swapping {0} of {1} in {2} tranches: {3}")]
+    StoppedEarly(u64, u64, u16, String),
+}
+
+/// Split the swap of a rebalance into sequential tranches whose quoted price impact
+/// stays below a threshold
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrancheOptions {
+    /// The max quoted price impact of a tranche, a fraction where 0.01 is 1%
+    pub max_price_impact: f64,
+    /// Wait between the tranches, letting the market recover
+    pub delay: Duration,
+    /// The most tranches the swap is split into, a tranche goes over the max price impact
+    /// when smaller ones would not swap the remaining amount in the tranches left
+    pub max_tranches: u16,
+}
+
+impl TrancheOptions {
+    pub fn new(max_price_impact: f64, delay: Duration, max_tranches: u16) -> Result<Self> {
+        if max_tranches == 0 {
+            return Err(TrancheError::InvalidMaxTranches.into());
+        }
+        if !(max_price_impact > 0.0 && max_price_impact <= 1.0) {
+            return Err(TrancheError::InvalidMaxPriceImpact(max_price_impact).into());
+        }
+        Ok(Self {
+            max_price_impact,
+            delay,
+            max_tranches,
+        })
+    }
+
+    /// The least amount of the next tranche for the remaining amount to be swapped
+    /// in the tranches left
+    pub fn min_tranche_amount(&self, remaining: u64, swapped_tranches: u16) -> u64 {
+        let tranches_left = self.max_tranches.saturating_sub(swapped_tranches).max(1);
+        remaining.div_ceil(tranches_left as u64)
+    }
+
+    /// Scale the quoted amount down to the max price impact, assuming the impact grows
+    /// linearly with the amount, without going below the min amount
+    pub fn scale_tranche_amount(&self, amount: u64, price_impact: f64, min_amount: u64) -> u64 {
+        if price_impact <= self.max_price_impact {
+            return amount;
+        }
+        let scaled = (amount as f64 * self.max_price_impact / price_impact) as u64;
+        scaled.max(min_amount).min(amount)
+    }
+}
+
+/// The price impact of an out amount below its fair value, a fraction like the quoted ones
+pub fn price_impact(fair_out: u64, out_amount: u64) -> f64 {
+    if fair_out == 0 {
+        return 0.0;
+    }
+    fair_out.saturating_sub(out_amount) as f64 / fair_out as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_min_tranche_amount() {
+        let options = TrancheOptions::new(0.01, Duration::ZERO, 4).unwrap();
+        assert_eq!(options.min_tranche_amount(1000, 0), 250);
+        assert_eq!(options.min_tranche_amount(1001, 0), 251);
+        assert_eq!(options.min_tranche_amount(500, 2), 250);
+        assert_eq!(options.min_tranche_amount(500, 4), 500);
+        assert_eq!(options.min_tranche_amount(500, 5), 500);
+    }
+
+    #[test]
+    fn test_scale_tranche_amount() {
+        let options = TrancheOptions::new(0.01, Duration::ZERO, 10).unwrap();
+        assert_eq!(options.scale_tranche_amount(1000, 0.005, 100), 1000);
+        assert_eq!(options.scale_tranche_amount(1000, 0.04, 100), 250);
+        assert_eq!(options.scale_tranche_amount(1000, 0.5, 100), 100);
+    }
+
+    #[test]
+    fn test_price_impact() {
+        assert_eq!(price_impact(1000, 990), 0.01);
+        assert_eq!(price_impact(1000, 1010), 0.0);
+        assert_eq!(price_impact(0, 10), 0.0);
+    }
+
+    #[test]
+    fn test_new_fail_on_zero_max_tranches() {
+        assert_eq!(
+            TrancheOptions::new(0.01, Duration::ZERO, 0)
+                .err()
+                .unwrap()
+                .to_string(),
+            TrancheError::InvalidMaxTranches.to_string()
+        );
+    }
+
+    #[test]
+    fn test_new_fail_on_invalid_max_price_impact() {
+        for max_price_impact in [0.0, -0.01, 1.5, f64::NAN] {
+            assert_eq!(
+                TrancheOptions::new(max_price_impact, Duration::ZERO, 10)
+                    .err()
+                    .unwrap()
+                    .to_string(),
+                TrancheError::InvalidMaxPriceImpact(max_price_impact).to_string()
+            );
+        }
+        assert!(TrancheOptions::new(1.0, Duration::ZERO, 10).is_ok());
+    }
+}
@@ -0,0 +1,268 @@
+use anyhow::Result;
+use quoter_lib::typedefs::RouteConstraints;
+use solana_sdk::instruction::Instruction;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum TransactionAssemblerError {
+    #[error("The rebalance instructions do not fit in a transaction")]
+    RebalanceTooLarge,
+
+    #[error("The setup instructions do not fit in a transaction")]
+    SetupTooLarge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransactionLayout {
+    /// Setup, rebalance and cleanup in one transaction
+    Single,
+    /// Setup and rebalance in one transaction, cleanup in a following one
+    SeparateCleanup,
+    /// Setup in a preceding transaction, rebalance and cleanup in one transaction
+    SeparatePrep,
+    /// Setup, rebalance and cleanup each in their own transaction
+    SeparatePrepAndCleanup,
+}
+
+/// How the rebalance is made to fit in a transaction, tried in order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SizeStrategy {
+    /// The best route
+    Unconstrained,
+    /// A route limited in accounts
+    MaxAccounts(u8),
+    /// A route through a single venue
+    DirectRoute,
+    /// A route through a single venue, with the setup in a preceding transaction
+    SeparateSetup,
+}
+
+pub const MAX_ROUTE_ACCOUNTS: u8 = 32;
+
+pub const SIZE_STRATEGIES: [SizeStrategy; 4] = [
+    SizeStrategy::Unconstrained,
+    SizeStrategy::MaxAccounts(MAX_ROUTE_ACCOUNTS),
+    SizeStrategy::DirectRoute,
+    SizeStrategy::SeparateSetup,
+];
+
+impl SizeStrategy {
+    pub fn route_constraints(&self) -> RouteConstraints {
+        match self {
+            SizeStrategy::Unconstrained => RouteConstraints::default(),
+            SizeStrategy::MaxAccounts(max_accounts) => RouteConstraints {
+                max_accounts: Some(*max_accounts),
+                only_direct_routes: false,
+            },
+            SizeStrategy::DirectRoute | SizeStrategy::SeparateSetup => RouteConstraints {
+                max_accounts: Some(MAX_ROUTE_ACCOUNTS),
+                only_direct_routes: true,
+            },
+        }
+    }
+
+    /// Whether the setup may move to a preceding transaction
+    pub fn allows_prep(&self) -> bool {
+        matches!(self, SizeStrategy::SeparateSetup)
+    }
+}
+
+/// The instructions of a rebalance, in execution order
+#[derive(Debug, Clone)]
+pub struct RebalanceInstructions {
+    pub compute_budget_instructions: Vec<Instruction>,
+    /// Token accounts creation and swap setup, run before the rebalance starts
+    pub setup_instructions: Vec<Instruction>,
+    pub start_instruction: Instruction,
+    pub swap_instructions: Vec<Instruction>,
+    pub end_instruction: Instruction,
+    /// Run after the rebalance ends
+    pub cleanup_instructions: Vec<Instruction>,
+}
+
+/// The transactions to send in order, the empty ones are skipped
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssembledTransactions {
+    pub layout: TransactionLayout,
+    pub prep_instructions: Vec<Instruction>,
+    pub instructions: Vec<Instruction>,
+    pub cleanup_instructions: Vec<Instruction>,
+}
+
+impl RebalanceInstructions {
+    /// Pack the instructions in as few transactions as `fits` allows, setup and cleanup
+    /// move out of the rebalance transaction only when it would not fit otherwise,
+    /// setup only when `allow_prep`
+    pub fn assemble(
+        self,
+        allow_prep: bool,
+        fits: impl Fn(&[Instruction]) -> Result<bool>,
+    ) -> Result<AssembledTransactions> {
+        let budget = self.compute_budget_instructions.as_slice();
+        let setup = self.setup_instructions.as_slice();
+        let cleanup = self.cleanup_instructions.as_slice();
+        let mut core = vec![self.start_instruction];
+        core.extend(self.swap_instructions);
+        core.push(self.end_instruction);
+        let core = core.as_slice();
+
+        let single = [budget, setup, core, cleanup].concat();
+        if fits(&single)? {
+            return Ok(AssembledTransactions {
+                layout: TransactionLayout::Single,
+                prep_instructions: vec![],
+                instructions: single,
+                cleanup_instructions: vec![],
+            });
+        }
+        let with_setup = [budget, setup, core].concat();
+        if fits(&with_setup)? {
+            return Ok(AssembledTransactions {
+                layout: TransactionLayout::SeparateCleanup,
+                prep_instructions: vec![],
+                instructions: with_setup,
+                cleanup_instructions: cleanup.to_vec(),
+            });
+        }
+
+        if !allow_prep || setup.is_empty() {
+            return Err(TransactionAssemblerError::RebalanceTooLarge.into());
+        }
+        if !fits(setup)? {
+            return Err(TransactionAssemblerError::SetupTooLarge.into());
+        }
+        let with_cleanup = [budget, core, cleanup].concat();
+        if fits(&with_cleanup)? {
+            return Ok(AssembledTransactions {
+                layout: TransactionLayout::SeparatePrep,
+                prep_instructions: setup.to_vec(),
+                instructions: with_cleanup,
+                cleanup_instructions: vec![],
+            });
+        }
+        let rebalance = [budget, core].concat();
+        if fits(&rebalance)? {
+            return Ok(AssembledTransactions {
+                layout: TransactionLayout::SeparatePrepAndCleanup,
+                prep_instructions: setup.to_vec(),
+                instructions: rebalance,
+                cleanup_instructions: cleanup.to_vec(),
+            });
+        }
+        Err(TransactionAssemblerError::RebalanceTooLarge.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::pubkey::Pubkey;
+
+    use super::*;
+
+    fn ix(tag: u8) -> Instruction {
+        Instruction::new_with_bytes(Pubkey::default(), &[tag], vec![])
+    }
+
+    fn tags(instructions: &[Instruction]) -> Vec<u8> {
+        instructions.iter().map(|ix| ix.data[0]).collect()
+    }
+
+    /// budget 1, setup 2 3, start 4, swap 5, end 6, cleanup 7
+    fn rebalance_instructions() -> RebalanceInstructions {
+        RebalanceInstructions {
+            compute_budget_instructions: vec![ix(1)],
+            setup_instructions: vec![ix(2), ix(3)],
+            start_instruction: ix(4),
+            swap_instructions: vec![ix(5)],
+            end_instruction: ix(6),
+            cleanup_instructions: vec![ix(7)],
+        }
+    }
+
+    fn assemble(max_instructions: usize) -> Result<AssembledTransactions> {
+        rebalance_instructions().assemble(true, |ixs| Ok(ixs.len() <= max_instructions))
+    }
+
+    #[test]
+    fn test_assemble_single() {
+        let assembled = assemble(7).unwrap();
+        assert_eq!(assembled.layout, TransactionLayout::Single);
+        assert!(assembled.prep_instructions.is_empty());
+        assert_eq!(tags(&assembled.instructions), vec![1, 2, 3, 4, 5, 6, 7]);
+        assert!(assembled.cleanup_instructions.is_empty());
+    }
+
+    #[test]
+    fn test_assemble_separate_cleanup() {
+        let assembled = assemble(6).unwrap();
+        assert_eq!(assembled.layout, TransactionLayout::SeparateCleanup);
+        assert!(assembled.prep_instructions.is_empty());
+        assert_eq!(tags(&assembled.instructions), vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(tags(&assembled.cleanup_instructions), vec![7]);
+    }
+
+    #[test]
+    fn test_assemble_separate_prep() {
+        let assembled = assemble(5).unwrap();
+        assert_eq!(assembled.layout, TransactionLayout::SeparatePrep);
+        assert_eq!(tags(&assembled.prep_instructions), vec![2, 3]);
+        assert_eq!(tags(&assembled.instructions), vec![1, 4, 5, 6, 7]);
+        assert!(assembled.cleanup_instructions.is_empty());
+    }
+
+    #[test]
+    fn test_assemble_separate_prep_and_cleanup() {
+        let assembled = assemble(4).unwrap();
+        assert_eq!(assembled.layout, TransactionLayout::SeparatePrepAndCleanup);
+        assert_eq!(tags(&assembled.prep_instructions), vec![2, 3]);
+        assert_eq!(tags(&assembled.instructions), vec![1, 4, 5, 6]);
+        assert_eq!(tags(&assembled.cleanup_instructions), vec![7]);
+    }
+
+    #[test]
+    fn test_assemble_fail_on_too_large() {
+        assert_eq!(
+            assemble(3).err().unwrap().to_string(),
+            TransactionAssemblerError::RebalanceTooLarge.to_string()
+        );
+        assert_eq!(
+            assemble(1).err().unwrap().to_string(),
+            TransactionAssemblerError::SetupTooLarge.to_string()
+        );
+
+        let mut instructions = rebalance_instructions();
+        instructions.setup_instructions = vec![];
+        assert_eq!(
+            instructions
+                .assemble(true, |ixs| Ok(ixs.len() <= 3))
+                .err()
+                .unwrap()
+                .to_string(),
+            TransactionAssemblerError::RebalanceTooLarge.to_string()
+        );
+    }
+
+    #[test]
+    fn test_assemble_fail_on_too_large_without_prep() {
+        assert_eq!(
+            rebalance_instructions()
+                .assemble(false, |ixs| Ok(ixs.len() <= 5))
+                .err()
+                .unwrap()
+                .to_string(),
+            TransactionAssemblerError::RebalanceTooLarge.to_string()
+        );
+    }
+
+    #[test]
+    fn test_size_strategies() {
+        assert_eq!(
+            SIZE_STRATEGIES[0].route_constraints(),
+            RouteConstraints::default()
+        );
+        assert!(SIZE_STRATEGIES
+            .iter()
+            .all(|strategy| strategy.allows_prep() == (*strategy == SizeStrategy::SeparateSetup)));
+        assert!(SIZE_STRATEGIES[3].route_constraints().only_direct_routes);
+    }
+}
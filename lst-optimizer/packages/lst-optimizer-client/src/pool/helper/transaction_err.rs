@@ -1,8 +1,19 @@
 use controller_lib::calculator::typedefs::SControllerError;
+use lst_optimizer_std::types::{
+    asset_repository::AssetRepositoryError, rebalance_outcome::RebalanceFailure,
+};
 use lst_optimizer_utils::logger::error;
+use quoter_lib::aggregate_quoter::AggregateQuoterError;
 use rust_decimal::prelude::FromPrimitive;
 use solana_client::client_error::{ClientError, ClientErrorKind};
 use solana_sdk::transaction::TransactionError;
+use stakedex_lib::quoter::StakedexQuoterError;
+
+use super::{
+    delayed_unstake::DelayedUnstakeError, journal::JournalError, swap_loss_guard::SwapLossError,
+    tranche::TrancheError,
+};
+use crate::pool::rebalancable::RebalanceError;
 
 pub fn unhandled_error<E>(e: E)
 where
@@ -62,3 +73,103 @@ pub fn handle_error(e: anyhow::Error) {
         error!("Unhandled error: {}", e);
     }
 }
+
+/// The typed failure of a rebalance error, the errors of the rebalance itself are kept apart
+pub fn rebalance_failure(e: &anyhow::Error) -> RebalanceFailure {
+    if let Some(err) = e.downcast_ref::<RebalanceError>() {
+        return match err {
+            RebalanceError::FailedToSendTransaction(message) => {
+                RebalanceFailure::SendFailed(message.clone())
+            }
+            RebalanceError::TransactionFailed(signature, message) => {
+                RebalanceFailure::TransactionFailed(signature.clone(), message.clone())
+            }
+            RebalanceError::TransactionExpired(signature) => {
+                RebalanceFailure::TransactionExpired(signature.clone())
+            }
+        };
+    }
+    if let Some(SwapLossError::MaxLossExceeded(..)) = e.downcast_ref::<SwapLossError>() {
+        return RebalanceFailure::LossGuardRejected(e.to_string());
+    }
+    if let Some(TrancheError::StoppedEarly(_, _, swapped_tranches, message)) =
+        e.downcast_ref::<TrancheError>()
+    {
+        return RebalanceFailure::StoppedEarly(*swapped_tranches, message.clone());
+    }
+    if let Some(AggregateQuoterError::AllQuotersFailed(message)) =
+        e.downcast_ref::<AggregateQuoterError>()
+    {
+        return RebalanceFailure::NoRoute(message.clone());
+    }
+    if let Some(StakedexQuoterError::NoRoute(..)) = e.downcast_ref::<StakedexQuoterError>() {
+        return RebalanceFailure::NoRoute(e.to_string());
+    }
+    if e.downcast_ref::<AssetRepositoryError>().is_some() {
+        return RebalanceFailure::UnknownAsset(e.to_string());
+    }
+    if e.downcast_ref::<DelayedUnstakeError>().is_some() {
+        return RebalanceFailure::DelayedUnstakeFailed(e.to_string());
+    }
+    if e.downcast_ref::<JournalError>().is_some() {
+        return RebalanceFailure::JournalFailed(e.to_string());
+    }
+    RebalanceFailure::Other(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rebalance_failure() {
+        let e: anyhow::Error = RebalanceError::TransactionExpired("sig".to_string()).into();
+        assert_eq!(
+            rebalance_failure(&e),
+            RebalanceFailure::TransactionExpired("sig".to_string())
+        );
+
+        let e: anyhow::Error = SwapLossError::MaxLossExceeded(90, 1000, 100, 50).into();
+        assert_eq!(
+            rebalance_failure(&e),
+            RebalanceFailure::LossGuardRejected(e.to_string())
+        );
+
+        let e: anyhow::Error = TrancheError::StoppedEarly(40, 100, 2, "expired".to_string()).into();
+        assert_eq!(
+            rebalance_failure(&e),
+            RebalanceFailure::StoppedEarly(2, "expired".to_string())
+        );
+
+        let e: anyhow::Error =
+            AggregateQuoterError::AllQuotersFailed("q0: no route".to_string()).into();
+        assert_eq!(
+            rebalance_failure(&e),
+            RebalanceFailure::NoRoute("q0: no route".to_string())
+        );
+
+        let e: anyhow::Error = AssetRepositoryError::AssetMintNotFound("mint".to_string()).into();
+        assert_eq!(
+            rebalance_failure(&e),
+            RebalanceFailure::UnknownAsset(e.to_string())
+        );
+
+        let e: anyhow::Error = DelayedUnstakeError::InsufficientWsol(10, 20).into();
+        assert_eq!(
+            rebalance_failure(&e),
+            RebalanceFailure::DelayedUnstakeFailed(e.to_string())
+        );
+
+        let e: anyhow::Error = JournalError::UnknownStep(3).into();
+        assert_eq!(
+            rebalance_failure(&e),
+            RebalanceFailure::JournalFailed(e.to_string())
+        );
+
+        let e = anyhow::anyhow!("unexpected");
+        assert_eq!(
+            rebalance_failure(&e),
+            RebalanceFailure::Other("unexpected".to_string())
+        );
+    }
+}
@@ -2,4 +2,7 @@ pub mod allocable;
 pub mod helper;
 pub mod pool;
 pub mod rebalancable;
+pub mod rebalance_cost;
+pub mod recovery;
 pub mod typedefs;
+pub mod unstake;
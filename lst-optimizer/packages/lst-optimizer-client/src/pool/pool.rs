@@ -1,8 +1,17 @@
+use std::collections::HashMap;
+
+use super::helper::journal::RebalanceJournal;
+use super::helper::swap_loss_guard::SwapLossGuard;
 use super::typedefs::MaxPoolOptions;
 use anyhow::{Context as _AnyhowContext, Ok, Result};
+use controller_lib::calculator::{query::CalculatorQuery, typedefs::CalculatorType};
 use controller_lib::controller::ControllerClient;
 use controller_lib::Pubkey;
-use lst_optimizer_std::{pool::PoolError, types::pool_allocation::MAX_ALLOCATION_BPS};
+use lst_optimizer_std::{
+    allocator::{apportion::apportion, AllocationRatios},
+    pool::PoolError,
+    types::{context::Context, pool_allocation::MAX_ALLOCATION_BPS},
+};
 use quoter_lib::typedefs::QuoterClient;
 use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
@@ -29,7 +38,8 @@ impl MaxPool {
             controller_client: ControllerClient::new(RpcClient::new_with_commitment(
                 options.rpc_url.clone(),
                 CommitmentConfig::confirmed(),
-            )),
+            ))
+            .with_compute_budget(options.compute_budget.clone()),
             quoter_client,
         }
     }
@@ -50,6 +60,67 @@ impl MaxPool {
         &*self.quoter_client
     }
 
+    pub fn swap_loss_guard(&self) -> Result<Option<SwapLossGuard>> {
+        match self.options.max_loss_bps {
+            Some(max_loss_bps) => Ok(Some(SwapLossGuard::new(max_loss_bps)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn journal(&self) -> Option<RebalanceJournal> {
+        self.options
+            .journal_path
+            .as_ref()
+            .map(RebalanceJournal::new)
+    }
+
+    /// The fair amount of dst tokens for the src amount, valued in SOL by the calculators
+    pub async fn get_fair_out_amount(
+        &self,
+        context: &Context,
+        src_calculator_type: CalculatorType,
+        dst_calculator_type: CalculatorType,
+        amount: u64,
+    ) -> Result<u64> {
+        let controller = self.controller_client();
+        let lamports = controller
+            .convert_lst_to_sol(context.get_payer(), src_calculator_type, amount)
+            .await?
+            .get_min();
+        let fair_out = controller
+            .convert_sol_to_lst(context.get_payer(), dst_calculator_type, lamports)
+            .await?
+            .get_min();
+        Ok(fair_out)
+    }
+
+    /// SOL value of the returned dst amount minus the withdrawn src amount, by the calculators
+    pub async fn get_sol_value_delta(
+        &self,
+        context: &Context,
+        src_calculator_type: CalculatorType,
+        dst_calculator_type: CalculatorType,
+        withdrawn: u64,
+        returned: u64,
+    ) -> Result<i64> {
+        let controller = self.controller_client();
+        let mut withdrawn_lamports = 0;
+        if withdrawn > 0 {
+            withdrawn_lamports = controller
+                .convert_lst_to_sol(context.get_payer(), src_calculator_type, withdrawn)
+                .await?
+                .get_min();
+        }
+        let mut returned_lamports = 0;
+        if returned > 0 {
+            returned_lamports = controller
+                .convert_lst_to_sol(context.get_payer(), dst_calculator_type, returned)
+                .await?
+                .get_min();
+        }
+        Ok(returned_lamports as i64 - withdrawn_lamports as i64)
+    }
+
     pub fn calculate_lamports_from_bps(
         &self,
         total_lamports: u64,
@@ -73,11 +144,31 @@ impl MaxPool {
                 symbol_bps,
             ))?)
     }
+
+    /// Distribute the total lamports between the allocation ratios, the target lamports
+    /// per mint always sum exactly to the total lamports
+    pub fn calculate_lamports_from_allocation_ratios(
+        &self,
+        total_lamports: u64,
+        allocation_ratios: &AllocationRatios,
+    ) -> Result<HashMap<String, u64>> {
+        let ratios = &allocation_ratios.asset_alloc_ratios;
+        let bps: Vec<Decimal> = ratios.iter().map(|ratio| ratio.bps).collect();
+        let target_lamports = apportion(&bps, total_lamports)
+            .context(PoolError::FailedToCalculateAllocationChanges)?;
+
+        let mut target_lamports_per_symbol: HashMap<String, u64> = HashMap::new();
+        for (ratio, lamports) in ratios.iter().zip(target_lamports) {
+            target_lamports_per_symbol.insert(ratio.mint.to_owned(), lamports);
+        }
+        Ok(target_lamports_per_symbol)
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
+    use lst_optimizer_std::allocator::AllocationRatio;
     use quoter_lib::mock_quoter::MockQuoterClient;
 
     use super::*;
@@ -121,4 +212,26 @@ mod tests {
             .unwrap();
         assert_eq!(target_lamports, 0);
     }
+
+    #[tokio::test]
+    async fn test_calculate_lamports_from_allocation_ratios_sum_to_total() {
+        let pool = MaxPool::new(
+            Pubkey::new_unique(),
+            Box::new(MockQuoterClient::new()),
+            MaxPoolOptions::default(),
+        );
+        let allocation_ratios = AllocationRatios::new(vec![
+            AllocationRatio::new("jupsol", 3334),
+            AllocationRatio::new("inf", 3333),
+            AllocationRatio::new("jitosol", 3333),
+        ]);
+        let total_lamports = 1_000_000_001;
+        let target_lamports = pool
+            .calculate_lamports_from_allocation_ratios(total_lamports, &allocation_ratios)
+            .unwrap();
+        assert_eq!(target_lamports.values().sum::<u64>(), total_lamports);
+        assert_eq!(target_lamports["jupsol"], 333_400_001);
+        assert_eq!(target_lamports["inf"], 333_300_000);
+        assert_eq!(target_lamports["jitosol"], 333_300_000);
+    }
 }
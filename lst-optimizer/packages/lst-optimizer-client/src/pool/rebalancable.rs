@@ -1,77 +1,156 @@
 use anyhow::Result;
-use controller_lib::{rebalance::RebalancingInstructions, state::PoolQuery, Pubkey};
+use controller_lib::{
+    rebalance::{RebalancingInstructions, StartRebalanceBounds},
+    state::PoolQuery,
+    transaction::TransactionOutcome,
+    Pubkey,
+};
 use log::{info, warn};
 use lst_optimizer_std::{
     pool::PoolRebalancable,
-    types::{context::Context, pool_allocation_changes::PoolAssetChange},
+    types::{
+        context::Context,
+        netted_change::NettedChange,
+        pool_allocation_changes::PoolAssetChange,
+        rebalance_outcome::{RebalanceOutcome, RebalanceRoute},
+    },
+};
+use quoter_lib::typedefs::{Quote, QuoterClient, RouteConstraints, SwapMode};
+use solana_sdk::{
+    address_lookup_table::AddressLookupTableAccount, instruction::Instruction, signature::Signature,
 };
-use solana_sdk::instruction::Instruction;
 use spl_associated_token_account::instruction::create_associated_token_account_idempotent;
 use spl_helper::{mint::MintAccountQuery, token_account::TokenAccountQuery};
+use spl_token::native_mint;
+use stakedex_lib::quoter::StakedexQuoterClient;
+use thiserror::Error;
 
 use crate::pool::helper::{
-    pool_asset_change_route::{PoolAssetChangeRoute, PoolAssetChangeRouter},
-    transaction_err::handle_error,
+    journal::TransactionKind,
+    pool_asset_change_route::{NettedChangeRouter, PoolAssetChangeRoute, PoolAssetChangeRouter},
+    swap_loss_guard::SwapLossGuard,
+    tranche::{price_impact, TrancheError, TrancheOptions},
+    transaction_assembler::{
+        AssembledTransactions, RebalanceInstructions, SizeStrategy, TransactionAssemblerError,
+        TransactionLayout, SIZE_STRATEGIES,
+    },
+    transaction_err::{handle_error, handle_transaction_error, rebalance_failure},
 };
+use crate::typedefs::pool_to_stakedex_pool;
 
 use super::pool::MaxPool;
 
-#[async_trait::async_trait]
-impl PoolRebalancable for MaxPool {
-    async fn rebalance_asset(
+/// The quotes sizing a tranche, the price impact is not exactly linear in the amount
+const MAX_TRANCHE_QUOTES: u8 = 3;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum RebalanceError {
+    #[error("Failed to send the rebalance transaction: {0}")]
+    FailedToSendTransaction(String),
+
+    #[error("Rebalance transaction {0} failed: {1}")]
+    TransactionFailed(String, String),
+
+    #[error("Rebalance transaction {0} expired before landing")]
+    TransactionExpired(String),
+}
+
+/// The transactions rebalancing a pool asset change, sent in order
+pub struct RebalanceTransaction {
+    pub strategy: SizeStrategy,
+    pub layout: TransactionLayout,
+    pub prep_instructions: Vec<Instruction>,
+    pub instructions: Vec<Instruction>,
+    pub cleanup_instructions: Vec<Instruction>,
+    pub address_lookup_table_accounts: Vec<AddressLookupTableAccount>,
+    pub quote: Option<Quote>,
+}
+
+impl MaxPool {
+    /// Build the rebalance instructions of the change without sending them,
+    /// none when the change does not need any swap
+    pub async fn build_rebalance_transaction(
         &self,
         context: &Context,
         pool_asset_change: &PoolAssetChange,
-    ) -> Result<()> {
+    ) -> Result<Option<RebalanceTransaction>> {
         let asset = context.get_known_asset_from_mint(&pool_asset_change.mint)?;
-        info!("Rebalancing asset: {}", asset.symbol);
+        let route = pool_asset_change.get_route(&asset)?;
+        self.build_route_transaction(context, &route).await
+    }
 
+    /// Build the rebalance instructions swapping along the route without sending them,
+    /// none when the route does not need any swap
+    pub async fn build_route_transaction(
+        &self,
+        context: &Context,
+        route: &PoolAssetChangeRoute,
+    ) -> Result<Option<RebalanceTransaction>> {
         let PoolAssetChangeRoute {
             src_mint,
             dst_mint,
             src_cal,
             dst_cal,
             amount,
-        } = pool_asset_change.get_route(&asset)?;
+        } = route.clone();
 
         if src_mint.eq(&dst_mint) {
-            warn!("The source and destination mints are the same, no rebalance needed");
-            return Ok(());
+            return Ok(None);
         }
 
         let payer: Pubkey = context.get_payer_pubkey();
         let controller = self.controller_client();
-        let rpc = controller.rpc_client();
         let quoter_client = self.quoter_client();
         let pool_program_id = self.program_id();
 
         let reserves_ata = controller
             .get_pool_reserves_address_by_mint(&pool_program_id, &dst_mint)
             .await?;
-        let swap_ixs = quoter_client
-            .create_swap_instructions(&payer, &reserves_ata, &src_mint, &dst_mint, amount, 0, None)
-            .await?;
-        let address_lookup_table_accs = quoter_client
-            .resolve_address_lookup_table_accounts(swap_ixs.address_lookup_tables)
-            .await?;
 
-        // rebalance instructions
-        let mut instructions: Vec<Instruction> = vec![];
+        // Deposit into the stake pool instead of buying the lst when it yields more
+        if let Some(tx) = self
+            .build_stake_deposit_transaction(context, route, &reserves_ata)
+            .await?
+        {
+            return Ok(Some(tx));
+        }
+        // Likewise unstake the lst instead of selling it
+        if let Some(tx) = self
+            .build_instant_unstake_transaction(context, route, &reserves_ata)
+            .await?
+        {
+            return Ok(Some(tx));
+        }
 
-        let mint_program_id = src_mint.get_mint_owner(rpc).await?;
-        let src_ata = src_mint
-            .get_associated_token_account_with_program_id(&payer, &mint_program_id)
-            .await?;
-        let src_ata_acc = rpc.get_account(&src_ata).await;
-        if src_ata_acc.is_err() {
-            instructions.push(create_associated_token_account_idempotent(
-                &payer,
-                &payer,
-                &src_mint,
-                &mint_program_id,
-            ));
+        // Bound the swap and the starting reserves by the max loss against the fair value
+        let swap_loss_guard = self.swap_loss_guard()?;
+        let mut fair_out: u64 = 0;
+        let mut min_amount_out: u64 = 0;
+        let mut slippage_bps: Option<u16> = None;
+        let mut bounds = StartRebalanceBounds::default();
+        if let Some(guard) = &swap_loss_guard {
+            let quote = quoter_client
+                .quote(&src_mint, &dst_mint, amount, SwapMode::ExactIn)
+                .await?;
+            fair_out = self
+                .get_fair_out_amount(context, src_cal.clone(), dst_cal.clone(), amount)
+                .await?;
+            guard.check(fair_out, quote.out_amount)?;
+
+            min_amount_out = guard.min_out_amount(fair_out);
+            slippage_bps = Some(guard.slippage_bps(fair_out, quote.out_amount));
+
+            bounds = self
+                .get_start_rebalance_bounds(guard, &src_mint, &reserves_ata)
+                .await?;
+            info!(
+                "Quoted {} for a fair value of {}, min out {}, slippage {:?} bps, {:?}",
+                quote.out_amount, fair_out, min_amount_out, slippage_bps, bounds
+            );
         }
 
+        let (src_ata, ata_instructions) = self.get_src_ata_instructions(&payer, &src_mint).await?;
+
         let start_ix = controller
             .create_start_rebalance_instruction(
                 &pool_program_id,
@@ -81,46 +160,832 @@ impl PoolRebalancable for MaxPool {
                 src_cal.clone(),
                 dst_cal.clone(),
                 amount,
+                bounds,
+            )
+            .await?;
+        let end_ix = controller
+            .create_end_rebalance_instruction_from_start(&start_ix)
+            .await?;
+
+        // Constrain the route, then split the setup out, until the rebalance fits
+        let mut size_err: anyhow::Error = TransactionAssemblerError::RebalanceTooLarge.into();
+        for strategy in SIZE_STRATEGIES {
+            let attempt = async {
+                let swap_ixs = quoter_client
+                    .create_swap_instructions(
+                        &payer,
+                        &reserves_ata,
+                        &src_mint,
+                        &dst_mint,
+                        amount,
+                        min_amount_out,
+                        slippage_bps,
+                        strategy.route_constraints(),
+                    )
+                    .await?;
+                // A fresh quote may build the instructions, its worst fill must hold the guard too
+                if let (Some(guard), Some(quote)) = (&swap_loss_guard, &swap_ixs.quote) {
+                    guard.check(fair_out, quote.min_out_amount)?;
+                }
+                let address_lookup_table_accs = quoter_client
+                    .resolve_address_lookup_table_accounts(swap_ixs.address_lookup_tables)
+                    .await?;
+
+                // The other instructions of the route set it up, they run before the swap
+                let mut setup_instructions = ata_instructions.clone();
+                setup_instructions.extend(swap_ixs.setup_instructions);
+                setup_instructions.extend(swap_ixs.other_instructions);
+
+                let AssembledTransactions {
+                    layout,
+                    prep_instructions,
+                    instructions,
+                    cleanup_instructions,
+                } = RebalanceInstructions {
+                    compute_budget_instructions: swap_ixs.compute_budget_instructions,
+                    setup_instructions,
+                    start_instruction: start_ix.clone(),
+                    swap_instructions: swap_ixs.swap_instructions,
+                    end_instruction: end_ix.clone(),
+                    cleanup_instructions: swap_ixs.cleanup_instructions,
+                }
+                .assemble(strategy.allows_prep(), |instructions| {
+                    controller.fits_in_transaction(&payer, instructions, &address_lookup_table_accs)
+                })?;
+
+                Ok::<RebalanceTransaction, anyhow::Error>(RebalanceTransaction {
+                    strategy,
+                    layout,
+                    prep_instructions,
+                    instructions,
+                    cleanup_instructions,
+                    address_lookup_table_accounts: address_lookup_table_accs,
+                    quote: swap_ixs.quote,
+                })
+            }
+            .await;
+
+            match attempt {
+                Ok(tx) => {
+                    info!(
+                        "Rebalance fits with the {:?} strategy in the {:?} layout",
+                        strategy, tx.layout
+                    );
+                    return Ok(Some(tx));
+                }
+                Err(e) if e.downcast_ref::<TransactionAssemblerError>().is_some() => {
+                    warn!("Rebalance with the {:?} strategy: {}", strategy, e);
+                    size_err = e;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(size_err)
+    }
+
+    /// Build the rebalance depositing the wSOL into the stake pool of the increased lst
+    /// through stakedex, none when the pool takes no SOL deposit or the best swap quote
+    /// yields more
+    async fn build_stake_deposit_transaction(
+        &self,
+        context: &Context,
+        route: &PoolAssetChangeRoute,
+        reserves_ata: &Pubkey,
+    ) -> Result<Option<RebalanceTransaction>> {
+        let Some((stakedex, deposit_quote)) = self.quote_stake_deposit(context, route).await?
+        else {
+            return Ok(None);
+        };
+
+        let payer = context.get_payer_pubkey();
+        let (src_ata, ata_instructions) = self
+            .get_src_ata_instructions(&payer, &route.src_mint)
+            .await?;
+        let swap_ixs = stakedex
+            .create_swap_instructions(
+                &payer,
+                reserves_ata,
+                &route.src_mint,
+                &route.dst_mint,
+                route.amount,
+                deposit_quote.out_amount,
+                None,
+                RouteConstraints::default(),
+            )
+            .await?;
+        let tx = self
+            .build_venue_transaction(
+                context,
+                route,
+                reserves_ata,
+                &src_ata,
+                ata_instructions,
+                swap_ixs.swap_instructions,
+                deposit_quote,
+            )
+            .await?;
+        Ok(Some(tx))
+    }
+
+    /// The stakedex client depositing the wSOL of the route into the stake pool of the lst
+    /// and its quote, none when the lst has no SPL stake pool, the pool takes no SOL deposit
+    /// or the best swap quote yields more
+    async fn quote_stake_deposit(
+        &self,
+        context: &Context,
+        route: &PoolAssetChangeRoute,
+    ) -> Result<Option<(StakedexQuoterClient, Quote)>> {
+        if !route.src_mint.eq(&native_mint::ID) {
+            return Ok(None);
+        }
+        let asset = context.get_known_asset_from_mint(&route.dst_mint.to_string())?;
+        let Some(pool) = pool_to_stakedex_pool(&asset)? else {
+            return Ok(None);
+        };
+        let stakedex = StakedexQuoterClient::new(&self.pool_options().rpc_url)
+            .with_stake_pool(&route.dst_mint, pool);
+
+        let mut deposit_quote = match stakedex
+            .quote(
+                &route.src_mint,
+                &route.dst_mint,
+                route.amount,
+                SwapMode::ExactIn,
+            )
+            .await
+        {
+            Ok(quote) => quote,
+            Err(e) => {
+                info!("No stake deposit into {}: {}", route.dst_mint, e);
+                return Ok(None);
+            }
+        };
+        let quote = self
+            .quoter_client()
+            .quote(
+                &route.src_mint,
+                &route.dst_mint,
+                route.amount,
+                SwapMode::ExactIn,
+            )
+            .await;
+        match quote {
+            Ok(quote) if quote.out_amount >= deposit_quote.out_amount => return Ok(None),
+            Ok(quote) => info!(
+                "Stake deposit yields {} against the quoted {}",
+                deposit_quote.out_amount, quote.out_amount
+            ),
+            // The deposit does not depend on the quoter
+            Err(e) => warn!("Failed to quote the swap, depositing instead: {}", e),
+        }
+
+        // The deposit fee is the price impact of the deposit against the fair value
+        let fair_out = self
+            .get_fair_out_amount(
+                context,
+                route.src_cal.clone(),
+                route.dst_cal.clone(),
+                route.amount,
+            )
+            .await?;
+        deposit_quote.price_impact_pct = price_impact(fair_out, deposit_quote.out_amount);
+        Ok(Some((stakedex, deposit_quote)))
+    }
+
+    /// Build the rebalance withdrawing stake from the stake pool of the decreased lst and
+    /// instantly unstaking it through stakedex, none when the lst has no SPL stake pool,
+    /// the unstake is unavailable or the best swap quote yields more
+    async fn build_instant_unstake_transaction(
+        &self,
+        context: &Context,
+        route: &PoolAssetChangeRoute,
+        reserves_ata: &Pubkey,
+    ) -> Result<Option<RebalanceTransaction>> {
+        let Some((stakedex, unstake_quote)) = self.quote_instant_unstake(context, route).await?
+        else {
+            return Ok(None);
+        };
+
+        let payer = context.get_payer_pubkey();
+        let (src_ata, ata_instructions) = self
+            .get_src_ata_instructions(&payer, &route.src_mint)
+            .await?;
+        let swap_ixs = stakedex
+            .create_swap_instructions(
+                &payer,
+                reserves_ata,
+                &route.src_mint,
+                &route.dst_mint,
+                route.amount,
+                unstake_quote.out_amount,
+                None,
+                RouteConstraints::default(),
+            )
+            .await?;
+        let tx = self
+            .build_venue_transaction(
+                context,
+                route,
+                reserves_ata,
+                &src_ata,
+                ata_instructions,
+                swap_ixs.swap_instructions,
+                swap_ixs.quote.unwrap_or(unstake_quote),
+            )
+            .await?;
+        Ok(Some(tx))
+    }
+
+    /// The stakedex client instantly unstaking the lst of the route and its quote, none when
+    /// the lst has no SPL stake pool, the unstake is unavailable or the best swap quote
+    /// yields more
+    async fn quote_instant_unstake(
+        &self,
+        context: &Context,
+        route: &PoolAssetChangeRoute,
+    ) -> Result<Option<(StakedexQuoterClient, Quote)>> {
+        if !route.dst_mint.eq(&native_mint::ID) {
+            return Ok(None);
+        }
+        let asset = context.get_known_asset_from_mint(&route.src_mint.to_string())?;
+        let Some(pool) = pool_to_stakedex_pool(&asset)? else {
+            return Ok(None);
+        };
+        let stakedex = StakedexQuoterClient::new(&self.pool_options().rpc_url)
+            .with_stake_pool(&route.src_mint, pool);
+
+        let unstake_quote = match stakedex
+            .quote(
+                &route.src_mint,
+                &route.dst_mint,
+                route.amount,
+                SwapMode::ExactIn,
+            )
+            .await
+        {
+            Ok(quote) => quote,
+            Err(e) => {
+                info!("No instant unstake of {}: {}", route.src_mint, e);
+                return Ok(None);
+            }
+        };
+        let quote = self
+            .quoter_client()
+            .quote(
+                &route.src_mint,
+                &route.dst_mint,
+                route.amount,
+                SwapMode::ExactIn,
+            )
+            .await;
+        match quote {
+            Ok(quote) if quote.out_amount >= unstake_quote.out_amount => return Ok(None),
+            Ok(quote) => info!(
+                "Instant unstake yields {} against the quoted {}",
+                unstake_quote.out_amount, quote.out_amount
+            ),
+            // The unstake does not depend on the quoter
+            Err(e) => warn!("Failed to quote the swap, unstaking instead: {}", e),
+        }
+        Ok(Some((stakedex, unstake_quote)))
+    }
+
+    /// The quote of the venue the route is rebalanced through, the stake deposit or the
+    /// instant unstake when they yield more than the best swap
+    async fn quote_route(&self, context: &Context, route: &PoolAssetChangeRoute) -> Result<Quote> {
+        if let Some((_, quote)) = self.quote_stake_deposit(context, route).await? {
+            return Ok(quote);
+        }
+        if let Some((_, quote)) = self.quote_instant_unstake(context, route).await? {
+            return Ok(quote);
+        }
+        self.quoter_client()
+            .quote(
+                &route.src_mint,
+                &route.dst_mint,
+                route.amount,
+                SwapMode::ExactIn,
+            )
+            .await
+    }
+
+    /// Build the rebalance running the instructions of a venue with an exact out amount
+    /// in place of the swap, held to the max loss against the fair value like a swap
+    async fn build_venue_transaction(
+        &self,
+        context: &Context,
+        route: &PoolAssetChangeRoute,
+        reserves_ata: &Pubkey,
+        src_ata: &Pubkey,
+        setup_instructions: Vec<Instruction>,
+        venue_instructions: Vec<Instruction>,
+        quote: Quote,
+    ) -> Result<RebalanceTransaction> {
+        let controller = self.controller_client();
+        let bounds = match self.swap_loss_guard()? {
+            Some(guard) => {
+                let fair_out = self
+                    .get_fair_out_amount(
+                        context,
+                        route.src_cal.clone(),
+                        route.dst_cal.clone(),
+                        route.amount,
+                    )
+                    .await?;
+                guard.check(fair_out, quote.min_out_amount)?;
+                self.get_start_rebalance_bounds(&guard, &route.src_mint, reserves_ata)
+                    .await?
+            }
+            None => StartRebalanceBounds::default(),
+        };
+
+        let payer = context.get_payer_pubkey();
+        let start_ix = controller
+            .create_start_rebalance_instruction(
+                &self.program_id(),
+                src_ata,
+                &route.src_mint,
+                &route.dst_mint,
+                route.src_cal.clone(),
+                route.dst_cal.clone(),
+                route.amount,
+                bounds,
             )
             .await?;
         let end_ix = controller
             .create_end_rebalance_instruction_from_start(&start_ix)
             .await?;
 
-        instructions.push(start_ix);
-        instructions.extend(swap_ixs.swap_instructions);
-        instructions.push(end_ix);
+        let AssembledTransactions {
+            layout,
+            prep_instructions,
+            instructions,
+            cleanup_instructions,
+        } = RebalanceInstructions {
+            compute_budget_instructions: vec![],
+            setup_instructions,
+            start_instruction: start_ix,
+            swap_instructions: venue_instructions,
+            end_instruction: end_ix,
+            cleanup_instructions: vec![],
+        }
+        .assemble(true, |instructions| {
+            controller.fits_in_transaction(&payer, instructions, &[])
+        })?;
+
+        Ok(RebalanceTransaction {
+            strategy: SizeStrategy::Unconstrained,
+            layout,
+            prep_instructions,
+            instructions,
+            cleanup_instructions,
+            address_lookup_table_accounts: vec![],
+            quote: Some(quote),
+        })
+    }
+
+    /// The least src and the most dst reserves the rebalance may start with
+    pub async fn get_start_rebalance_bounds(
+        &self,
+        guard: &SwapLossGuard,
+        src_mint: &Pubkey,
+        dst_reserves_ata: &Pubkey,
+    ) -> Result<StartRebalanceBounds> {
+        let controller = self.controller_client();
+        let src_reserves_ata = controller
+            .get_pool_reserves_address_by_mint(&self.program_id(), src_mint)
+            .await?;
+        let src_reserves = controller
+            .get_pool_reserves_account(&src_reserves_ata)
+            .await?;
+        let dst_reserves = controller
+            .get_pool_reserves_account(dst_reserves_ata)
+            .await?;
+        Ok(StartRebalanceBounds {
+            min_starting_src_lst: guard.min_starting_src_lst(src_reserves.amount),
+            max_starting_dst_lst: guard.max_starting_dst_lst(dst_reserves.amount),
+        })
+    }
+
+    /// The payer src token account the rebalance withdraws to, and its creation when missing
+    pub async fn get_src_ata_instructions(
+        &self,
+        payer: &Pubkey,
+        src_mint: &Pubkey,
+    ) -> Result<(Pubkey, Vec<Instruction>)> {
+        let rpc = self.controller_client().rpc_client();
+        let mint_program_id = src_mint.get_mint_owner(rpc).await?;
+        let src_ata = src_mint
+            .get_associated_token_account_with_program_id(payer, &mint_program_id)
+            .await?;
+        let mut instructions: Vec<Instruction> = vec![];
+        if rpc.get_account(&src_ata).await.is_err() {
+            instructions.push(create_associated_token_account_idempotent(
+                payer,
+                payer,
+                src_mint,
+                &mint_program_id,
+            ));
+        }
+        Ok((src_ata, instructions))
+    }
+
+    /// Rebalance along the route, the outcome tells what landed and the SOL value it
+    /// realized against the calculators fair value
+    async fn rebalance_route(
+        &self,
+        context: &Context,
+        route: &PoolAssetChangeRoute,
+    ) -> RebalanceOutcome {
+        let mut outcome = RebalanceOutcome::new(RebalanceRoute::new(
+            &route.src_mint.to_string(),
+            &route.dst_mint.to_string(),
+        ));
+        if let Err(e) = self.try_rebalance_route(context, route, &mut outcome).await {
+            outcome.error = Some(rebalance_failure(&e));
+        }
+        if outcome.withdrawn > 0 || outcome.returned > 0 {
+            match self
+                .get_sol_value_delta(
+                    context,
+                    route.src_cal.clone(),
+                    route.dst_cal.clone(),
+                    outcome.withdrawn,
+                    outcome.returned,
+                )
+                .await
+            {
+                Ok(sol_value_delta) => outcome.sol_value_delta = sol_value_delta,
+                Err(e) => warn!("Failed to value the rebalance: {}", e),
+            }
+        }
+        outcome
+    }
+
+    /// Rebalance along the route, in tranches when the pool enables them, or unstaked over
+    /// an epoch when the asset opted in and the loss guard rejects the sale
+    async fn try_rebalance_route(
+        &self,
+        context: &Context,
+        route: &PoolAssetChangeRoute,
+        outcome: &mut RebalanceOutcome,
+    ) -> Result<()> {
+        if self.should_delay_unstake(context, route).await? {
+            return self.start_delayed_unstake(context, route, outcome).await;
+        }
+        match &self.pool_options().tranche {
+            Some(options) => {
+                self.rebalance_route_in_tranches(context, route, options, outcome)
+                    .await
+            }
+            None => self.send_route_transactions(context, route, outcome).await,
+        }
+    }
+
+    /// Swap the route in tranches under the max price impact, one after the other.
+    /// Every tranche is quoted again and checked by the loss guard, the rebalance stops
+    /// once the market moves beyond it.
+    async fn rebalance_route_in_tranches(
+        &self,
+        context: &Context,
+        route: &PoolAssetChangeRoute,
+        options: &TrancheOptions,
+        outcome: &mut RebalanceOutcome,
+    ) -> Result<()> {
+        let mut remaining = route.amount;
+        let mut swapped_tranches: u16 = 0;
+        while remaining > 0 {
+            let amount = self
+                .quote_tranche_amount(context, route, remaining, swapped_tranches, options)
+                .await?;
+            info!(
+                "Tranche {}: swapping {} of the remaining {}",
+                swapped_tranches + 1,
+                amount,
+                remaining
+            );
+
+            let tranche = PoolAssetChangeRoute {
+                amount,
+                ..route.clone()
+            };
+            if let Err(e) = self
+                .send_route_transactions(context, &tranche, outcome)
+                .await
+            {
+                if swapped_tranches == 0 {
+                    return Err(e);
+                }
+                return Err(TrancheError::StoppedEarly(
+                    route.amount - remaining,
+                    route.amount,
+                    swapped_tranches,
+                    e.to_string(),
+                )
+                .into());
+            }
+
+            remaining -= amount;
+            swapped_tranches += 1;
+            if remaining > 0 {
+                tokio::time::sleep(options.delay).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// The largest amount of the remaining one quoted under the max price impact,
+    /// by the venue the tranche of that amount is rebalanced through
+    async fn quote_tranche_amount(
+        &self,
+        context: &Context,
+        route: &PoolAssetChangeRoute,
+        remaining: u64,
+        swapped_tranches: u16,
+        options: &TrancheOptions,
+    ) -> Result<u64> {
+        let min_amount = options.min_tranche_amount(remaining, swapped_tranches);
+        let mut amount = remaining;
+        for _ in 0..MAX_TRANCHE_QUOTES {
+            let tranche = PoolAssetChangeRoute {
+                amount,
+                ..route.clone()
+            };
+            let quote = self.quote_route(context, &tranche).await?;
+            let scaled = options.scale_tranche_amount(amount, quote.price_impact_pct, min_amount);
+            if scaled == amount {
+                break;
+            }
+            amount = scaled;
+        }
+        Ok(amount)
+    }
+
+    /// Send the rebalance transactions of the route in order, each one landing before the next
+    async fn send_route_transactions(
+        &self,
+        context: &Context,
+        route: &PoolAssetChangeRoute,
+        outcome: &mut RebalanceOutcome,
+    ) -> Result<()> {
+        let Some(RebalanceTransaction {
+            prep_instructions,
+            instructions,
+            cleanup_instructions,
+            address_lookup_table_accounts: address_lookup_table_accs,
+            quote,
+            ..
+        }) = self.build_route_transaction(context, route).await?
+        else {
+            warn!("The source and destination mints are the same, no rebalance needed");
+            return Ok(());
+        };
+        if let (Some(quote), Some(outcome_route)) = (&quote, &mut outcome.route) {
+            outcome_route.add_labels(&quote.route_labels);
+        }
+
+        if !prep_instructions.is_empty() {
+            info!("Invoking setup instructions");
+            self.send_recorded_instructions(
+                context,
+                &prep_instructions,
+                &address_lookup_table_accs,
+                TransactionKind::Prep,
+                outcome,
+            )
+            .await?;
+        }
 
+        // Journaled ahead so a crash after the rebalance lands still gets it sent
+        let journal = self.journal();
+        if let Some(journal) = &journal {
+            journal.record_pending_cleanup(&cleanup_instructions)?;
+        }
         info!("Invoking rebalance instructions");
-        let ret = controller
-            .invoke_instructions(
+        self.send_recorded_instructions(
+            context,
+            &instructions,
+            &address_lookup_table_accs,
+            TransactionKind::Rebalance,
+            outcome,
+        )
+        .await?;
+
+        // The rebalance landed, a failed cleanup only leaves accounts to close
+        if !cleanup_instructions.is_empty() {
+            info!("Invoking cleanup instructions");
+            if let Err(e) = self
+                .send_recorded_instructions(
+                    context,
+                    &cleanup_instructions,
+                    &address_lookup_table_accs,
+                    TransactionKind::Cleanup,
+                    outcome,
+                )
+                .await
+            {
+                warn!("Cleanup failed: {}", e);
+            }
+        }
+        if let Some(journal) = &journal {
+            journal.record_pending_cleanup(&[])?;
+        }
+        Ok(())
+    }
+
+    /// Send the instructions and wait for them to land, every signed attempt is journaled
+    /// as the kind of transaction before it is sent
+    pub async fn send_rebalance_instructions(
+        &self,
+        context: &Context,
+        instructions: &[Instruction],
+        address_lookup_table_accounts: &[AddressLookupTableAccount],
+        kind: TransactionKind,
+    ) -> Result<Signature> {
+        self.send_rebalance_instructions_with_hook(
+            context,
+            instructions,
+            address_lookup_table_accounts,
+            kind,
+            &|_, _| Ok(()),
+        )
+        .await
+    }
+
+    /// Send the instructions as `send_rebalance_instructions`, every signed attempt is also
+    /// passed to the hook before it is sent
+    pub async fn send_rebalance_instructions_with_hook(
+        &self,
+        context: &Context,
+        instructions: &[Instruction],
+        address_lookup_table_accounts: &[AddressLookupTableAccount],
+        kind: TransactionKind,
+        on_signed: &(dyn Fn(&Signature, u64) -> Result<()> + Sync),
+    ) -> Result<Signature> {
+        let journal = self.journal();
+        let ret = self
+            .controller_client()
+            .send_and_confirm_instructions_with_hook(
                 context.get_payer(),
-                &instructions,
-                &address_lookup_table_accs,
+                instructions,
+                address_lookup_table_accounts,
+                &self.pool_options().confirm,
+                &|signature, last_valid_block_height| {
+                    if let Some(journal) = &journal {
+                        journal.record_transaction(kind, signature, last_valid_block_height)?;
+                    }
+                    on_signed(signature, last_valid_block_height)
+                },
             )
             .await;
         match ret {
+            Ok(TransactionOutcome::Landed { signature, slot }) => {
+                info!("Transaction {} landed in slot {}", signature, slot);
+                Ok(signature)
+            }
+            Ok(TransactionOutcome::Failed { signature, error }) => {
+                handle_transaction_error(&error);
+                Err(
+                    RebalanceError::TransactionFailed(signature.to_string(), error.to_string())
+                        .into(),
+                )
+            }
+            Ok(TransactionOutcome::Expired { signature }) => {
+                Err(RebalanceError::TransactionExpired(signature.to_string()).into())
+            }
+            Err(e) => {
+                let message = e.to_string();
+                handle_error(e);
+                Err(RebalanceError::FailedToSendTransaction(message).into())
+            }
+        }
+    }
+
+    /// Send the instructions as `send_rebalance_instructions`, the signature, fee and pool
+    /// reserves changes of the transaction are recorded in the outcome once it landed
+    pub async fn send_recorded_instructions(
+        &self,
+        context: &Context,
+        instructions: &[Instruction],
+        address_lookup_table_accounts: &[AddressLookupTableAccount],
+        kind: TransactionKind,
+        outcome: &mut RebalanceOutcome,
+    ) -> Result<()> {
+        self.send_recorded_instructions_with_hook(
+            context,
+            instructions,
+            address_lookup_table_accounts,
+            kind,
+            outcome,
+            &|_, _| Ok(()),
+        )
+        .await
+    }
+
+    /// Send the instructions as `send_recorded_instructions`, every signed attempt is also
+    /// passed to the hook before it is sent
+    pub async fn send_recorded_instructions_with_hook(
+        &self,
+        context: &Context,
+        instructions: &[Instruction],
+        address_lookup_table_accounts: &[AddressLookupTableAccount],
+        kind: TransactionKind,
+        outcome: &mut RebalanceOutcome,
+        on_signed: &(dyn Fn(&Signature, u64) -> Result<()> + Sync),
+    ) -> Result<()> {
+        let ret = self
+            .send_rebalance_instructions_with_hook(
+                context,
+                instructions,
+                address_lookup_table_accounts,
+                kind,
+                on_signed,
+            )
+            .await;
+        // A transaction failing on chain still pays its fee
+        let signature = match &ret {
             Ok(signature) => {
-                info!("Rebalance invoked with signature: {}", signature);
-
-                if swap_ixs.cleanup_instructions.len() > 0 {
-                    info!("Invoking cleanup instructions");
-                    let ret = controller
-                        .invoke_instructions(
-                            context.get_payer(),
-                            &swap_ixs.cleanup_instructions,
-                            &address_lookup_table_accs,
-                        )
-                        .await;
-                    match ret {
-                        Ok(signature) => info!("Cleanup invoked with signature: {}", signature),
-                        Err(e) => handle_error(e),
-                    }
-                }
+                outcome.signatures.push(signature.to_string());
+                Some(*signature)
+            }
+            Err(e) => match e.downcast_ref::<RebalanceError>() {
+                Some(RebalanceError::TransactionFailed(signature, _)) => signature.parse().ok(),
+                _ => None,
+            },
+        };
+        if let Some(signature) = signature {
+            if let Err(e) = self.record_receipt(&signature, kind, outcome).await {
+                warn!(
+                    "Failed to read the receipt of transaction {}: {}",
+                    signature, e
+                );
             }
-            Err(e) => handle_error(e),
         }
+        ret.map(|_| ())
+    }
 
+    async fn record_receipt(
+        &self,
+        signature: &Signature,
+        kind: TransactionKind,
+        outcome: &mut RebalanceOutcome,
+    ) -> Result<()> {
+        let controller = self.controller_client();
+        let commitment = self.pool_options().confirm.commitment;
+        let Some(receipt) = controller
+            .get_transaction_receipt(signature, commitment)
+            .await?
+        else {
+            return Ok(());
+        };
+        outcome.fees += receipt.fee;
+
+        // Only the rebalance transaction moves the pool reserves
+        let Some(route) = outcome
+            .route
+            .as_ref()
+            .filter(|_| kind == TransactionKind::Rebalance)
+        else {
+            return Ok(());
+        };
+        let pool_state = controller.get_pool_state_address(&self.program_id()).await;
+        let src_mint: Pubkey = route.src_mint.parse()?;
+        let dst_mint: Pubkey = route.dst_mint.parse()?;
+        outcome.withdrawn += (-receipt.get_token_delta(&pool_state, &src_mint)).max(0) as u64;
+        outcome.returned += receipt.get_token_delta(&pool_state, &dst_mint).max(0) as u64;
         Ok(())
     }
 }
+
+#[async_trait::async_trait]
+impl PoolRebalancable for MaxPool {
+    async fn rebalance_asset(
+        &self,
+        context: &Context,
+        pool_asset_change: &PoolAssetChange,
+    ) -> RebalanceOutcome {
+        let route = context
+            .get_known_asset_from_mint(&pool_asset_change.mint)
+            .and_then(|asset| {
+                info!("Rebalancing asset: {}", asset.symbol);
+                pool_asset_change.get_route(&asset)
+            });
+        match route {
+            Ok(route) => self.rebalance_route(context, &route).await,
+            Err(e) => RebalanceOutcome::failed(rebalance_failure(&e)),
+        }
+    }
+
+    async fn rebalance_netted_change(
+        &self,
+        context: &Context,
+        netted_change: &NettedChange,
+    ) -> RebalanceOutcome {
+        info!("Rebalancing {}", netted_change);
+
+        match netted_change.get_route(context) {
+            Ok(route) => self.rebalance_route(context, &route).await,
+            Err(e) => RebalanceOutcome::failed(rebalance_failure(&e)),
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use log::{info, warn};
+use lst_optimizer_std::{
+    allocator::{is_same_mint, AllocationRatios},
+    types::{
+        context::Context,
+        netted_change::{NettedChange, NettedChanges},
+        pool_allocation::PoolAllocations,
+        rebalance_decision::{
+            weighted_apy, RebalanceCostOptions, RebalanceDecision, RebalanceDecisionKind,
+            RebalanceDecisions,
+        },
+    },
+};
+use quoter_lib::typedefs::SwapMode;
+use rust_decimal::prelude::ToPrimitive;
+use spl_token::native_mint;
+
+use super::{
+    helper::pool_asset_change_route::{NettedChangeRouter, PoolAssetChangeRoute},
+    pool::MaxPool,
+};
+
+impl MaxPool {
+    /// Drop or shrink the netted changes whose expected APY gain over the holding horizon does
+    /// not pay for their swap cost, quoted without building any instruction. A change whose
+    /// cost fails to quote is dropped. The kept changes are the swaps the rebalance sends, the
+    /// increases out of wSOL are shrunk to what the reserve and the kept decreases fund.
+    ///
+    /// A direct swap compares the APYs of its two lsts. An increase through wSOL is compared
+    /// to the APY of the current pool the lamports come from, a decrease through wSOL to the
+    /// APY of the target allocation the lamports go to.
+    pub async fn filter_changes_by_cost(
+        &self,
+        context: &Context,
+        pool_allocations: &PoolAllocations,
+        new_allocation_ratios: &AllocationRatios,
+        apys: &HashMap<String, f64>,
+        netted_changes: NettedChanges,
+        options: &RebalanceCostOptions,
+    ) -> Result<(NettedChanges, RebalanceDecisions)> {
+        let get_apy = |mint: &str| apys.get(mint).cloned().unwrap_or(0.0);
+        let current_apy = weighted_apy(
+            &pool_allocations
+                .assets
+                .iter()
+                .map(|asset| (asset.lamports, get_apy(&asset.mint)))
+                .collect::<Vec<(u64, f64)>>(),
+        );
+        let target_apy = weighted_apy(
+            &new_allocation_ratios
+                .asset_alloc_ratios
+                .iter()
+                .map(|ratio| (ratio.bps.to_u64().unwrap_or(0), get_apy(&ratio.mint)))
+                .collect::<Vec<(u64, f64)>>(),
+        );
+        info!(
+            "Current pool APY: {:.4}, target pool APY: {:.4}",
+            current_apy, target_apy
+        );
+
+        let minimum_rebalance_lamports = self.pool_options().minimum_rebalance_lamports;
+        let mut kept_changes: Vec<(NettedChange, usize)> = vec![];
+        let mut decisions: Vec<RebalanceDecision> = vec![];
+        for change in netted_changes.changes.into_iter() {
+            let (src_mint, dst_mint) = change.get_labels();
+            let lamports = change.get_lamports();
+            let apy_gain = match (&change.decrease, &change.increase) {
+                (Some(decrease), Some(increase)) => {
+                    get_apy(&increase.mint) - get_apy(&decrease.mint)
+                }
+                (None, Some(increase)) => get_apy(&increase.mint) - current_apy,
+                (Some(decrease), None) => target_apy - get_apy(&decrease.mint),
+                (None, None) => continue,
+            };
+
+            let gain = options.expected_gain_lamports(lamports, apy_gain);
+            let cost = match self.quote_swap_cost(context, &change, options).await {
+                Ok(cost) => cost,
+                Err(err) => {
+                    warn!("Failed to quote the cost of {}: {}", change, err);
+                    decisions.push(
+                        RebalanceDecision::new(
+                            src_mint,
+                            dst_mint,
+                            lamports,
+                            apy_gain,
+                            gain,
+                            0,
+                            RebalanceDecisionKind::Drop,
+                        )
+                        .with_reason(&format!("failed to quote: {}", err)),
+                    );
+                    continue;
+                }
+            };
+            if gain >= cost as f64 {
+                decisions.push(RebalanceDecision::new(
+                    src_mint,
+                    dst_mint,
+                    lamports,
+                    apy_gain,
+                    gain,
+                    cost,
+                    RebalanceDecisionKind::Keep,
+                ));
+                kept_changes.push((change, decisions.len() - 1));
+                continue;
+            }
+
+            // The fixed costs weigh more on smaller changes, only a price impact
+            // growing with the amount makes shrinking worth a try
+            let mut decision = RebalanceDecision::new(
+                src_mint,
+                dst_mint,
+                lamports,
+                apy_gain,
+                gain,
+                cost,
+                RebalanceDecisionKind::Drop,
+            );
+            let mut shrunk_lamports = lamports;
+            for _ in 0..options.max_shrink_steps {
+                if apy_gain <= 0.0 {
+                    break;
+                }
+                shrunk_lamports /= 2;
+                if shrunk_lamports <= minimum_rebalance_lamports {
+                    break;
+                }
+
+                let shrunk_change = change.shrink_to(shrunk_lamports);
+                let gain = options.expected_gain_lamports(shrunk_lamports, apy_gain);
+                let cost = match self.quote_swap_cost(context, &shrunk_change, options).await {
+                    Ok(cost) => cost,
+                    Err(err) => {
+                        warn!("Failed to quote the cost of {}: {}", shrunk_change, err);
+                        decision = decision.with_reason(&format!("failed to quote: {}", err));
+                        break;
+                    }
+                };
+                if gain >= cost as f64 {
+                    decision = RebalanceDecision::new(
+                        src_mint,
+                        dst_mint,
+                        lamports,
+                        apy_gain,
+                        gain,
+                        cost,
+                        RebalanceDecisionKind::Shrink {
+                            lamports: shrunk_lamports,
+                        },
+                    );
+                    kept_changes.push((shrunk_change, decisions.len()));
+                    break;
+                }
+            }
+            decisions.push(decision);
+        }
+
+        // A dropped decrease into wSOL no longer funds the increases out of it
+        let wsol_mint = native_mint::ID.to_string();
+        let wsol_lamports = pool_allocations
+            .assets
+            .iter()
+            .find(|asset| is_same_mint(&asset.mint, &wsol_mint))
+            .map(|asset| asset.lamports)
+            .unwrap_or(0);
+        let (kept_changes, kept_decisions): (Vec<NettedChange>, Vec<usize>) =
+            kept_changes.into_iter().unzip();
+        let kept_changes = NettedChanges::new(kept_changes);
+        let funded_lamports = kept_changes.wsol_funded_lamports(&wsol_mint, wsol_lamports);
+        let mut funded_changes: Vec<NettedChange> = vec![];
+        for ((change, index), funded) in kept_changes
+            .changes
+            .into_iter()
+            .zip(kept_decisions)
+            .zip(funded_lamports)
+        {
+            if funded == change.get_lamports() {
+                funded_changes.push(change);
+                continue;
+            }
+            let decision = &mut decisions[index];
+            decision.reason = Some(format!("the wSOL reserve funds {} lamports", funded));
+            if funded <= minimum_rebalance_lamports {
+                decision.kind = RebalanceDecisionKind::Drop;
+                continue;
+            }
+            decision.kind = RebalanceDecisionKind::Shrink { lamports: funded };
+            funded_changes.push(change.shrink_to(funded));
+        }
+
+        Ok((
+            NettedChanges::new(funded_changes),
+            RebalanceDecisions::new(decisions),
+        ))
+    }
+
+    /// Quote the swap of the netted change the rebalance sends and compare it with the fair
+    /// value of the change. A direct swap covers both the decrease and the increase legs,
+    /// a change through wSOL only has its own leg as the pool reserve holds the wSOL.
+    async fn quote_swap_cost(
+        &self,
+        context: &Context,
+        change: &NettedChange,
+        options: &RebalanceCostOptions,
+    ) -> Result<u64> {
+        let PoolAssetChangeRoute {
+            src_mint,
+            dst_mint,
+            amount,
+            ..
+        } = change.get_route(context)?;
+        if src_mint.eq(&dst_mint) {
+            return Ok(0);
+        }
+
+        let quote = self
+            .quoter_client()
+            .quote(&src_mint, &dst_mint, amount, SwapMode::ExactIn)
+            .await?;
+        let fair_out = match (&change.decrease, &change.increase) {
+            (_, Some(increase)) => increase.amount.get_lst_amount(),
+            (Some(decrease), None) => decrease.amount.get_lamports(),
+            (None, None) => 0,
+        };
+        Ok(options.swap_cost_lamports(change.get_lamports(), fair_out, quote.out_amount))
+    }
+}
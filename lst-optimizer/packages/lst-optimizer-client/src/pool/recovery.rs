@@ -0,0 +1,129 @@
+use anyhow::Result;
+use controller_lib::transaction::TransactionOutcome;
+use log::{info, warn};
+use lst_optimizer_std::types::context::Context;
+use solana_sdk::signature::Signature;
+
+use super::{
+    helper::journal::{JournalStep, JournalTransaction, StepStatus, TransactionKind},
+    pool::MaxPool,
+};
+
+impl MaxPool {
+    /// Finish or abandon the steps of the journaled cycle a crash interrupted, a step whose
+    /// rebalance landed gets its cleanup sent and its delayed unstake resumed, any other one
+    /// is abandoned and left to the next cycle to plan again from the chain state
+    pub async fn reconcile_journal(&self, context: &Context) -> Result<()> {
+        let Some(journal) = self.journal() else {
+            return Ok(());
+        };
+        let Some(cycle) = journal.load_unfinished()? else {
+            return Ok(());
+        };
+        warn!(
+            "Reconciling the rebalance cycle started at {}",
+            cycle.started_at
+        );
+
+        for (index, step) in cycle.steps.iter().enumerate() {
+            let status = match step.status {
+                StepStatus::InProgress => self.reconcile_step(context, step).await?,
+                StepStatus::Pending => StepStatus::Abandoned {
+                    reason: "Planned against a stale pool state".to_string(),
+                },
+                _ => continue,
+            };
+            info!("Reconciled step {} ({}): {:?}", index, step.change, status);
+            journal.finish_step(index, status)?;
+        }
+        Ok(())
+    }
+
+    async fn reconcile_step(&self, context: &Context, step: &JournalStep) -> Result<StepStatus> {
+        let rebalances = step.transactions_of(TransactionKind::Rebalance);
+        // The delayed unstake the step withdrew for is resumed or forgotten along with it
+        if let Err(e) = self
+            .reconcile_journaled_delayed_unstake(context, &rebalances)
+            .await
+        {
+            warn!(
+                "Failed to reconcile the delayed unstake of {}: {}",
+                step.change, e
+            );
+        }
+        if !self.any_transaction_landed(&rebalances).await? {
+            return Ok(StepStatus::Abandoned {
+                reason: "No rebalance transaction landed".to_string(),
+            });
+        }
+
+        // The rebalance landed, a failed cleanup only leaves accounts to close
+        if !step.pending_cleanup.is_empty()
+            && !self
+                .any_transaction_landed(&step.transactions_of(TransactionKind::Cleanup))
+                .await?
+        {
+            info!(
+                "Invoking the pending cleanup instructions of {}",
+                step.change
+            );
+            if let Err(e) = self
+                .send_rebalance_instructions(
+                    context,
+                    &step.pending_cleanup,
+                    &[],
+                    TransactionKind::Cleanup,
+                )
+                .await
+            {
+                warn!("Cleanup failed: {}", e);
+            }
+        }
+        Ok(StepStatus::Landed)
+    }
+
+    /// Whether any of the journaled transactions landed, waiting for those still valid
+    pub async fn any_transaction_landed(
+        &self,
+        transactions: &[&JournalTransaction],
+    ) -> Result<bool> {
+        for transaction in transactions {
+            if let Some(TransactionOutcome::Landed { .. }) =
+                self.await_journaled_transaction(transaction).await?
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// The outcome of the journaled transaction, waiting for it while its blockhash is
+    /// valid, none once it expired without landing
+    async fn await_journaled_transaction(
+        &self,
+        transaction: &JournalTransaction,
+    ) -> Result<Option<TransactionOutcome>> {
+        let controller = self.controller_client();
+        let options = &self.pool_options().confirm;
+        let signature: Signature = transaction.signature.parse()?;
+        loop {
+            if let Some(outcome) = controller
+                .find_transaction_outcome(&signature, options.commitment)
+                .await?
+            {
+                return Ok(Some(outcome));
+            }
+            let block_height = controller
+                .rpc_client()
+                .get_block_height_with_commitment(options.commitment)
+                .await?;
+            if block_height > transaction.last_valid_block_height {
+                // The transaction may have landed right before the blockhash expired
+                return controller
+                    .find_transaction_outcome(&signature, options.commitment)
+                    .await;
+            }
+            tokio::time::sleep(options.poll_interval).await;
+        }
+    }
+}
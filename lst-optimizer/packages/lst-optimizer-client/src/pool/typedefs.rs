@@ -1,7 +1,28 @@
+use std::path::PathBuf;
+
+use controller_lib::{compute_budget::ComputeBudgetOptions, transaction::ConfirmOptions};
+use lst_optimizer_std::types::rebalance_decision::RebalanceCostOptions;
+
+use super::helper::{delayed_unstake::DelayedUnstakeOptions, tranche::TrancheOptions};
+
 #[derive(Debug, Clone)]
 pub struct MaxPoolOptions {
     pub rpc_url: String,
     pub minimum_rebalance_lamports: u64,
+    /// Drop or shrink the changes not paying for their swap cost, disabled when none
+    pub rebalance_cost: Option<RebalanceCostOptions>,
+    /// Abort the swaps losing more than the bps against the calculators fair value
+    pub max_loss_bps: Option<u16>,
+    /// How the rebalance transactions are confirmed
+    pub confirm: ConfirmOptions,
+    /// Compute unit limit and priority fee of the rebalance transactions
+    pub compute_budget: ComputeBudgetOptions,
+    /// Split the swaps into tranches under a max price impact, disabled when none
+    pub tranche: Option<TrancheOptions>,
+    /// Unstake the opted in decreases the loss guard rejects over an epoch, disabled when none
+    pub delayed_unstake: Option<DelayedUnstakeOptions>,
+    /// Journal the rebalance cycles to the file to recover them after a crash, disabled when none
+    pub journal_path: Option<PathBuf>,
 }
 
 impl Default for MaxPoolOptions {
@@ -9,6 +30,13 @@ impl Default for MaxPoolOptions {
         Self {
             rpc_url: "https://api.mainnet-beta.solana.com".to_string(),
             minimum_rebalance_lamports: 1_000_000,
+            rebalance_cost: None,
+            max_loss_bps: None,
+            confirm: ConfirmOptions::default(),
+            compute_budget: ComputeBudgetOptions::default(),
+            tranche: None,
+            delayed_unstake: None,
+            journal_path: None,
         }
     }
 }
@@ -0,0 +1,542 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use controller_lib::{
+    calculator::query::CalculatorQuery,
+    rebalance::{RebalancingInstructions, StartRebalanceBounds},
+    state::PoolQuery,
+    Pubkey,
+};
+use log::{info, warn};
+use lst_optimizer_std::types::{context::Context, rebalance_outcome::RebalanceOutcome};
+use quoter_lib::typedefs::SwapMode;
+use solana_sdk::{
+    instruction::Instruction,
+    program_pack::Pack,
+    stake::{self, state::StakeStateV2},
+    system_instruction,
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account_idempotent,
+};
+use spl_token::native_mint;
+use stakedex_lib::{
+    quoter::StakedexPool, stake_pool::StakePoolState, validator_list::ValidatorList,
+};
+
+use super::{
+    helper::{
+        delayed_unstake::{
+            stake_seed, DelayedUnstake, DelayedUnstakeError, DelayedUnstakeStage,
+            DelayedUnstakeStore,
+        },
+        journal::{JournalTransaction, TransactionKind},
+        pool_asset_change_route::PoolAssetChangeRoute,
+    },
+    pool::MaxPool,
+};
+use crate::typedefs::pool_to_stakedex_pool;
+
+/// The route label of a delayed unstake
+pub const DELAYED_UNSTAKE_LABEL: &str = "Delayed Unstake";
+
+impl MaxPool {
+    fn delayed_unstake_store(&self) -> Option<DelayedUnstakeStore> {
+        self.pool_options()
+            .delayed_unstake
+            .as_ref()
+            .map(|options| DelayedUnstakeStore::new(&options.state_path))
+    }
+
+    /// Whether the decrease is unstaked over an epoch rather than sold, the asset opted in
+    /// and the loss guard rejects the sale
+    pub async fn should_delay_unstake(
+        &self,
+        context: &Context,
+        route: &PoolAssetChangeRoute,
+    ) -> Result<bool> {
+        if self.pool_options().delayed_unstake.is_none() || !route.dst_mint.eq(&native_mint::ID) {
+            return Ok(false);
+        }
+        let asset = context.get_known_asset_from_mint(&route.src_mint.to_string())?;
+        if !asset.delayed_unstake {
+            return Ok(false);
+        }
+        let Some(guard) = self.swap_loss_guard()? else {
+            return Ok(false);
+        };
+
+        let fair_out = self
+            .get_fair_out_amount(
+                context,
+                route.src_cal.clone(),
+                route.dst_cal.clone(),
+                route.amount,
+            )
+            .await?;
+        let quote = self
+            .quoter_client()
+            .quote(
+                &route.src_mint,
+                &route.dst_mint,
+                route.amount,
+                SwapMode::ExactIn,
+            )
+            .await;
+        match quote.map(|quote| guard.check(fair_out, quote.out_amount)) {
+            Ok(Ok(())) => Ok(false),
+            Ok(Err(e)) => {
+                info!("{}, unstaking {} over an epoch", e, asset.symbol);
+                Ok(true)
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to quote the sale of {}, unstaking it: {}",
+                    asset.symbol, e
+                );
+                Ok(true)
+            }
+        }
+    }
+
+    /// Withdraw the lst from the pool for the payer's wSOL at the fair value, so the pool
+    /// exits without slippage, then split its stake off the stake pool and deactivate it.
+    /// A stake account takes at most the stake of a single validator, the rest of the
+    /// decrease is left to the next cycles.
+    ///
+    /// The rebalance must repay the pool within its transaction, so the pool cannot hold the
+    /// stake while it deactivates. The payer fronts the exit with its own wSOL instead,
+    /// up to the max fronted lamports across the unstakes in progress, and is repaid by the
+    /// unstaked SOL an epoch later. The unstake is recorded before the withdrawal is sent,
+    /// with every signed attempt, so an interrupted one is reconciled by
+    /// `advance_delayed_unstakes`.
+    pub async fn start_delayed_unstake(
+        &self,
+        context: &Context,
+        route: &PoolAssetChangeRoute,
+        outcome: &mut RebalanceOutcome,
+    ) -> Result<()> {
+        let options = self
+            .pool_options()
+            .delayed_unstake
+            .as_ref()
+            .ok_or(DelayedUnstakeError::Disabled)?;
+        let store = DelayedUnstakeStore::new(&options.state_path);
+        let fronting = store.fronted_lamports()?;
+        let available_lamports = options.max_fronted_lamports.saturating_sub(fronting);
+        if available_lamports == 0 {
+            return Err(DelayedUnstakeError::FrontedLimitReached(
+                fronting,
+                options.max_fronted_lamports,
+            )
+            .into());
+        }
+        let controller = self.controller_client();
+        let rpc = controller.rpc_client();
+        let payer = context.get_payer_pubkey();
+
+        let pool = self.get_stakedex_pool(context, &route.src_mint)?;
+        let epoch = rpc.get_epoch_info().await?.epoch;
+        let stake_pool = self.get_updated_stake_pool(&pool, epoch).await?;
+        let account = rpc.get_account(&stake_pool.validator_list).await?;
+        let validator_list = ValidatorList::from_account_data(&account.data)?;
+        let Some(validator) = validator_list
+            .find_largest_withdrawable(stake_pool.preferred_withdraw_validator.as_ref())
+        else {
+            return Err(DelayedUnstakeError::NoWithdrawableStake(
+                stake_pool.address.to_string(),
+                stake_pool.stake_withdraw_out(route.amount),
+            )
+            .into());
+        };
+        let mut amount = route
+            .amount
+            .min(stake_pool.pool_tokens_for(validator.withdrawable_lamports()));
+        let mut fronted_lamports = self.get_fronted_lamports(context, route, amount).await?;
+        if fronted_lamports > available_lamports {
+            amount =
+                (amount as u128 * available_lamports as u128 / fronted_lamports as u128) as u64;
+            fronted_lamports = self.get_fronted_lamports(context, route, amount).await?;
+        }
+        if amount == 0 || fronted_lamports > available_lamports {
+            return Err(DelayedUnstakeError::FrontedLimitReached(
+                fronting,
+                options.max_fronted_lamports,
+            )
+            .into());
+        }
+
+        // A missing wSOL account holds none, any other failure to fetch it is an error
+        let wsol_ata = get_associated_token_address(&payer, &native_mint::ID);
+        let wsol_balance = match rpc
+            .get_account_with_commitment(&wsol_ata, rpc.commitment())
+            .await?
+            .value
+        {
+            Some(account) => spl_token::state::Account::unpack(&account.data)?.amount,
+            None => 0,
+        };
+        if wsol_balance < fronted_lamports {
+            return Err(
+                DelayedUnstakeError::InsufficientWsol(wsol_balance, fronted_lamports).into(),
+            );
+        }
+
+        // The rebalance ends atomically, the payer's wSOL stands in for the stake to unstake
+        let reserves_ata = controller
+            .get_pool_reserves_address_by_mint(&self.program_id(), &route.dst_mint)
+            .await?;
+        let (src_ata, mut instructions) = self
+            .get_src_ata_instructions(&payer, &route.src_mint)
+            .await?;
+        // Held to the loss guard like any other rebalance
+        let bounds = match self.swap_loss_guard()? {
+            Some(guard) => {
+                self.get_start_rebalance_bounds(&guard, &route.src_mint, &reserves_ata)
+                    .await?
+            }
+            None => StartRebalanceBounds::default(),
+        };
+        let start_ix = controller
+            .create_start_rebalance_instruction(
+                &self.program_id(),
+                &src_ata,
+                &route.src_mint,
+                &route.dst_mint,
+                route.src_cal.clone(),
+                route.dst_cal.clone(),
+                amount,
+                bounds,
+            )
+            .await?;
+        let end_ix = controller
+            .create_end_rebalance_instruction_from_start(&start_ix)
+            .await?;
+        instructions.extend([
+            start_ix,
+            spl_token::instruction::transfer(
+                &spl_token::ID,
+                &wsol_ata,
+                &reserves_ata,
+                &payer,
+                &[],
+                fronted_lamports,
+            )?,
+            end_ix,
+        ]);
+        info!(
+            "Withdrawing {} of {} for {} fronted lamports",
+            amount, route.src_mint, fronted_lamports
+        );
+        if let Some(outcome_route) = &mut outcome.route {
+            outcome_route.add_labels(&[DELAYED_UNSTAKE_LABEL.to_string()]);
+        }
+
+        let nonce = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+        let stake_seed = stake_seed(nonce);
+        let stake_account = Pubkey::create_with_seed(&payer, &stake_seed, &stake::program::ID)?;
+        let mut unstake = DelayedUnstake {
+            mint: route.src_mint.to_string(),
+            lst_amount: amount,
+            fronted_lamports,
+            stake_seed,
+            stake_account: stake_account.to_string(),
+            stage: DelayedUnstakeStage::Withdrawing,
+            transactions: vec![],
+        };
+        store.upsert(&unstake)?;
+        self.send_recorded_instructions_with_hook(
+            context,
+            &instructions,
+            &[],
+            TransactionKind::Rebalance,
+            outcome,
+            &|signature, last_valid_block_height| {
+                store.record_transaction(
+                    &unstake.stake_account,
+                    JournalTransaction {
+                        kind: TransactionKind::Rebalance,
+                        signature: signature.to_string(),
+                        last_valid_block_height,
+                    },
+                )
+            },
+        )
+        .await?;
+
+        unstake.stage = DelayedUnstakeStage::Withdrawn;
+        store.upsert(&unstake)?;
+        self.deactivate_delayed_unstake(context, &store, unstake)
+            .await
+    }
+
+    /// Advance every delayed unstake a stage, the stake of the withdrawn lst is split off
+    /// its stake pool and deactivated, the stake deactivated in an earlier epoch is
+    /// withdrawn into the payer's wSOL
+    pub async fn advance_delayed_unstakes(&self, context: &Context) -> Result<()> {
+        let Some(store) = self.delayed_unstake_store() else {
+            return Ok(());
+        };
+        let unstakes = store.load()?;
+        if unstakes.is_empty() {
+            return Ok(());
+        }
+        let epoch = self
+            .controller_client()
+            .rpc_client()
+            .get_epoch_info()
+            .await?
+            .epoch;
+
+        for unstake in unstakes {
+            let stake_account = unstake.stake_account.clone();
+            let stage = unstake.stage;
+            let result = match stage {
+                DelayedUnstakeStage::Withdrawing => {
+                    self.reconcile_delayed_unstake(context, &store, unstake)
+                        .await
+                }
+                DelayedUnstakeStage::Withdrawn => {
+                    self.deactivate_delayed_unstake(context, &store, unstake)
+                        .await
+                }
+                DelayedUnstakeStage::Deactivating { .. } if unstake.is_withdrawable(epoch) => {
+                    self.withdraw_delayed_unstake(context, &store, &unstake)
+                        .await
+                }
+                DelayedUnstakeStage::Deactivating { epoch: deactivated } => {
+                    info!(
+                        "Stake account {} deactivating since epoch {}",
+                        stake_account, deactivated
+                    );
+                    continue;
+                }
+            };
+            if let Err(e) = result {
+                warn!(
+                    "Failed to advance the delayed unstake of {}: {}",
+                    stake_account, e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Reconcile the delayed unstake withdrawing through any of the journaled transactions
+    /// of an interrupted cycle, the ones recorded without a transaction are left to
+    /// `advance_delayed_unstakes`
+    pub async fn reconcile_journaled_delayed_unstake(
+        &self,
+        context: &Context,
+        transactions: &[&JournalTransaction],
+    ) -> Result<()> {
+        let Some(store) = self.delayed_unstake_store() else {
+            return Ok(());
+        };
+        let unstake = store.load()?.into_iter().find(|unstake| {
+            matches!(unstake.stage, DelayedUnstakeStage::Withdrawing)
+                && unstake.transactions.iter().any(|recorded| {
+                    transactions
+                        .iter()
+                        .any(|transaction| transaction.signature.eq(&recorded.signature))
+                })
+        });
+        match unstake {
+            Some(unstake) => {
+                self.reconcile_delayed_unstake(context, &store, unstake)
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Resume the delayed unstake whose withdrawal from the pool was sent without knowing
+    /// whether it landed, it is forgotten when none of its transactions did
+    async fn reconcile_delayed_unstake(
+        &self,
+        context: &Context,
+        store: &DelayedUnstakeStore,
+        mut unstake: DelayedUnstake,
+    ) -> Result<()> {
+        let transactions: Vec<&JournalTransaction> = unstake.transactions.iter().collect();
+        if !self.any_transaction_landed(&transactions).await? {
+            warn!(
+                "No withdrawal of the delayed unstake {} landed, forgetting it",
+                unstake.stake_account
+            );
+            return store.remove(&unstake.stake_account);
+        }
+
+        unstake.stage = DelayedUnstakeStage::Withdrawn;
+        store.upsert(&unstake)?;
+        self.deactivate_delayed_unstake(context, store, unstake)
+            .await
+    }
+
+    /// Burn the withdrawn lst for a stake account of the payer and deactivate it, the stake
+    /// account already on chain is only deactivated if it is not yet
+    async fn deactivate_delayed_unstake(
+        &self,
+        context: &Context,
+        store: &DelayedUnstakeStore,
+        mut unstake: DelayedUnstake,
+    ) -> Result<()> {
+        let rpc = self.controller_client().rpc_client();
+        let payer = context.get_payer_pubkey();
+        let mint: Pubkey = unstake.mint.parse()?;
+        let stake_account: Pubkey = unstake.stake_account.parse()?;
+
+        let existing = rpc
+            .get_account_with_commitment(&stake_account, rpc.commitment())
+            .await?
+            .value;
+        if let Some(account) = existing {
+            let state: StakeStateV2 = account.deserialize_data()?;
+            let epoch = match state.delegation() {
+                Some(delegation) if delegation.deactivation_epoch != u64::MAX => {
+                    delegation.deactivation_epoch
+                }
+                _ => {
+                    info!("Deactivating stake account {}", stake_account);
+                    self.send_rebalance_instructions(
+                        context,
+                        &[stake::instruction::deactivate_stake(&stake_account, &payer)],
+                        &[],
+                        TransactionKind::Unstake,
+                    )
+                    .await?;
+                    rpc.get_epoch_info().await?.epoch
+                }
+            };
+            unstake.stage = DelayedUnstakeStage::Deactivating { epoch };
+            return store.upsert(&unstake);
+        }
+
+        let pool = self.get_stakedex_pool(context, &mint)?;
+        let epoch = rpc.get_epoch_info().await?.epoch;
+        let stake_pool = self.get_updated_stake_pool(&pool, epoch).await?;
+        let account = rpc.get_account(&stake_pool.validator_list).await?;
+        let lamports = stake_pool.stake_withdraw_out(unstake.lst_amount);
+        let validator = ValidatorList::from_account_data(&account.data)?
+            .find_withdrawable(lamports, stake_pool.preferred_withdraw_validator.as_ref())
+            .ok_or(DelayedUnstakeError::NoWithdrawableStake(
+                stake_pool.address.to_string(),
+                lamports,
+            ))?
+            .clone();
+
+        let (src_ata, _) = self.get_src_ata_instructions(&payer, &mint).await?;
+        let rent = rpc
+            .get_minimum_balance_for_rent_exemption(StakeStateV2::size_of())
+            .await?;
+        let instructions: Vec<Instruction> = vec![
+            system_instruction::create_account_with_seed(
+                &payer,
+                &stake_account,
+                &payer,
+                &unstake.stake_seed,
+                rent,
+                StakeStateV2::size_of() as u64,
+                &stake::program::ID,
+            ),
+            stake_pool.withdraw_stake_instruction(
+                &validator,
+                &stake_account,
+                &payer,
+                &src_ata,
+                unstake.lst_amount,
+            ),
+            stake::instruction::deactivate_stake(&stake_account, &payer),
+        ];
+        info!(
+            "Unstaking {} of {} in stake account {}",
+            unstake.lst_amount, mint, stake_account
+        );
+        self.send_rebalance_instructions(context, &instructions, &[], TransactionKind::Unstake)
+            .await?;
+
+        unstake.stage = DelayedUnstakeStage::Deactivating { epoch };
+        store.upsert(&unstake)
+    }
+
+    /// Withdraw the inactive stake into the payer's wSOL, repaying the fronted lamports
+    async fn withdraw_delayed_unstake(
+        &self,
+        context: &Context,
+        store: &DelayedUnstakeStore,
+        unstake: &DelayedUnstake,
+    ) -> Result<()> {
+        let rpc = self.controller_client().rpc_client();
+        let payer = context.get_payer_pubkey();
+        let stake_account: Pubkey = unstake.stake_account.parse()?;
+        let lamports = rpc.get_balance(&stake_account).await?;
+
+        let wsol_ata = get_associated_token_address(&payer, &native_mint::ID);
+        let instructions: Vec<Instruction> = vec![
+            create_associated_token_account_idempotent(
+                &payer,
+                &payer,
+                &native_mint::ID,
+                &spl_token::ID,
+            ),
+            stake::instruction::withdraw(&stake_account, &payer, &wsol_ata, lamports, None),
+            spl_token::instruction::sync_native(&spl_token::ID, &wsol_ata)?,
+        ];
+        self.send_rebalance_instructions(context, &instructions, &[], TransactionKind::Unstake)
+            .await?;
+        info!(
+            "Unstaked {} lamports of {} for the {} fronted",
+            lamports, unstake.mint, unstake.fronted_lamports
+        );
+
+        store.remove(&unstake.stake_account)
+    }
+
+    /// The wSOL fronted for the lst, valued at the top of the calculators range so the
+    /// pool's SOL value does not drop to their rounding
+    async fn get_fronted_lamports(
+        &self,
+        context: &Context,
+        route: &PoolAssetChangeRoute,
+        amount: u64,
+    ) -> Result<u64> {
+        let controller = self.controller_client();
+        let lamports = controller
+            .convert_lst_to_sol(context.get_payer(), route.src_cal.clone(), amount)
+            .await?
+            .get_max();
+        let fronted_lamports = controller
+            .convert_sol_to_lst(context.get_payer(), route.dst_cal.clone(), lamports)
+            .await?
+            .get_max();
+        Ok(fronted_lamports)
+    }
+
+    fn get_stakedex_pool(&self, context: &Context, mint: &Pubkey) -> Result<StakedexPool> {
+        let asset = context.get_known_asset_from_mint(&mint.to_string())?;
+        let pool =
+            pool_to_stakedex_pool(&asset)?.ok_or(DelayedUnstakeError::NoStakePool(asset.symbol))?;
+        Ok(pool)
+    }
+
+    /// The stake pool state, withdrawals are rejected until it is updated this epoch
+    async fn get_updated_stake_pool(
+        &self,
+        pool: &StakedexPool,
+        epoch: u64,
+    ) -> Result<StakePoolState> {
+        let account = self
+            .controller_client()
+            .rpc_client()
+            .get_account(&pool.stake_pool)
+            .await?;
+        let stake_pool =
+            StakePoolState::from_account_data(&pool.program_id, &pool.stake_pool, &account.data)?;
+        if !stake_pool.is_updated(epoch) {
+            return Err(
+                DelayedUnstakeError::StakePoolNotUpdated(pool.stake_pool.to_string()).into(),
+            );
+        }
+        Ok(stake_pool)
+    }
+}
@@ -1,9 +1,18 @@
-use std::time::Duration;
+use std::{str::FromStr, time::Duration};
 
 use anyhow::Result;
 use backoff::ExponentialBackoff;
-use controller_lib::calculator::typedefs::CalculatorType;
+use controller_lib::{calculator::typedefs::CalculatorType, Pubkey};
 use lst_optimizer_std::types::asset::Asset;
+use solana_sdk::pubkey;
+use stakedex_lib::quoter::StakedexPool;
+
+pub const SPL_STAKE_POOL_PROGRAM_ID: Pubkey =
+    pubkey!("SPoo1Ku8WFXoNDMHPsrGSTSG1Y47rzgn41SLUNakuHy");
+pub const SANCTUM_SPL_STAKE_POOL_PROGRAM_ID: Pubkey =
+    pubkey!("SP12tWFxD9oJsVWNavTTBZvMbA6gkAmxtVgxdqvyvhY");
+pub const SANCTUM_SPL_MULTI_STAKE_POOL_PROGRAM_ID: Pubkey =
+    pubkey!("SPMBzsVUuoHA4Jm6KunbsotaahvVikZs1JyTW6iJvbn");
 
 pub fn default_backoff() -> ExponentialBackoff {
     ExponentialBackoff {
@@ -51,3 +60,34 @@ pub fn pool_to_calculator_type(asset: &Asset) -> Result<CalculatorType> {
         )),
     }
 }
+
+/// The program of the SPL stake pool behind the calculator, none for the other calculators
+pub fn stake_pool_program_id(calculator_type: &CalculatorType) -> Option<Pubkey> {
+    match calculator_type {
+        CalculatorType::Spl(_) => Some(SPL_STAKE_POOL_PROGRAM_ID),
+        CalculatorType::SanctumSpl(_) => Some(SANCTUM_SPL_STAKE_POOL_PROGRAM_ID),
+        CalculatorType::SanctumSplMulti(_) => Some(SANCTUM_SPL_MULTI_STAKE_POOL_PROGRAM_ID),
+        _ => None,
+    }
+}
+
+/// The stake pool stakedex routes the asset through, none for the non SPL stake pools
+pub fn pool_to_stakedex_pool(asset: &Asset) -> Result<Option<StakedexPool>> {
+    if asset.pool.is_none() {
+        return Ok(None);
+    }
+    let calculator_type = pool_to_calculator_type(asset)?;
+    let (CalculatorType::Spl(pool)
+    | CalculatorType::SanctumSpl(pool)
+    | CalculatorType::SanctumSplMulti(pool)) = &calculator_type
+    else {
+        return Ok(None);
+    };
+    let Some(program_id) = stake_pool_program_id(&calculator_type) else {
+        return Ok(None);
+    };
+    Ok(Some(StakedexPool {
+        program_id,
+        stake_pool: Pubkey::from_str(pool)?,
+    }))
+}
@@ -1,8 +1,106 @@
-use clap::Parser;
+use std::{path::PathBuf, str::FromStr, time::Duration};
+
+use anyhow::Result;
+use clap::{Parser, Subcommand, ValueEnum};
+use controller_lib::{
+    compute_budget::{ComputeBudgetOptions, ComputeUnitLimit, PriorityFee},
+    transaction::ConfirmOptions,
+    Pubkey,
+};
+use jupiter_lib::{options::JupiterQuoterOptions, quoter::JupiterQuoterClient};
+use lst_optimizer_std::{
+    allocator::{
+        processor::{processors_with_options, AllocationProcessor},
+        Allocator, WeightMode,
+    },
+    fetcher::{apy::Apy, fetcher::Fetcher},
+    types::{asset_repository::AssetRepository, rebalance_decision::RebalanceCostOptions},
+};
+use quoter_lib::{aggregate_quoter::AggregateQuoterClient, typedefs::QuoterClient};
+use solana_sdk::commitment_config::CommitmentConfig;
+use stakedex_lib::quoter::StakedexQuoterClient;
+
+use crate::{
+    allocator::{
+        ema::{EmaAllocationMode, EmaAllocator},
+        equal::EqualAllocator,
+        ma::MaAllocator,
+        single::SingleAllocator,
+    },
+    fetcher::apy::SanctumHistoricalApyFetcher,
+    pool::helper::{delayed_unstake::DelayedUnstakeOptions, tranche::TrancheOptions},
+    typedefs::pool_to_stakedex_pool,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum AllocatorKind {
+    /// Equally allocate to the top LSTs ranked by the exponential moving average APY
+    Ema,
+    /// Equally allocate to the top LSTs ranked by the simple moving average APY
+    Sma,
+    /// Allocate everything to a single LST, `--single-mint` or the best latest APY
+    Single,
+    /// Equally allocate to every known LST
+    Equal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum AllocationModeKind {
+    /// Split the bps equally between the selected LSTs
+    Equal,
+    /// Split the bps proportionally to the APY in excess of `--baseline-apy`
+    Proportional,
+    /// Split the bps by the softmax of the APYs with `--temperature`
+    Softmax,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum WeightModeKind {
+    /// Replace the allocated bps by the normalized registry weights
+    Override,
+    /// Multiply the allocated bps by the registry weights and renormalize
+    Multiply,
+    /// Ignore the registry weights
+    Ignore,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum CommitmentKind {
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum FetcherKind {
+    /// Historical epoch APYs from the Sanctum API
+    Sanctum,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum QuoterKind {
+    /// The Jupiter swap API
+    Jupiter,
+    /// Stakedex routes through the SPL stake pools of the registry
+    Stakedex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Subcommand)]
+pub enum Command {
+    /// Keep rebalancing the pool every interval
+    Run,
+    /// Print the rebalance plan with every transaction simulated, without sending anything
+    Plan,
+}
 
 #[derive(Debug, Clone, Parser)]
 #[command(name = "optimizer")]
 pub struct AppArgs {
+    /// What to do with the pool
+    /// (default: run)
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Rebalancing authority keypair file
     #[arg(
         long,
@@ -24,4 +122,367 @@ pub struct AppArgs {
     /// (default: 1_000_000_000)
     #[arg(long, short, default_value_t = 1_000_000_000)]
     pub minimum_rebalance_lamports: u64,
+
+    /// Allocation strategy
+    /// (default: ema)
+    #[arg(long, value_enum, default_value_t = AllocatorKind::Ema)]
+    pub allocator: AllocatorKind,
+
+    /// Maximum number of LSTs to allocate to
+    /// (default: 10)
+    #[arg(long, default_value_t = 10)]
+    pub allocation_limit: usize,
+
+    /// Moving average period in epochs
+    /// (default: 5)
+    #[arg(long, default_value_t = 5)]
+    pub period: usize,
+
+    /// How the ema allocator splits the bps between the selected LSTs
+    /// (default: equal)
+    #[arg(long, value_enum, default_value_t = AllocationModeKind::Equal)]
+    pub allocation_mode: AllocationModeKind,
+
+    /// APY baseline of the proportional allocation mode
+    /// (default: 0)
+    #[arg(long)]
+    pub baseline_apy: Option<f64>,
+
+    /// Temperature of the softmax allocation mode, in APY units as the APYs are fractions:
+    /// an APY higher by the temperature gets e times the allocation
+    /// (default: 0.01)
+    #[arg(long, default_value_t = 0.01)]
+    pub temperature: f64,
+
+    /// The mint allocated by the single allocator
+    /// (default: the LST with the best latest APY)
+    #[arg(long)]
+    pub single_mint: Option<String>,
+
+    /// How the registry weights are composed with the allocation
+    /// (default: override)
+    #[arg(long, value_enum, default_value_t = WeightModeKind::Override)]
+    pub weight_mode: WeightModeKind,
+
+    /// APY margin a challenger must beat an incumbent LST by to replace it in the ema selection
+    /// (default: no hysteresis)
+    #[arg(long)]
+    pub hysteresis_margin: Option<f64>,
+
+    /// Maximum bps of the pool moved in a single rebalance
+    /// (default: unlimited)
+    #[arg(long)]
+    pub max_turnover_bps: Option<u16>,
+
+    /// Holding horizon in days the expected APY gain of a change must pay for its swap cost over,
+    /// changes not paying for themselves are shrunk or dropped
+    /// (default: no cost filtering)
+    #[arg(long)]
+    pub holding_horizon_days: Option<f64>,
+
+    /// Flat fee in lamports of a rebalance transaction, used by the cost filtering
+    /// (default: 10_000)
+    #[arg(long, default_value_t = 10_000)]
+    pub rebalance_fee_lamports: u64,
+
+    /// Maximum number of times a change not paying for itself is halved
+    /// (default: 3)
+    #[arg(long, default_value_t = 3)]
+    pub max_shrink_steps: u8,
+
+    /// Maximum loss in bps of a swap against the calculators fair value, the rebalance is aborted
+    /// beyond it and the swap slippage and starting reserves are bounded by it
+    /// (default: no loss guard)
+    #[arg(long)]
+    pub max_loss_bps: Option<u16>,
+
+    /// Maximum quoted price impact of a swap as a fraction in (0, 1], 0.01 being 1%, larger
+    /// swaps are split into tranches under it
+    /// (default: no tranches)
+    #[arg(long)]
+    pub max_price_impact: Option<f64>,
+
+    /// Seconds waited between two tranches
+    /// (default: 10)
+    #[arg(long, default_value_t = 10)]
+    pub tranche_delay_secs: u64,
+
+    /// Maximum number of tranches of a swap, the tranches exceed the max price impact beyond it
+    /// (default: 10)
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..))]
+    pub max_tranches: u16,
+
+    /// File the delayed unstakes in progress are kept in, enables unstaking the decreases of
+    /// the assets opted in with `delayed_unstake` over an epoch when the loss guard rejects them.
+    /// The payer fronts each exit with its own wSOL at the fair value and is repaid an epoch
+    /// later, it must hold wSOL worth the exits in progress
+    /// (default: no delayed unstake)
+    #[arg(long, requires = "delayed_unstake_max_fronted_lamports")]
+    pub delayed_unstake_state: Option<PathBuf>,
+
+    /// The most wSOL the payer fronts across the delayed unstakes in progress, the exits
+    /// beyond it are unstaked once earlier ones are repaid
+    #[arg(long, requires = "delayed_unstake_state")]
+    pub delayed_unstake_max_fronted_lamports: Option<u64>,
+
+    /// File the rebalance cycles are journaled to, an interrupted cycle is reconciled
+    /// against the chain on restart instead of being run again
+    /// (default: no journal)
+    #[arg(long)]
+    pub journal_path: Option<PathBuf>,
+
+    /// Quoters the swaps are priced with, the best execution among them is kept
+    /// (default: jupiter)
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [QuoterKind::Jupiter])]
+    pub quoters: Vec<QuoterKind>,
+
+    /// Seconds a quoter has to answer before it is skipped, when there is more than one
+    /// (default: 10)
+    #[arg(long, default_value_t = 10)]
+    pub quote_timeout_secs: u64,
+
+    /// Jupiter swap API url, a self hosted instance or a local stand-in works as well
+    /// (default: "https://quote-api.jup.ag/v6")
+    #[arg(long, default_value = "https://quote-api.jup.ag/v6")]
+    pub jupiter_url: String,
+
+    /// Jupiter API key, sent as the `x-api-key` header
+    /// (default: none)
+    #[arg(long)]
+    pub jupiter_api_key: Option<String>,
+
+    /// Seconds a Jupiter request may take
+    /// (default: 30)
+    #[arg(long, default_value_t = 30)]
+    pub jupiter_timeout_secs: u64,
+
+    /// Maximum accounts of a Jupiter route
+    /// (default: unlimited)
+    #[arg(long)]
+    pub jupiter_max_accounts: Option<u8>,
+
+    /// Dexes Jupiter may only route through, comma separated
+    /// (default: any)
+    #[arg(long, value_delimiter = ',')]
+    pub jupiter_dexes: Vec<String>,
+
+    /// Dexes Jupiter never routes through, comma separated
+    /// (default: none)
+    #[arg(long, value_delimiter = ',')]
+    pub jupiter_excluded_dexes: Vec<String>,
+
+    /// Slippage in bps of the Jupiter swaps without a loss guard
+    /// (default: 3000)
+    #[arg(long, default_value_t = 3000)]
+    pub jupiter_slippage_bps: u16,
+
+    /// Accept Jupiter swaps whose worst fill is below the min amount out,
+    /// leaving the check to the rebalance
+    #[arg(long)]
+    pub jupiter_allow_below_min_out: bool,
+
+    /// Commitment a rebalance transaction must reach to be landed
+    /// (default: confirmed)
+    #[arg(long, value_enum, default_value_t = CommitmentKind::Confirmed)]
+    pub commitment: CommitmentKind,
+
+    /// How many times an expired rebalance transaction is signed again and resent
+    /// (default: 2)
+    #[arg(long, default_value_t = 2)]
+    pub max_resends: u8,
+
+    /// Compute unit limit of the rebalance transactions
+    /// (default: the runtime default limit)
+    #[arg(long, conflicts_with = "compute_unit_margin_bps")]
+    pub compute_unit_limit: Option<u32>,
+
+    /// Set the compute unit limit to the units consumed by a simulation plus the margin in bps
+    /// (default: the runtime default limit)
+    #[arg(long)]
+    pub compute_unit_margin_bps: Option<u16>,
+
+    /// Fixed priority fee in micro-lamports per compute unit, with a percentile priority fee
+    /// the one paid when the recent prioritization fees fail to be fetched
+    /// (default: no priority fee)
+    #[arg(long)]
+    pub priority_fee_micro_lamports: Option<u64>,
+
+    /// Set the priority fee to the percentile of the recent prioritization fees
+    /// paid for the accounts written by the rebalance
+    /// (default: no priority fee)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub priority_fee_percentile: Option<u8>,
+
+    /// Cap in micro-lamports per compute unit of the percentile priority fee
+    /// (default: uncapped)
+    #[arg(long)]
+    pub max_priority_fee_micro_lamports: Option<u64>,
+
+    /// Historical APY source
+    /// (default: sanctum)
+    #[arg(long, value_enum, default_value_t = FetcherKind::Sanctum)]
+    pub fetcher: FetcherKind,
+}
+
+impl AppArgs {
+    pub fn allocator(&self) -> Box<dyn Allocator<Apy>> {
+        match self.allocator {
+            AllocatorKind::Ema => Box::new(
+                EmaAllocator::new(Some(self.allocation_limit), Some(self.period))
+                    .with_mode(self.allocation_mode())
+                    .with_hysteresis_margin(self.hysteresis_margin),
+            ),
+            AllocatorKind::Sma => Box::new(MaAllocator::new(
+                Some(self.allocation_limit),
+                Some(self.period),
+            )),
+            AllocatorKind::Single => Box::new(SingleAllocator::new(self.single_mint.clone())),
+            AllocatorKind::Equal => Box::new(EqualAllocator::new()),
+        }
+    }
+
+    pub fn allocation_mode(&self) -> EmaAllocationMode {
+        match self.allocation_mode {
+            AllocationModeKind::Equal => EmaAllocationMode::Equal,
+            AllocationModeKind::Proportional => EmaAllocationMode::Proportional {
+                baseline: self.baseline_apy,
+            },
+            AllocationModeKind::Softmax => EmaAllocationMode::Softmax {
+                temperature: self.temperature,
+            },
+        }
+    }
+
+    pub fn processors(&self) -> Vec<Box<dyn AllocationProcessor>> {
+        let weight_mode = match self.weight_mode {
+            WeightModeKind::Override => WeightMode::Override,
+            WeightModeKind::Multiply => WeightMode::Multiply,
+            WeightModeKind::Ignore => WeightMode::Ignore,
+        };
+        processors_with_options(weight_mode, self.max_turnover_bps)
+    }
+
+    pub fn fetcher(&self) -> Box<dyn Fetcher<Apy>> {
+        match self.fetcher {
+            FetcherKind::Sanctum => Box::new(SanctumHistoricalApyFetcher::new()),
+        }
+    }
+
+    pub fn rebalance_cost(&self) -> Option<RebalanceCostOptions> {
+        self.holding_horizon_days
+            .map(|holding_horizon_days| RebalanceCostOptions {
+                holding_horizon_days,
+                fee_lamports: self.rebalance_fee_lamports,
+                max_shrink_steps: self.max_shrink_steps,
+            })
+    }
+
+    pub fn tranche(&self) -> Result<Option<TrancheOptions>> {
+        self.max_price_impact
+            .map(|max_price_impact| {
+                TrancheOptions::new(
+                    max_price_impact,
+                    Duration::from_secs(self.tranche_delay_secs),
+                    self.max_tranches,
+                )
+            })
+            .transpose()
+    }
+
+    pub fn delayed_unstake(&self) -> Option<DelayedUnstakeOptions> {
+        self.delayed_unstake_state
+            .clone()
+            .zip(self.delayed_unstake_max_fronted_lamports)
+            .map(|(state_path, max_fronted_lamports)| DelayedUnstakeOptions {
+                state_path,
+                max_fronted_lamports,
+            })
+    }
+
+    pub fn jupiter_options(&self) -> JupiterQuoterOptions {
+        JupiterQuoterOptions {
+            base_url: self.jupiter_url.clone(),
+            api_key: self.jupiter_api_key.clone(),
+            timeout: Duration::from_secs(self.jupiter_timeout_secs),
+            max_accounts: self.jupiter_max_accounts,
+            dexes: self.jupiter_dexes.clone(),
+            excluded_dexes: self.jupiter_excluded_dexes.clone(),
+            default_slippage_bps: self.jupiter_slippage_bps,
+            enforce_min_amount_out: !self.jupiter_allow_below_min_out,
+        }
+    }
+
+    pub fn quoter_client(
+        &self,
+        asset_repository: &AssetRepository,
+    ) -> Result<Box<dyn QuoterClient>> {
+        let mut quoters: Vec<(&str, Box<dyn QuoterClient>)> = vec![];
+        for kind in self.quoters.iter() {
+            match kind {
+                QuoterKind::Jupiter => {
+                    let quoter =
+                        JupiterQuoterClient::new(&self.url).with_options(self.jupiter_options());
+                    quoters.push(("Jupiter", Box::new(quoter)));
+                }
+                QuoterKind::Stakedex => {
+                    let mut quoter = StakedexQuoterClient::new(&self.url);
+                    for asset in asset_repository.get_assets() {
+                        if let Some(pool) = pool_to_stakedex_pool(&asset)? {
+                            quoter = quoter.with_stake_pool(&Pubkey::from_str(&asset.mint)?, pool);
+                        }
+                    }
+                    quoters.push(("Stakedex", Box::new(quoter)));
+                }
+            }
+        }
+
+        if quoters.len() == 1 {
+            return Ok(quoters.pop().unwrap().1);
+        }
+        let aggregate = AggregateQuoterClient::new(&self.url)
+            .with_timeout(Duration::from_secs(self.quote_timeout_secs));
+        Ok(Box::new(
+            quoters
+                .into_iter()
+                .fold(aggregate, |aggregate, (name, quoter)| {
+                    aggregate.with_quoter(name, quoter)
+                }),
+        ))
+    }
+
+    pub fn confirm_options(&self) -> ConfirmOptions {
+        ConfirmOptions {
+            commitment: match self.commitment {
+                CommitmentKind::Processed => CommitmentConfig::processed(),
+                CommitmentKind::Confirmed => CommitmentConfig::confirmed(),
+                CommitmentKind::Finalized => CommitmentConfig::finalized(),
+            },
+            max_resends: self.max_resends,
+            ..Default::default()
+        }
+    }
+
+    pub fn compute_budget(&self) -> ComputeBudgetOptions {
+        let unit_limit = match (self.compute_unit_limit, self.compute_unit_margin_bps) {
+            (Some(units), _) => ComputeUnitLimit::Fixed(units),
+            (None, Some(margin_bps)) => ComputeUnitLimit::Simulated { margin_bps },
+            (None, None) => ComputeUnitLimit::Default,
+        };
+        let priority_fee = match (
+            self.priority_fee_micro_lamports,
+            self.priority_fee_percentile,
+        ) {
+            (micro_lamports, Some(percentile)) => PriorityFee::RecentPercentile {
+                percentile,
+                max_micro_lamports: self.max_priority_fee_micro_lamports,
+                fallback_micro_lamports: micro_lamports.unwrap_or(0),
+            },
+            (Some(micro_lamports), None) => PriorityFee::Fixed(micro_lamports),
+            (None, None) => PriorityFee::None,
+        };
+        ComputeBudgetOptions {
+            unit_limit,
+            priority_fee,
+        }
+    }
 }
@@ -1,5 +1,9 @@
-use std::path::PathBuf;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
+use anyhow::Result;
 use lst_optimizer_utils::path::{get_workspace_file, resolve_path};
 
 pub fn get_package_file(f: &str) -> PathBuf {
@@ -9,3 +13,11 @@ pub fn get_package_file(f: &str) -> PathBuf {
 pub fn get_registry_file() -> PathBuf {
     get_workspace_file("registry.toml")
 }
+
+/// Write a temporary file renamed over the path, a crash mid-write keeps the previous contents
+pub fn write_atomically<P: AsRef<Path>>(path: P, contents: &str) -> Result<()> {
+    let tmp_path = path.as_ref().with_extension("tmp");
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
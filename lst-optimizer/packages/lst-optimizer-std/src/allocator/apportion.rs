@@ -0,0 +1,143 @@
+use anyhow::Result;
+use rust_decimal::{
+    prelude::{ToPrimitive, Zero},
+    Decimal,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ApportionError {
+    #[error("No weights to apportion")]
+    EmptyWeights,
+
+    #[error("Weight {0} must not be negative")]
+    NegativeWeight(Decimal),
+
+    #[error("Total weight must be greater than zero")]
+    ZeroTotalWeight,
+
+    #[error("Failed to apportion {0} between the weights")]
+    FailedToApportion(u64),
+}
+
+/// Apportion `total` units between the weights using the largest remainder method.
+///
+/// Every weight receives the floor of its exact quota, the units left over are then handed
+/// one by one to the largest fractional remainders (ties go to the earlier weight), so the
+/// result always sums exactly to `total`.
+pub fn apportion(weights: &[Decimal], total: u64) -> Result<Vec<u64>> {
+    if weights.is_empty() {
+        return Err(ApportionError::EmptyWeights.into());
+    }
+
+    let mut total_weight = Decimal::zero();
+    for weight in weights.iter() {
+        if weight.is_sign_negative() && !weight.is_zero() {
+            return Err(ApportionError::NegativeWeight(*weight).into());
+        }
+        total_weight = total_weight
+            .checked_add(*weight)
+            .ok_or(ApportionError::FailedToApportion(total))?;
+    }
+    if total_weight.is_zero() {
+        return Err(ApportionError::ZeroTotalWeight.into());
+    }
+
+    let total_dec = Decimal::from(total);
+    let mut floors: Vec<u64> = Vec::with_capacity(weights.len());
+    let mut remainders: Vec<(usize, Decimal)> = Vec::with_capacity(weights.len());
+    for (index, weight) in weights.iter().enumerate() {
+        let quota = weight
+            .checked_mul(total_dec)
+            .and_then(|v| v.checked_div(total_weight))
+            .ok_or(ApportionError::FailedToApportion(total))?;
+        let floor = quota.floor();
+        floors.push(
+            floor
+                .to_u64()
+                .ok_or(ApportionError::FailedToApportion(total))?,
+        );
+        remainders.push((index, quota - floor));
+    }
+
+    let allocated: u64 = floors.iter().sum();
+    let leftover = total
+        .checked_sub(allocated)
+        .ok_or(ApportionError::FailedToApportion(total))? as usize;
+
+    // Stable sort keeps the original order between equal remainders
+    remainders.sort_by(|a, b| b.1.cmp(&a.1));
+    for (index, _) in remainders.iter().take(leftover) {
+        floors[*index] += 1;
+    }
+
+    Ok(floors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimals(values: &[i64]) -> Vec<Decimal> {
+        values.iter().map(|v| Decimal::from(*v)).collect()
+    }
+
+    #[test]
+    fn test_apportion_equal_weights_sum_exactly() {
+        for n in 1..=12 {
+            let weights = vec![Decimal::ONE; n];
+            let parts = apportion(&weights, 10_000).unwrap();
+            assert_eq!(parts.iter().sum::<u64>(), 10_000);
+            let max = parts.iter().max().unwrap();
+            let min = parts.iter().min().unwrap();
+            assert!(max - min <= 1);
+        }
+    }
+
+    #[test]
+    fn test_apportion_three_ways() {
+        let parts = apportion(&decimals(&[1, 1, 1]), 10_000).unwrap();
+        assert_eq!(parts, vec![3334, 3333, 3333]);
+    }
+
+    #[test]
+    fn test_apportion_largest_remainder_wins() {
+        // quotas: 1428.57, 2857.14, 5714.28
+        let parts = apportion(&decimals(&[1, 2, 4]), 10_000).unwrap();
+        assert_eq!(parts, vec![1429, 2857, 5714]);
+    }
+
+    #[test]
+    fn test_apportion_zero_weight_gets_nothing() {
+        let parts = apportion(&decimals(&[0, 1, 1]), 3).unwrap();
+        assert_eq!(parts, vec![0, 2, 1]);
+    }
+
+    #[test]
+    fn test_apportion_zero_total() {
+        let parts = apportion(&decimals(&[1, 2]), 0).unwrap();
+        assert_eq!(parts, vec![0, 0]);
+    }
+
+    #[test]
+    fn test_apportion_fail_on_invalid_weights() {
+        assert_eq!(
+            apportion(&[], 10_000).err().unwrap().to_string(),
+            ApportionError::EmptyWeights.to_string()
+        );
+        assert_eq!(
+            apportion(&decimals(&[0, 0]), 10_000)
+                .err()
+                .unwrap()
+                .to_string(),
+            ApportionError::ZeroTotalWeight.to_string()
+        );
+        assert_eq!(
+            apportion(&decimals(&[1, -1]), 10_000)
+                .err()
+                .unwrap()
+                .to_string(),
+            ApportionError::NegativeWeight(Decimal::from(-1)).to_string()
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use anyhow::Result;
+use rust_decimal::{prelude::Zero, Decimal};
+use thiserror::Error;
+
+use super::apportion::apportion;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ConstraintError {
+    #[error("Invalid bps constraint for {0}, min {1} and max {2} must be within 0 and {3}")]
+    InvalidConstraint(String, u16, u16, u64),
+
+    #[error("Constraints are infeasible, the minimums sum to {0} and the maximums sum to {1} while the total is {2}")]
+    InfeasibleConstraints(u64, u64, u64),
+
+    #[error("Failed to project the bps onto the constraints")]
+    FailedToProject,
+}
+
+/// The allowed bps range of an asset
+#[derive(Debug, Clone, PartialEq)]
+pub struct BpsConstraint {
+    pub mint: String,
+    pub min_bps: u16,
+    pub max_bps: u16,
+}
+
+impl BpsConstraint {
+    pub fn new(mint: &str, min_bps: u16, max_bps: u16) -> Self {
+        Self {
+            mint: mint.to_string(),
+            min_bps,
+            max_bps,
+        }
+    }
+}
+
+/// Project the bps onto the constraints while keeping the total.
+///
+/// The free assets are scaled proportionally to their bps to fill the remaining total, then
+/// the assets breaking their range are pinned to the violated bound (caps first when the excess
+/// outweighs the shortfall, floors otherwise) and the rest is redistributed to the remaining
+/// free assets until no constraint is violated. The result is apportioned into integer bps.
+pub fn project_bps(bps: &[Decimal], constraints: &[BpsConstraint], total: u64) -> Result<Vec<u64>> {
+    if bps.len() != constraints.len() {
+        return Err(ConstraintError::FailedToProject.into());
+    }
+
+    let mut min_total: u64 = 0;
+    let mut max_total: u64 = 0;
+    for constraint in constraints.iter() {
+        if constraint.min_bps > constraint.max_bps || constraint.max_bps as u64 > total {
+            return Err(ConstraintError::InvalidConstraint(
+                constraint.mint.clone(),
+                constraint.min_bps,
+                constraint.max_bps,
+                total,
+            )
+            .into());
+        }
+        min_total += constraint.min_bps as u64;
+        max_total += constraint.max_bps as u64;
+    }
+    if min_total > total || max_total < total {
+        return Err(ConstraintError::InfeasibleConstraints(min_total, max_total, total).into());
+    }
+
+    let mut pinned: Vec<Option<u64>> = vec![None; bps.len()];
+    let mut free_bps: Vec<Decimal> = vec![Decimal::zero(); bps.len()];
+    for _ in 0..=bps.len() {
+        let free: Vec<usize> = (0..bps.len()).filter(|i| pinned[*i].is_none()).collect();
+        let pinned_total: u64 = pinned.iter().flatten().sum();
+        let remaining = Decimal::from(
+            total
+                .checked_sub(pinned_total)
+                .ok_or(ConstraintError::FailedToProject)?,
+        );
+        if free.is_empty() {
+            if !remaining.is_zero() {
+                return Err(ConstraintError::FailedToProject.into());
+            }
+            break;
+        }
+
+        // Scale the free assets proportionally to fill the remaining bps
+        let weight_total: Decimal = free.iter().map(|i| bps[*i].max(Decimal::zero())).sum();
+        for i in free.iter() {
+            free_bps[*i] = if weight_total.is_zero() {
+                remaining / Decimal::from(free.len())
+            } else {
+                bps[*i].max(Decimal::zero()) * remaining / weight_total
+            };
+        }
+
+        let mut excess = Decimal::zero();
+        let mut shortfall = Decimal::zero();
+        for i in free.iter() {
+            let constraint = &constraints[*i];
+            excess += (free_bps[*i] - Decimal::from(constraint.max_bps)).max(Decimal::zero());
+            shortfall += (Decimal::from(constraint.min_bps) - free_bps[*i]).max(Decimal::zero());
+        }
+        if excess.is_zero() && shortfall.is_zero() {
+            break;
+        }
+
+        for i in free.iter() {
+            let constraint = &constraints[*i];
+            if excess >= shortfall && free_bps[*i] > Decimal::from(constraint.max_bps) {
+                pinned[*i] = Some(constraint.max_bps as u64);
+            } else if excess < shortfall && free_bps[*i] < Decimal::from(constraint.min_bps) {
+                pinned[*i] = Some(constraint.min_bps as u64);
+            }
+        }
+    }
+
+    // Apportion the remaining bps between the free assets
+    let free: Vec<usize> = (0..bps.len()).filter(|i| pinned[*i].is_none()).collect();
+    let pinned_total: u64 = pinned.iter().flatten().sum();
+    let mut projected: Vec<u64> = pinned.iter().map(|p| p.unwrap_or(0)).collect();
+    if !free.is_empty() {
+        let free_weights: Vec<Decimal> = free.iter().map(|i| free_bps[*i]).collect();
+        let remaining = total
+            .checked_sub(pinned_total)
+            .ok_or(ConstraintError::FailedToProject)?;
+        let parts = if free_weights.iter().all(|w| w.is_zero()) {
+            apportion(&vec![Decimal::ONE; free.len()], remaining)?
+        } else {
+            apportion(&free_weights, remaining)?
+        };
+        for (i, part) in free.iter().zip(parts) {
+            projected[*i] = part;
+        }
+    }
+
+    Ok(projected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimals(values: &[i64]) -> Vec<Decimal> {
+        values.iter().map(|v| Decimal::from(*v)).collect()
+    }
+
+    #[test]
+    fn test_project_bps_within_constraints_is_unchanged() {
+        let constraints = vec![
+            BpsConstraint::new("a", 0, 10_000),
+            BpsConstraint::new("b", 0, 10_000),
+        ];
+        let projected = project_bps(&decimals(&[6000, 4000]), &constraints, 10_000).unwrap();
+        assert_eq!(projected, vec![6000, 4000]);
+    }
+
+    #[test]
+    fn test_project_bps_caps_and_redistributes_excess() {
+        let constraints = vec![
+            BpsConstraint::new("a", 0, 2500),
+            BpsConstraint::new("b", 0, 10_000),
+            BpsConstraint::new("c", 0, 10_000),
+        ];
+        let projected = project_bps(&decimals(&[8000, 1000, 1000]), &constraints, 10_000).unwrap();
+        assert_eq!(projected, vec![2500, 3750, 3750]);
+    }
+
+    #[test]
+    fn test_project_bps_caps_iteratively() {
+        // "b" only breaks its cap after "a" excess has been redistributed
+        let constraints = vec![
+            BpsConstraint::new("a", 0, 2500),
+            BpsConstraint::new("b", 0, 2500),
+            BpsConstraint::new("c", 0, 10_000),
+            BpsConstraint::new("d", 0, 10_000),
+        ];
+        let projected =
+            project_bps(&decimals(&[7000, 2000, 500, 500]), &constraints, 10_000).unwrap();
+        assert_eq!(projected, vec![2500, 2500, 2500, 2500]);
+    }
+
+    #[test]
+    fn test_project_bps_raises_floors() {
+        let constraints = vec![
+            BpsConstraint::new("a", 0, 10_000),
+            BpsConstraint::new("b", 0, 10_000),
+            BpsConstraint::new("sol", 500, 10_000),
+        ];
+        let projected = project_bps(&decimals(&[5000, 5000, 0]), &constraints, 10_000).unwrap();
+        assert_eq!(projected, vec![4750, 4750, 500]);
+    }
+
+    #[test]
+    fn test_project_bps_disabled_asset() {
+        let constraints = vec![
+            BpsConstraint::new("a", 0, 0),
+            BpsConstraint::new("b", 0, 10_000),
+            BpsConstraint::new("c", 0, 10_000),
+        ];
+        let projected = project_bps(&decimals(&[3334, 3333, 3333]), &constraints, 10_000).unwrap();
+        assert_eq!(projected, vec![0, 5000, 5000]);
+    }
+
+    #[test]
+    fn test_project_bps_fail_on_infeasible_constraints() {
+        let constraints = vec![
+            BpsConstraint::new("a", 0, 2500),
+            BpsConstraint::new("b", 0, 2500),
+        ];
+        let ret = project_bps(&decimals(&[5000, 5000]), &constraints, 10_000);
+        assert_eq!(
+            ret.err().unwrap().to_string(),
+            ConstraintError::InfeasibleConstraints(0, 5000, 10_000).to_string()
+        );
+    }
+
+    #[test]
+    fn test_project_bps_fail_on_invalid_constraint() {
+        let constraints = vec![BpsConstraint::new("a", 3000, 2000)];
+        let ret = project_bps(&decimals(&[10_000]), &constraints, 10_000);
+        assert_eq!(
+            ret.err().unwrap().to_string(),
+            ConstraintError::InvalidConstraint("a".to_string(), 3000, 2000, 10_000).to_string()
+        );
+    }
+}
@@ -1,11 +1,36 @@
+pub mod apportion;
+pub mod constraint;
+pub mod processor;
+
 use anyhow::Result;
+use apportion::apportion;
+use constraint::{project_bps, BpsConstraint};
 use lst_optimizer_utils::logger::info;
 use rust_decimal::{prelude::Zero, Decimal};
 
-use crate::types::{asset::Asset, datapoint::SymbolData, pool_allocation::MAX_ALLOCATION_BPS};
+use crate::types::{
+    asset::Asset,
+    datapoint::SymbolData,
+    pool_allocation::{PoolAllocations, MAX_ALLOCATION_BPS},
+};
 
-pub trait Allocator<T> {
+pub trait Allocator<T>: Send + Sync {
     fn allocate(&self, symbol_datas: Vec<SymbolData<T>>) -> Result<AllocationRatios>;
+
+    /// Allocate knowing the current pool allocations, allocators that care about
+    /// the incumbent assets (e.g. to avoid churn) override this
+    fn allocate_with_current(
+        &self,
+        symbol_datas: Vec<SymbolData<T>>,
+        _current_allocations: &PoolAllocations,
+    ) -> Result<AllocationRatios> {
+        self.allocate(symbol_datas)
+    }
+}
+
+/// Whether the mints are the same, the registry and the allocators may differ in case
+pub fn is_same_mint(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -23,6 +48,19 @@ impl AllocationRatio {
     }
 }
 
+/// How the registry asset weights are composed with the allocator output
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WeightMode {
+    /// Replace the allocated bps by the normalized weights of the allocated assets
+    #[default]
+    Override,
+    /// Multiply the allocated bps by the weights and renormalize, the weights act as
+    /// risk multipliers on top of the allocator output
+    Multiply,
+    /// Keep the allocated bps as is
+    Ignore,
+}
+
 #[derive(Debug, Clone)]
 pub struct AllocationRatios {
     pub asset_alloc_ratios: Vec<AllocationRatio>,
@@ -33,33 +71,87 @@ impl AllocationRatios {
         Self { asset_alloc_ratios }
     }
 
-    pub fn apply_weights(&mut self, assets: &Vec<Asset>) {
-        let max_allocation_bps = Decimal::from(MAX_ALLOCATION_BPS);
+    /// Create allocation ratios from real-valued weights (scores), apportioned into integer bps
+    /// that sum exactly to the max allocation bps
+    pub fn from_weights(mint_weights: Vec<(String, Decimal)>) -> Result<Self> {
+        let weights: Vec<Decimal> = mint_weights.iter().map(|(_, weight)| *weight).collect();
+        let bps = apportion(&weights, MAX_ALLOCATION_BPS as u64)?;
+        Ok(Self::new(
+            mint_weights
+                .into_iter()
+                .zip(bps)
+                .map(|((mint, _), bps)| AllocationRatio {
+                    bps: Decimal::from(bps),
+                    mint,
+                })
+                .collect(),
+        ))
+    }
+
+    /// Compose the asset weights with the allocation ratios with the given mode
+    pub fn compose_weights(&mut self, assets: &Vec<Asset>, mode: WeightMode) -> Result<()> {
+        match mode {
+            WeightMode::Override => self.apply_weights(assets),
+            WeightMode::Multiply => self.multiply_weights(assets),
+            WeightMode::Ignore => Ok(()),
+        }
+    }
+
+    /// Multiply the allocation ratios by the asset weights and renormalize them,
+    /// the assets without a known weight keep a weight of 1
+    pub fn multiply_weights(&mut self, assets: &Vec<Asset>) -> Result<()> {
+        let weighted_bps: Vec<Decimal> = self
+            .asset_alloc_ratios
+            .iter()
+            .map(|symbol_ratio| {
+                let weight = assets
+                    .iter()
+                    .find(|asset| is_same_mint(&asset.mint, &symbol_ratio.mint))
+                    .map(|asset| asset.weight)
+                    .unwrap_or(Decimal::ONE);
+                symbol_ratio.bps * weight
+            })
+            .collect();
+        let bps_per_asset = apportion(&weighted_bps, MAX_ALLOCATION_BPS as u64)?;
+
+        for (symbol_ratio, bps) in self.asset_alloc_ratios.iter_mut().zip(bps_per_asset) {
+            let bps = Decimal::from(bps);
+            info!(
+                "Multiplied weight for {} from {} to {}",
+                symbol_ratio.mint, symbol_ratio.bps, bps
+            );
+            symbol_ratio.bps = bps;
+        }
 
+        Ok(())
+    }
+
+    pub fn apply_weights(&mut self, assets: &Vec<Asset>) -> Result<()> {
         // Filter out weights that are not in the allocation ratios
         let assets: &Vec<Asset> = &assets
             .iter()
             .filter(|asset| {
-                let s = &asset.mint.to_lowercase();
-                self.asset_alloc_ratios.iter().any(|symbol_ratio| {
-                    let sr = &symbol_ratio.mint.to_lowercase();
-                    sr.eq(s)
-                })
+                self.asset_alloc_ratios
+                    .iter()
+                    .any(|symbol_ratio| is_same_mint(&symbol_ratio.mint, &asset.mint))
             })
             .cloned()
             .collect();
 
-        // Calculate total weight of all assets
-        let mut total_weight = Decimal::zero();
-        for asset in assets {
-            total_weight += asset.weight;
+        // No known weight for the allocated assets, the allocation is kept as is
+        if assets.is_empty() {
+            return Ok(());
         }
 
+        // Apportion the max allocation bps between the weights of the allocated assets
+        let weights: Vec<Decimal> = assets.iter().map(|asset| asset.weight).collect();
+        let bps_per_asset = apportion(&weights, MAX_ALLOCATION_BPS as u64)?;
+
         // Adjust allocation ratios based on weights
         for symbol_ratio in self.asset_alloc_ratios.iter_mut() {
-            for asset in assets.iter() {
-                if symbol_ratio.mint == asset.mint {
-                    symbol_ratio.bps = (asset.weight / total_weight) * max_allocation_bps;
+            for (asset, bps) in assets.iter().zip(bps_per_asset.iter()) {
+                if is_same_mint(&symbol_ratio.mint, &asset.mint) {
+                    symbol_ratio.bps = Decimal::from(*bps);
                     info!(
                         "Adjusted weight for {} to {} = {}",
                         symbol_ratio.mint, asset.weight, symbol_ratio.bps
@@ -67,6 +159,143 @@ impl AllocationRatios {
                 }
             }
         }
+
+        Ok(())
+    }
+
+    /// Project the allocation ratios onto the assets min/max bps and enabled constraints,
+    /// the excess of capped assets is redistributed to the unconstrained ones. Enabled assets
+    /// with a minimum bps are added to the allocation when missing.
+    pub fn apply_constraints(&mut self, assets: &Vec<Asset>) -> Result<()> {
+        for asset in assets.iter() {
+            let is_allocated = self
+                .asset_alloc_ratios
+                .iter()
+                .any(|symbol_ratio| is_same_mint(&symbol_ratio.mint, &asset.mint));
+            if !is_allocated && asset.enabled && asset.min_bps.unwrap_or(0) > 0 {
+                self.asset_alloc_ratios
+                    .push(AllocationRatio::new(&asset.mint, 0));
+            }
+        }
+
+        let max_allocation_bps = MAX_ALLOCATION_BPS as u16;
+        let constraints: Vec<BpsConstraint> = self
+            .asset_alloc_ratios
+            .iter()
+            .map(|symbol_ratio| {
+                match assets
+                    .iter()
+                    .find(|asset| is_same_mint(&asset.mint, &symbol_ratio.mint))
+                {
+                    Some(asset) if !asset.enabled => BpsConstraint::new(&asset.mint, 0, 0),
+                    Some(asset) => BpsConstraint::new(
+                        &asset.mint,
+                        asset.min_bps.unwrap_or(0),
+                        asset.max_bps.unwrap_or(max_allocation_bps),
+                    ),
+                    None => BpsConstraint::new(&symbol_ratio.mint, 0, max_allocation_bps),
+                }
+            })
+            .collect();
+
+        let bps: Vec<Decimal> = self
+            .asset_alloc_ratios
+            .iter()
+            .map(|symbol_ratio| symbol_ratio.bps)
+            .collect();
+        let projected = project_bps(&bps, &constraints, MAX_ALLOCATION_BPS as u64)?;
+
+        for (symbol_ratio, bps) in self.asset_alloc_ratios.iter_mut().zip(projected) {
+            let bps = Decimal::from(bps);
+            if symbol_ratio.bps != bps {
+                info!(
+                    "Constrained allocation for {} from {} to {}",
+                    symbol_ratio.mint, symbol_ratio.bps, bps
+                );
+                symbol_ratio.bps = bps;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Limit the turnover from the current pool allocations to the max turnover bps, the half sum
+    /// of the absolute bps changes. When exceeded, every asset only moves the same fraction of
+    /// the way from its current bps toward its target bps.
+    pub fn limit_turnover(
+        &mut self,
+        current_allocations: &PoolAllocations,
+        max_turnover_bps: u16,
+    ) -> Result<()> {
+        if current_allocations.get_total_lamports() == 0 {
+            return Ok(());
+        }
+
+        // Union of the current and target mints, the targets first
+        let mut mints: Vec<String> = self
+            .asset_alloc_ratios
+            .iter()
+            .map(|symbol_ratio| symbol_ratio.mint.clone())
+            .collect();
+        for asset in current_allocations.assets.iter() {
+            if !mints.iter().any(|mint| is_same_mint(mint, &asset.mint)) {
+                mints.push(asset.mint.clone());
+            }
+        }
+
+        let total_lamports = Decimal::from(current_allocations.get_total_lamports());
+        let mut current_bps: Vec<Decimal> = vec![];
+        let mut target_bps: Vec<Decimal> = vec![];
+        let mut turnover = Decimal::zero();
+        for mint in mints.iter() {
+            let current = current_allocations
+                .assets
+                .iter()
+                .find(|asset| is_same_mint(&asset.mint, mint))
+                .map(|asset| {
+                    Decimal::from(asset.lamports) * Decimal::from(MAX_ALLOCATION_BPS)
+                        / total_lamports
+                })
+                .unwrap_or(Decimal::zero());
+            let target = self
+                .asset_alloc_ratios
+                .iter()
+                .find(|symbol_ratio| is_same_mint(&symbol_ratio.mint, mint))
+                .map(|symbol_ratio| symbol_ratio.bps)
+                .unwrap_or(Decimal::zero());
+            turnover += (target - current).abs();
+            current_bps.push(current);
+            target_bps.push(target);
+        }
+        let turnover = turnover / Decimal::TWO;
+
+        let max_turnover = Decimal::from(max_turnover_bps);
+        if turnover <= max_turnover {
+            return Ok(());
+        }
+        info!(
+            "Turnover {} bps exceeds the max turnover {} bps",
+            turnover, max_turnover
+        );
+
+        let fraction = max_turnover / turnover;
+        let blended: Vec<Decimal> = current_bps
+            .iter()
+            .zip(target_bps.iter())
+            .map(|(current, target)| current + (target - current) * fraction)
+            .collect();
+        let bps_per_asset = apportion(&blended, MAX_ALLOCATION_BPS as u64)?;
+
+        self.asset_alloc_ratios = mints
+            .into_iter()
+            .zip(bps_per_asset)
+            .map(|(mint, bps)| AllocationRatio {
+                bps: Decimal::from(bps),
+                mint,
+            })
+            .collect();
+
+        Ok(())
     }
 
     pub fn validate(&self) -> Result<()> {
@@ -89,6 +318,8 @@ impl AllocationRatios {
 
 #[cfg(test)]
 mod tests {
+    use crate::types::pool_asset::PoolAsset;
+
     use super::*;
 
     #[test]
@@ -107,7 +338,7 @@ mod tests {
                 mint: "inf".to_string(),
             },
         ]);
-        allocation.apply_weights(&assets);
+        allocation.apply_weights(&assets).unwrap();
         assert_eq!(allocation.asset_alloc_ratios[0].bps, (8000).into());
         assert_eq!(allocation.asset_alloc_ratios[1].bps, (2000).into());
     }
@@ -119,7 +350,7 @@ mod tests {
             bps: (10000).into(),
             mint: "jupsol".to_string(),
         }]);
-        allocation.apply_weights(&assets);
+        allocation.apply_weights(&assets).unwrap();
         assert_eq!(allocation.asset_alloc_ratios[0].bps, (10000).into());
     }
 
@@ -134,10 +365,294 @@ mod tests {
             bps: (10000).into(),
             mint: "jupsol".to_string(),
         }]);
-        allocation.apply_weights(&assets);
+        allocation.apply_weights(&assets).unwrap();
         assert_eq!(allocation.asset_alloc_ratios[0].bps, (10000).into());
     }
 
+    #[test]
+    fn test_allocation_apply_weight_success_repeating_decimals() {
+        let assets = vec![
+            Asset::new_with_weight("jupsol", 1.0),
+            Asset::new_with_weight("inf", 1.0),
+            Asset::new_with_weight("jitosol", 1.0),
+        ];
+        let mut allocation = AllocationRatios::new(vec![
+            AllocationRatio::new("jupsol", 5000),
+            AllocationRatio::new("inf", 2500),
+            AllocationRatio::new("jitosol", 2500),
+        ]);
+        allocation.apply_weights(&assets).unwrap();
+        assert_eq!(allocation.asset_alloc_ratios[0].bps, (3334).into());
+        assert_eq!(allocation.asset_alloc_ratios[1].bps, (3333).into());
+        assert_eq!(allocation.asset_alloc_ratios[2].bps, (3333).into());
+        assert!(allocation.validate().is_ok());
+    }
+
+    #[test]
+    fn test_allocation_apply_weight_success_mint_case() {
+        let assets = vec![
+            Asset::new_with_weight("JupSOL", 0.8),
+            Asset::new_with_weight("inf", 0.2),
+        ];
+        let mut allocation = AllocationRatios::new(vec![
+            AllocationRatio::new("jupsol", 5000),
+            AllocationRatio::new("inf", 5000),
+        ]);
+        allocation.apply_weights(&assets).unwrap();
+        assert_eq!(allocation.asset_alloc_ratios[0].bps, (8000).into());
+        assert_eq!(allocation.asset_alloc_ratios[1].bps, (2000).into());
+        assert!(allocation.validate().is_ok());
+    }
+
+    #[test]
+    fn test_allocation_apply_weight_success_no_known_weight() {
+        let assets = vec![Asset::new_with_weight("jitosol", 1.0)];
+        let mut allocation = AllocationRatios::new(vec![
+            AllocationRatio::new("jupsol", 6000),
+            AllocationRatio::new("inf", 4000),
+        ]);
+        allocation.apply_weights(&assets).unwrap();
+        assert_eq!(allocation.asset_alloc_ratios[0].bps, (6000).into());
+        assert_eq!(allocation.asset_alloc_ratios[1].bps, (4000).into());
+    }
+
+    #[test]
+    fn test_allocation_from_weights_success() {
+        let allocation = AllocationRatios::from_weights(
+            ["a", "b", "c", "d", "e", "f", "g"]
+                .iter()
+                .map(|mint| (mint.to_string(), Decimal::ONE))
+                .collect(),
+        )
+        .unwrap();
+        assert_eq!(allocation.asset_alloc_ratios.len(), 7);
+        assert!(allocation.validate().is_ok());
+    }
+
+    fn test_weighted_assets() -> Vec<Asset> {
+        vec![
+            Asset::new_with_weight("jupsol", 1.0),
+            Asset::new_with_weight("inf", 0.5),
+        ]
+    }
+
+    fn test_weighted_allocation() -> AllocationRatios {
+        AllocationRatios::new(vec![
+            AllocationRatio::new("jupsol", 2000),
+            AllocationRatio::new("inf", 8000),
+        ])
+    }
+
+    #[test]
+    fn test_allocation_compose_weights_override() {
+        let mut allocation = test_weighted_allocation();
+        allocation
+            .compose_weights(&test_weighted_assets(), WeightMode::Override)
+            .unwrap();
+        assert_eq!(
+            allocation.asset_alloc_ratios,
+            vec![
+                AllocationRatio::new("jupsol", 6667),
+                AllocationRatio::new("inf", 3333),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_allocation_compose_weights_multiply() {
+        let mut allocation = test_weighted_allocation();
+        allocation
+            .compose_weights(&test_weighted_assets(), WeightMode::Multiply)
+            .unwrap();
+        // 2000 * 1.0 = 2000 and 8000 * 0.5 = 4000 renormalized
+        assert_eq!(
+            allocation.asset_alloc_ratios,
+            vec![
+                AllocationRatio::new("jupsol", 3333),
+                AllocationRatio::new("inf", 6667),
+            ]
+        );
+        assert!(allocation.validate().is_ok());
+    }
+
+    #[test]
+    fn test_allocation_compose_weights_multiply_unknown_asset() {
+        let mut allocation = AllocationRatios::new(vec![
+            AllocationRatio::new("jupsol", 5000),
+            AllocationRatio::new("unknown", 5000),
+        ]);
+        allocation
+            .compose_weights(&test_weighted_assets(), WeightMode::Multiply)
+            .unwrap();
+        assert_eq!(allocation.asset_alloc_ratios[0].bps, (5000).into());
+        assert_eq!(allocation.asset_alloc_ratios[1].bps, (5000).into());
+    }
+
+    #[test]
+    fn test_allocation_compose_weights_multiply_fail_on_zero_weights() {
+        let mut allocation = AllocationRatios::new(vec![AllocationRatio::new("sol", 10000)]);
+        let ret = allocation.compose_weights(
+            &vec![Asset::new_with_weight("sol", 0.0)],
+            WeightMode::Multiply,
+        );
+        assert!(ret.is_err());
+    }
+
+    #[test]
+    fn test_allocation_compose_weights_ignore() {
+        let mut allocation = test_weighted_allocation();
+        allocation
+            .compose_weights(&test_weighted_assets(), WeightMode::Ignore)
+            .unwrap();
+        assert_eq!(
+            allocation.asset_alloc_ratios,
+            test_weighted_allocation().asset_alloc_ratios
+        );
+    }
+
+    #[test]
+    fn test_allocation_apply_constraints_success() {
+        let assets = vec![
+            Asset::new_with_weight("jupsol", 1.0).with_bps_limits(None, Some(2500)),
+            Asset::new_with_weight("inf", 1.0),
+            Asset::new_with_weight("jitosol", 1.0).with_enabled(false),
+            Asset::new_with_weight("sol", 0.0).with_bps_limits(Some(1000), None),
+        ];
+        let mut allocation = AllocationRatios::new(vec![
+            AllocationRatio::new("jupsol", 6000),
+            AllocationRatio::new("inf", 2000),
+            AllocationRatio::new("jitosol", 2000),
+        ]);
+        allocation.apply_constraints(&assets).unwrap();
+        assert_eq!(
+            allocation.asset_alloc_ratios,
+            vec![
+                AllocationRatio::new("jupsol", 2500),
+                AllocationRatio::new("inf", 6500),
+                AllocationRatio::new("jitosol", 0),
+                AllocationRatio::new("sol", 1000),
+            ]
+        );
+        assert!(allocation.validate().is_ok());
+    }
+
+    #[test]
+    fn test_allocation_apply_constraints_success_mint_case() {
+        let assets = vec![
+            Asset::new_with_weight("JupSOL", 1.0).with_bps_limits(None, Some(2500)),
+            Asset::new_with_weight("INF", 1.0).with_bps_limits(Some(1000), None),
+        ];
+        let mut allocation = AllocationRatios::new(vec![
+            AllocationRatio::new("jupsol", 6000),
+            AllocationRatio::new("inf", 4000),
+        ]);
+        allocation.apply_constraints(&assets).unwrap();
+        // The constrained assets are matched, none is added again
+        assert_eq!(
+            allocation.asset_alloc_ratios,
+            vec![
+                AllocationRatio::new("jupsol", 2500),
+                AllocationRatio::new("inf", 7500),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_allocation_apply_constraints_fail_on_infeasible() {
+        let assets = vec![
+            Asset::new_with_weight("jupsol", 1.0).with_bps_limits(None, Some(2500)),
+            Asset::new_with_weight("inf", 1.0).with_bps_limits(None, Some(2500)),
+        ];
+        let mut allocation = AllocationRatios::new(vec![
+            AllocationRatio::new("jupsol", 5000),
+            AllocationRatio::new("inf", 5000),
+        ]);
+        assert!(allocation.apply_constraints(&assets).is_err());
+    }
+
+    #[test]
+    fn test_allocation_limit_turnover_success() {
+        let current_allocations = PoolAllocations {
+            assets: vec![
+                PoolAsset::new("jupsol", 500, 0),
+                PoolAsset::new("inf", 500, 0),
+            ],
+        };
+        let mut allocation = AllocationRatios::new(vec![
+            AllocationRatio::new("jupsol", 5000),
+            AllocationRatio::new("jitosol", 5000),
+        ]);
+        // Moving inf 5000 bps to jitosol is a 5000 bps turnover, limited to 2000
+        allocation
+            .limit_turnover(&current_allocations, 2000)
+            .unwrap();
+        assert_eq!(
+            allocation.asset_alloc_ratios,
+            vec![
+                AllocationRatio::new("jupsol", 5000),
+                AllocationRatio::new("jitosol", 2000),
+                AllocationRatio::new("inf", 3000),
+            ]
+        );
+        assert!(allocation.validate().is_ok());
+    }
+
+    #[test]
+    fn test_allocation_limit_turnover_within_limit() {
+        let current_allocations = PoolAllocations {
+            assets: vec![
+                PoolAsset::new("jupsol", 500, 0),
+                PoolAsset::new("inf", 500, 0),
+            ],
+        };
+        let mut allocation = AllocationRatios::new(vec![
+            AllocationRatio::new("jupsol", 6000),
+            AllocationRatio::new("inf", 4000),
+        ]);
+        allocation
+            .limit_turnover(&current_allocations, 2000)
+            .unwrap();
+        assert_eq!(allocation.asset_alloc_ratios[0].bps, (6000).into());
+        assert_eq!(allocation.asset_alloc_ratios[1].bps, (4000).into());
+    }
+
+    #[test]
+    fn test_allocation_limit_turnover_mint_case() {
+        let current_allocations = PoolAllocations {
+            assets: vec![
+                PoolAsset::new("JUPSOL", 500, 0),
+                PoolAsset::new("inf", 500, 0),
+            ],
+        };
+        let mut allocation = AllocationRatios::new(vec![
+            AllocationRatio::new("jupsol", 6000),
+            AllocationRatio::new("inf", 4000),
+        ]);
+        allocation
+            .limit_turnover(&current_allocations, 1000)
+            .unwrap();
+        assert_eq!(
+            allocation.asset_alloc_ratios,
+            vec![
+                AllocationRatio::new("jupsol", 6000),
+                AllocationRatio::new("inf", 4000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_allocation_limit_turnover_empty_pool() {
+        let current_allocations = PoolAllocations {
+            assets: vec![PoolAsset::new("inf", 0, 0)],
+        };
+        let mut allocation = AllocationRatios::new(vec![AllocationRatio::new("jupsol", 10000)]);
+        allocation.limit_turnover(&current_allocations, 0).unwrap();
+        assert_eq!(
+            allocation.asset_alloc_ratios,
+            vec![AllocationRatio::new("jupsol", 10000)]
+        );
+    }
+
     #[test]
     fn test_allocation_total_ratio_validation_succcess() {
         let allocation = AllocationRatios::new(vec![
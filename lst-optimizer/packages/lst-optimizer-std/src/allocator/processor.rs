@@ -0,0 +1,210 @@
+use anyhow::Result;
+
+use crate::types::{context::Context, pool_allocation::PoolAllocations};
+
+use super::{AllocationRatios, WeightMode};
+
+/// A post-processing step applied to the allocator output before it is turned into pool changes
+pub trait AllocationProcessor: Send + Sync {
+    fn process(
+        &self,
+        context: &Context,
+        current_allocations: &PoolAllocations,
+        allocations: AllocationRatios,
+    ) -> Result<AllocationRatios>;
+}
+
+/// Validate the allocations sum up to the max allocation bps
+#[derive(Debug, Clone, Default)]
+pub struct ValidateProcessor {}
+
+impl ValidateProcessor {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl AllocationProcessor for ValidateProcessor {
+    fn process(
+        &self,
+        _: &Context,
+        _: &PoolAllocations,
+        allocations: AllocationRatios,
+    ) -> Result<AllocationRatios> {
+        allocations.validate()?;
+        Ok(allocations)
+    }
+}
+
+/// Compose the known asset weights with the allocations
+#[derive(Debug, Clone, Default)]
+pub struct WeightsProcessor {
+    mode: WeightMode,
+}
+
+impl WeightsProcessor {
+    pub fn new() -> Self {
+        Self {
+            mode: WeightMode::default(),
+        }
+    }
+
+    pub fn with_mode(self, mode: WeightMode) -> Self {
+        Self { mode }
+    }
+}
+
+impl AllocationProcessor for WeightsProcessor {
+    fn process(
+        &self,
+        context: &Context,
+        _: &PoolAllocations,
+        allocations: AllocationRatios,
+    ) -> Result<AllocationRatios> {
+        let mut allocations = allocations;
+        allocations.compose_weights(&context.get_kwown_assets(), self.mode)?;
+        Ok(allocations)
+    }
+}
+
+/// Project the allocations onto the known asset min/max bps and enabled constraints
+#[derive(Debug, Clone, Default)]
+pub struct ConstraintsProcessor {}
+
+impl ConstraintsProcessor {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl AllocationProcessor for ConstraintsProcessor {
+    fn process(
+        &self,
+        context: &Context,
+        _: &PoolAllocations,
+        allocations: AllocationRatios,
+    ) -> Result<AllocationRatios> {
+        let mut allocations = allocations;
+        allocations.apply_constraints(&context.get_kwown_assets())?;
+        Ok(allocations)
+    }
+}
+
+/// Limit the turnover between the current pool allocations and the allocations
+#[derive(Debug, Clone)]
+pub struct TurnoverProcessor {
+    max_turnover_bps: u16,
+}
+
+impl TurnoverProcessor {
+    pub fn new(max_turnover_bps: u16) -> Self {
+        Self { max_turnover_bps }
+    }
+}
+
+impl AllocationProcessor for TurnoverProcessor {
+    fn process(
+        &self,
+        _: &Context,
+        current_allocations: &PoolAllocations,
+        allocations: AllocationRatios,
+    ) -> Result<AllocationRatios> {
+        let mut allocations = allocations;
+        allocations.limit_turnover(current_allocations, self.max_turnover_bps)?;
+        Ok(allocations)
+    }
+}
+
+/// The processors applied when no custom processors are configured
+pub fn default_processors() -> Vec<Box<dyn AllocationProcessor>> {
+    processors_with_weight_mode(WeightMode::default())
+}
+
+/// The default processors with the given weight composition mode
+pub fn processors_with_weight_mode(mode: WeightMode) -> Vec<Box<dyn AllocationProcessor>> {
+    processors_with_options(mode, None)
+}
+
+/// The default processors with the given weight composition mode and optional turnover limit,
+/// the constraints run last so a limited turnover never keeps a disabled or capped asset
+pub fn processors_with_options(
+    mode: WeightMode,
+    max_turnover_bps: Option<u16>,
+) -> Vec<Box<dyn AllocationProcessor>> {
+    let mut processors: Vec<Box<dyn AllocationProcessor>> = vec![
+        Box::new(ValidateProcessor::new()),
+        Box::new(WeightsProcessor::new().with_mode(mode)),
+    ];
+    if let Some(max_turnover_bps) = max_turnover_bps {
+        processors.push(Box::new(TurnoverProcessor::new(max_turnover_bps)));
+    }
+    processors.push(Box::new(ConstraintsProcessor::new()));
+    processors.push(Box::new(ValidateProcessor::new()));
+    processors
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        allocator::AllocationRatio,
+        types::{asset::Asset, asset_repository::AssetRepository, pool_asset::PoolAsset},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_default_processors_apply_weights() {
+        let context = Context::default().with_asset_repository(AssetRepository::new(vec![
+            Asset::new_with_weight("jupsol", 0.75),
+            Asset::new_with_weight("inf", 0.25),
+        ]));
+        let mut allocations = AllocationRatios::new(vec![
+            AllocationRatio::new("jupsol", 5000),
+            AllocationRatio::new("inf", 5000),
+        ]);
+        let current_allocations = PoolAllocations { assets: vec![] };
+        for processor in default_processors() {
+            allocations = processor
+                .process(&context, &current_allocations, allocations)
+                .unwrap();
+        }
+        assert_eq!(allocations.asset_alloc_ratios[0].bps, (7500).into());
+        assert_eq!(allocations.asset_alloc_ratios[1].bps, (2500).into());
+    }
+
+    #[test]
+    fn test_processors_with_options_constrain_after_turnover() {
+        let context = Context::default().with_asset_repository(AssetRepository::new(vec![
+            Asset::new_with_weight("jupsol", 1.0),
+            Asset::new_with_weight("inf", 1.0).with_enabled(false),
+        ]));
+        let mut allocations = AllocationRatios::new(vec![AllocationRatio::new("jupsol", 10000)]);
+        let current_allocations = PoolAllocations {
+            assets: vec![PoolAsset::new("inf", 500, 0)],
+        };
+        for processor in processors_with_options(WeightMode::default(), Some(2000)) {
+            allocations = processor
+                .process(&context, &current_allocations, allocations)
+                .unwrap();
+        }
+        // The limited turnover keeps 8000 bps of inf, the disabled asset is still emptied
+        assert_eq!(
+            allocations.asset_alloc_ratios,
+            vec![
+                AllocationRatio::new("jupsol", 10000),
+                AllocationRatio::new("inf", 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_processor_fail() {
+        let allocations = AllocationRatios::new(vec![AllocationRatio::new("jupsol", 5000)]);
+        let ret = ValidateProcessor::new().process(
+            &Context::default(),
+            &PoolAllocations { assets: vec![] },
+            allocations,
+        );
+        assert!(ret.is_err());
+    }
+}
@@ -3,6 +3,6 @@ use anyhow::Result;
 use crate::types::asset::Asset;
 
 #[async_trait::async_trait]
-pub trait Fetcher<T> {
+pub trait Fetcher<T>: Send + Sync {
     async fn fetch(&self, asset: &Asset) -> Result<Vec<T>>;
 }
@@ -6,10 +6,12 @@ use crate::{
     allocator::AllocationRatios,
     types::{
         context::Context,
+        netted_change::NettedChange,
         pool_allocation::PoolAllocations,
         pool_allocation_changes::{
             PoolAllocationChanges, PoolAllocationLamportsChanges, PoolAssetChange,
         },
+        rebalance_outcome::RebalanceOutcome,
     },
 };
 
@@ -46,9 +48,17 @@ pub trait PoolAllocable {
 
 #[async_trait::async_trait]
 pub trait PoolRebalancable {
+    /// Rebalance a pool asset change, the outcome carries the error when it failed
     async fn rebalance_asset(
         &self,
         context: &Context,
         pool_asset_change: &PoolAssetChange,
-    ) -> Result<()>;
+    ) -> RebalanceOutcome;
+
+    /// Rebalance a netted change, swapping directly between lsts when both sides are set
+    async fn rebalance_netted_change(
+        &self,
+        context: &Context,
+        netted_change: &NettedChange,
+    ) -> RebalanceOutcome;
 }
@@ -19,4 +19,99 @@ impl AmountChange {
             _ => false,
         }
     }
+
+    pub fn get_lamports(&self) -> u64 {
+        match self {
+            AmountChange::Increase { lamports, .. } => *lamports,
+            AmountChange::Decrease { lamports, .. } => *lamports,
+        }
+    }
+
+    pub fn get_lst_amount(&self) -> u64 {
+        match self {
+            AmountChange::Increase { lst_amount, .. } => *lst_amount,
+            AmountChange::Decrease { lst_amount, .. } => *lst_amount,
+        }
+    }
+
+    /// Shrink the change to `lamports`, the lst amount is scaled proportionally
+    pub fn shrink_to(&self, lamports: u64) -> AmountChange {
+        let current_lamports = self.get_lamports();
+        let lamports = lamports.min(current_lamports);
+        let lst_amount = if current_lamports == 0 {
+            0
+        } else {
+            (self.get_lst_amount() as u128 * lamports as u128 / current_lamports as u128) as u64
+        };
+        match self {
+            AmountChange::Increase { .. } => AmountChange::Increase {
+                lamports,
+                lst_amount,
+            },
+            AmountChange::Decrease { .. } => AmountChange::Decrease {
+                lamports,
+                lst_amount,
+            },
+        }
+    }
+
+    /// Split the change into the part of `lamports` and the rest, the lst amounts add up
+    pub fn split_at(&self, lamports: u64) -> (AmountChange, AmountChange) {
+        let taken = self.shrink_to(lamports);
+        let lamports = self.get_lamports() - taken.get_lamports();
+        let lst_amount = self.get_lst_amount() - taken.get_lst_amount();
+        let rest = match self {
+            AmountChange::Increase { .. } => AmountChange::Increase {
+                lamports,
+                lst_amount,
+            },
+            AmountChange::Decrease { .. } => AmountChange::Decrease {
+                lamports,
+                lst_amount,
+            },
+        };
+        (taken, rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shrink_to() {
+        let change = AmountChange::Decrease {
+            lamports: 1000,
+            lst_amount: 900,
+        };
+        assert_eq!(
+            change.shrink_to(500),
+            AmountChange::Decrease {
+                lamports: 500,
+                lst_amount: 450,
+            }
+        );
+        assert_eq!(change.shrink_to(2000), change);
+    }
+
+    #[test]
+    fn test_split_at() {
+        let change = AmountChange::Increase {
+            lamports: 1000,
+            lst_amount: 901,
+        };
+        assert_eq!(
+            change.split_at(300),
+            (
+                AmountChange::Increase {
+                    lamports: 300,
+                    lst_amount: 270,
+                },
+                AmountChange::Increase {
+                    lamports: 700,
+                    lst_amount: 631,
+                }
+            )
+        );
+    }
 }
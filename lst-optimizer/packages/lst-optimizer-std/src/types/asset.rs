@@ -8,6 +8,20 @@ pub struct Asset {
     pub weight: Decimal,
     pub token_program: String,
     pub pool: Option<PoolInfo>,
+    /// Minimum allocation of the asset in bps, the asset is always allocated when set
+    pub min_bps: Option<u16>,
+    /// Maximum allocation of the asset in bps
+    pub max_bps: Option<u16>,
+    /// Disabled assets are never allocated
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Decreases the loss guard rejects are unstaked over an epoch instead of sold
+    #[serde(default)]
+    pub delayed_unstake: bool,
+}
+
+fn default_enabled() -> bool {
+    true
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -27,6 +41,10 @@ impl Asset {
             weight: Decimal::from_f64(weight).unwrap(),
             token_program: "".to_string(),
             pool: None,
+            min_bps: None,
+            max_bps: None,
+            enabled: true,
+            delayed_unstake: false,
         }
     }
 
@@ -37,4 +55,23 @@ impl Asset {
     pub fn new_with_symbol(mint: &str, symbol: &str) -> Self {
         Asset::new(mint, symbol, 1.0)
     }
+
+    pub fn with_bps_limits(self, min_bps: Option<u16>, max_bps: Option<u16>) -> Self {
+        Self {
+            min_bps,
+            max_bps,
+            ..self
+        }
+    }
+
+    pub fn with_enabled(self, enabled: bool) -> Self {
+        Self { enabled, ..self }
+    }
+
+    pub fn with_delayed_unstake(self, delayed_unstake: bool) -> Self {
+        Self {
+            delayed_unstake,
+            ..self
+        }
+    }
 }
@@ -4,6 +4,10 @@ pub mod asset_repository;
 pub mod context;
 pub mod datapoint;
 pub mod lamports_change;
+pub mod netted_change;
 pub mod pool_allocation;
 pub mod pool_allocation_changes;
 pub mod pool_asset;
+pub mod rebalance_decision;
+pub mod rebalance_outcome;
+pub mod rebalance_plan;
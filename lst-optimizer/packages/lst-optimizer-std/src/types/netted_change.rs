@@ -0,0 +1,408 @@
+use std::fmt::Display;
+
+use crate::allocator::is_same_mint;
+
+use super::{
+    amount_change::AmountChange,
+    pool_allocation_changes::{PoolAllocationChanges, PoolAssetChange},
+};
+
+/// A single swap of a rebalance. A decrease netted against an increase swaps one lst
+/// directly into the other, an unmatched decrease or increase swaps through wSOL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NettedChange {
+    pub decrease: Option<PoolAssetChange>,
+    pub increase: Option<PoolAssetChange>,
+}
+
+impl NettedChange {
+    pub fn direct(decrease: PoolAssetChange, increase: PoolAssetChange) -> Self {
+        Self {
+            decrease: Some(decrease),
+            increase: Some(increase),
+        }
+    }
+
+    pub fn from_change(change: PoolAssetChange) -> Self {
+        match change.amount {
+            AmountChange::Increase { .. } => Self {
+                decrease: None,
+                increase: Some(change),
+            },
+            AmountChange::Decrease { .. } => Self {
+                decrease: Some(change),
+                increase: None,
+            },
+        }
+    }
+
+    pub fn is_direct(&self) -> bool {
+        self.decrease.is_some() && self.increase.is_some()
+    }
+
+    pub fn get_lamports(&self) -> u64 {
+        self.decrease
+            .as_ref()
+            .or(self.increase.as_ref())
+            .map(|change| change.amount.get_lamports())
+            .unwrap_or(0)
+    }
+
+    /// The mints swapped from and to, wSOL for a missing side
+    pub fn get_labels(&self) -> (&str, &str) {
+        let decrease = self
+            .decrease
+            .as_ref()
+            .map(|change| change.mint.as_str())
+            .unwrap_or("wSOL");
+        let increase = self
+            .increase
+            .as_ref()
+            .map(|change| change.mint.as_str())
+            .unwrap_or("wSOL");
+        (decrease, increase)
+    }
+
+    /// Shrink both sides to `lamports`, so the swap still nets out
+    pub fn shrink_to(&self, lamports: u64) -> Self {
+        let shrink = |change: &PoolAssetChange| {
+            PoolAssetChange::new(&change.mint, change.amount.shrink_to(lamports))
+        };
+        Self {
+            decrease: self.decrease.as_ref().map(shrink),
+            increase: self.increase.as_ref().map(shrink),
+        }
+    }
+}
+
+impl Display for NettedChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (decrease, increase) = self.get_labels();
+        write!(
+            f,
+            "{} -> {}: {} lamports",
+            decrease,
+            increase,
+            self.get_lamports()
+        )
+    }
+}
+
+/// The swaps of a rebalance, direct ones first, then the decreases into wSOL
+/// and the increases out of it
+#[derive(Debug, Clone)]
+pub struct NettedChanges {
+    pub changes: Vec<NettedChange>,
+}
+
+impl NettedChanges {
+    pub fn new(changes: Vec<NettedChange>) -> Self {
+        Self { changes }
+    }
+
+    /// Pair the decreases with the increases, equal changes first and then the largest
+    /// remaining ones, so that fewer swaps move the same volume. The residual goes through wSOL.
+    pub fn net(pool_allocation_changes: &PoolAllocationChanges) -> Self {
+        let (increases, decreases): (Vec<PoolAssetChange>, Vec<PoolAssetChange>) =
+            pool_allocation_changes
+                .assets
+                .iter()
+                .filter(|change| change.amount.get_lamports() > 0)
+                .cloned()
+                .partition(|change| change.amount.is_increase());
+        let mut increases: Vec<Option<PoolAssetChange>> = increases.into_iter().map(Some).collect();
+        let mut decreases: Vec<Option<PoolAssetChange>> = decreases.into_iter().map(Some).collect();
+        let mut changes: Vec<NettedChange> = vec![];
+
+        // A pair of equal changes nets out in a single swap
+        for decrease in decreases.iter_mut() {
+            let lamports = decrease.as_ref().unwrap().amount.get_lamports();
+            let equal = increases.iter_mut().find(|increase| {
+                increase
+                    .as_ref()
+                    .is_some_and(|increase| increase.amount.get_lamports() == lamports)
+            });
+            if let Some(increase) = equal {
+                changes.push(NettedChange::direct(
+                    decrease.take().unwrap(),
+                    increase.take().unwrap(),
+                ));
+            }
+        }
+
+        let mut decreases: Vec<PoolAssetChange> = decreases.into_iter().flatten().collect();
+        let mut increases: Vec<PoolAssetChange> = increases.into_iter().flatten().collect();
+        loop {
+            decreases.sort_by_key(|change| std::cmp::Reverse(change.amount.get_lamports()));
+            increases.sort_by_key(|change| std::cmp::Reverse(change.amount.get_lamports()));
+            if decreases.is_empty() || increases.is_empty() {
+                break;
+            }
+
+            let decrease = decreases.remove(0);
+            let increase = increases.remove(0);
+            let lamports = decrease
+                .amount
+                .get_lamports()
+                .min(increase.amount.get_lamports());
+            let (decrease_part, decrease_rest) = decrease.amount.split_at(lamports);
+            let (increase_part, increase_rest) = increase.amount.split_at(lamports);
+            changes.push(NettedChange::direct(
+                PoolAssetChange::new(&decrease.mint, decrease_part),
+                PoolAssetChange::new(&increase.mint, increase_part),
+            ));
+            if decrease_rest.get_lamports() > 0 {
+                decreases.push(PoolAssetChange::new(&decrease.mint, decrease_rest));
+            }
+            if increase_rest.get_lamports() > 0 {
+                increases.push(PoolAssetChange::new(&increase.mint, increase_rest));
+            }
+        }
+
+        changes.extend(decreases.into_iter().map(NettedChange::from_change));
+        changes.extend(increases.into_iter().map(NettedChange::from_change));
+        Self::new(changes)
+    }
+
+    /// The lamports moved by all the swaps
+    pub fn volume_lamports(&self) -> u64 {
+        self.changes
+            .iter()
+            .map(|change| change.get_lamports())
+            .sum()
+    }
+
+    /// The lamports of every swap the wSOL reserve of `wsol_lamports` funds, sent in order.
+    /// The swaps into wSOL fill the reserve, the ones out of it are funded as far as
+    /// it goes, the others are fully funded.
+    pub fn wsol_funded_lamports(&self, wsol_mint: &str, wsol_lamports: u64) -> Vec<u64> {
+        let is_wsol = |change: &Option<PoolAssetChange>| match change {
+            Some(change) => is_same_mint(&change.mint, wsol_mint),
+            None => true,
+        };
+        let mut reserve = wsol_lamports;
+        self.changes
+            .iter()
+            .map(|change| {
+                let lamports = change.get_lamports();
+                match (is_wsol(&change.decrease), is_wsol(&change.increase)) {
+                    (true, false) => {
+                        let funded = lamports.min(reserve);
+                        reserve -= funded;
+                        funded
+                    }
+                    (false, true) => {
+                        reserve = reserve.saturating_add(lamports);
+                        lamports
+                    }
+                    _ => lamports,
+                }
+            })
+            .collect()
+    }
+
+    /// The pool allocation changes the swaps add up to, one per mint
+    pub fn to_pool_allocation_changes(&self) -> PoolAllocationChanges {
+        let mut assets: Vec<PoolAssetChange> = vec![];
+        let sides = self
+            .changes
+            .iter()
+            .flat_map(|change| change.decrease.iter().chain(change.increase.iter()));
+        for side in sides {
+            let merged = assets.iter_mut().find(|asset| {
+                asset.mint == side.mint && asset.amount.is_increase() == side.amount.is_increase()
+            });
+            let Some(asset) = merged else {
+                assets.push(side.clone());
+                continue;
+            };
+            let lamports = asset.amount.get_lamports() + side.amount.get_lamports();
+            let lst_amount = asset.amount.get_lst_amount() + side.amount.get_lst_amount();
+            asset.amount = match asset.amount {
+                AmountChange::Increase { .. } => AmountChange::Increase {
+                    lamports,
+                    lst_amount,
+                },
+                AmountChange::Decrease { .. } => AmountChange::Decrease {
+                    lamports,
+                    lst_amount,
+                },
+            };
+        }
+        PoolAllocationChanges::new(assets)
+    }
+}
+
+impl Display for NettedChanges {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "NettedChanges:")?;
+        for change in self.changes.iter() {
+            writeln!(f, " - {}", change)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn increase(mint: &str, lamports: u64) -> PoolAssetChange {
+        PoolAssetChange::new(
+            mint,
+            AmountChange::Increase {
+                lamports,
+                lst_amount: lamports * 9 / 10,
+            },
+        )
+    }
+
+    fn decrease(mint: &str, lamports: u64) -> PoolAssetChange {
+        PoolAssetChange::new(
+            mint,
+            AmountChange::Decrease {
+                lamports,
+                lst_amount: lamports * 9 / 10,
+            },
+        )
+    }
+
+    fn legs(netted: &NettedChanges) -> Vec<(Option<String>, Option<String>, u64)> {
+        netted
+            .changes
+            .iter()
+            .map(|change| {
+                (
+                    change.decrease.as_ref().map(|change| change.mint.clone()),
+                    change.increase.as_ref().map(|change| change.mint.clone()),
+                    change.get_lamports(),
+                )
+            })
+            .collect()
+    }
+
+    fn leg(
+        decrease: Option<&str>,
+        increase: Option<&str>,
+        lamports: u64,
+    ) -> (Option<String>, Option<String>, u64) {
+        (
+            decrease.map(String::from),
+            increase.map(String::from),
+            lamports,
+        )
+    }
+
+    #[test]
+    fn test_net_equal_changes() {
+        let netted = NettedChanges::net(&PoolAllocationChanges::new(vec![
+            decrease("msol", 1000),
+            decrease("bsol", 400),
+            increase("jitosol", 600),
+            increase("inf", 400),
+        ]));
+        assert_eq!(
+            legs(&netted),
+            vec![
+                leg(Some("bsol"), Some("inf"), 400),
+                leg(Some("msol"), Some("jitosol"), 600),
+                leg(Some("msol"), None, 400),
+            ]
+        );
+        assert_eq!(netted.volume_lamports(), 1400);
+        assert!(netted.changes[0].is_direct());
+        assert!(!netted.changes[2].is_direct());
+    }
+
+    #[test]
+    fn test_net_largest_first() {
+        let netted = NettedChanges::net(&PoolAllocationChanges::new(vec![
+            decrease("msol", 700),
+            decrease("bsol", 300),
+            increase("jitosol", 500),
+            increase("inf", 600),
+        ]));
+        assert_eq!(
+            legs(&netted),
+            vec![
+                leg(Some("msol"), Some("inf"), 600),
+                leg(Some("bsol"), Some("jitosol"), 300),
+                leg(Some("msol"), Some("jitosol"), 100),
+                leg(None, Some("jitosol"), 100),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_net_keeps_lst_amounts() {
+        let netted = NettedChanges::net(&PoolAllocationChanges::new(vec![
+            decrease("msol", 1000),
+            increase("jitosol", 300),
+        ]));
+        let lst_amounts: u64 = netted
+            .changes
+            .iter()
+            .filter_map(|change| change.decrease.as_ref())
+            .map(|change| change.amount.get_lst_amount())
+            .sum();
+        assert_eq!(lst_amounts, 900);
+        assert_eq!(
+            legs(&netted),
+            vec![
+                leg(Some("msol"), Some("jitosol"), 300),
+                leg(Some("msol"), None, 700),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shrink_to_keeps_sides_netted() {
+        let change = NettedChange::direct(decrease("msol", 1000), increase("jitosol", 1000));
+        let shrunk = change.shrink_to(500);
+        assert_eq!(shrunk.decrease, Some(decrease("msol", 500)));
+        assert_eq!(shrunk.increase, Some(increase("jitosol", 500)));
+        assert_eq!(shrunk.get_labels(), ("msol", "jitosol"));
+        assert_eq!(
+            NettedChange::from_change(decrease("msol", 1000)).get_labels(),
+            ("msol", "wSOL")
+        );
+    }
+
+    #[test]
+    fn test_wsol_funded_lamports() {
+        let netted = NettedChanges::new(vec![
+            NettedChange::direct(decrease("wsol", 300), increase("inf", 300)),
+            NettedChange::direct(decrease("msol", 200), increase("jitosol", 200)),
+            NettedChange::from_change(decrease("bsol", 400)),
+            NettedChange::from_change(increase("inf", 500)),
+            NettedChange::from_change(increase("jitosol", 500)),
+        ]);
+        // The 500 in the reserve funds the direct swap out of it, the 400 the bsol
+        // decrease brings in only part of the increases
+        assert_eq!(
+            netted.wsol_funded_lamports("wsol", 500),
+            vec![300, 200, 400, 500, 100]
+        );
+        assert_eq!(
+            netted.wsol_funded_lamports("wsol", 1000),
+            vec![300, 200, 400, 500, 500]
+        );
+    }
+
+    #[test]
+    fn test_to_pool_allocation_changes_merges_mints() {
+        let netted = NettedChanges::new(vec![
+            NettedChange::direct(decrease("msol", 600), increase("inf", 600)),
+            NettedChange::direct(decrease("msol", 200), increase("jitosol", 200)),
+            NettedChange::from_change(increase("inf", 100)),
+        ]);
+        assert_eq!(
+            netted.to_pool_allocation_changes().assets,
+            vec![
+                decrease("msol", 800),
+                increase("inf", 700),
+                increase("jitosol", 200),
+            ]
+        );
+    }
+}
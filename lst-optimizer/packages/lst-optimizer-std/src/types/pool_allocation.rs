@@ -2,6 +2,7 @@ use std::fmt::Display;
 
 use super::{asset::Asset, pool_asset::PoolAsset};
 use anyhow::Result;
+use rust_decimal::{prelude::Zero, Decimal};
 
 pub const MAX_ALLOCATION_BPS: i16 = 10_000;
 
@@ -28,6 +29,18 @@ impl PoolAllocations {
         None
     }
 
+    /// Get the current allocation of the asset in bps of the total lamports
+    pub fn get_asset_bps(&self, mint: &str) -> Decimal {
+        let total_lamports = self.get_total_lamports();
+        match self.get_pool_asset(mint) {
+            Some(asset) if total_lamports > 0 => {
+                Decimal::from(asset.lamports) * Decimal::from(MAX_ALLOCATION_BPS)
+                    / Decimal::from(total_lamports)
+            }
+            _ => Decimal::zero(),
+        }
+    }
+
     pub fn assert_pool_allocations_are_defined(&self, assets: &Vec<Asset>) -> Result<()> {
         for asset in assets.iter() {
             let mut is_defined = false;
@@ -80,6 +93,22 @@ mod tests {
         assert_eq!(asset.is_none(), true);
     }
 
+    #[test]
+    fn test_get_asset_bps() {
+        let pool_allocations = PoolAllocations {
+            assets: vec![
+                PoolAsset::new("jupsol", 100, 0),
+                PoolAsset::new("inf", 300, 0),
+            ],
+        };
+        assert_eq!(
+            pool_allocations.get_asset_bps("jupsol"),
+            Decimal::from(2500)
+        );
+        assert_eq!(pool_allocations.get_asset_bps("inf"), Decimal::from(7500));
+        assert_eq!(pool_allocations.get_asset_bps("sol"), Decimal::zero());
+    }
+
     #[test]
     fn test_validate_pool_allocations_are_defined() {
         let pool_allocations = PoolAllocations {
@@ -79,7 +79,7 @@ impl Display for PoolAllocationChanges {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PoolAssetChange {
     pub mint: String,
     pub amount: AmountChange,
@@ -0,0 +1,201 @@
+use std::fmt::Display;
+
+const DAYS_PER_YEAR: f64 = 365.0;
+
+/// Options of the cost-aware filtering of the rebalance changes
+#[derive(Debug, Clone, PartialEq)]
+pub struct RebalanceCostOptions {
+    /// Days the rebalanced lamports are expected to stay in the asset
+    pub holding_horizon_days: f64,
+    /// Flat fee paid by every rebalance transaction
+    pub fee_lamports: u64,
+    /// How many times a change that does not pay for itself is halved and quoted again
+    pub max_shrink_steps: u8,
+}
+
+impl Default for RebalanceCostOptions {
+    fn default() -> Self {
+        Self {
+            holding_horizon_days: 30.0,
+            fee_lamports: 10_000,
+            max_shrink_steps: 3,
+        }
+    }
+}
+
+impl RebalanceCostOptions {
+    /// Expected yield of moving `lamports` into an asset earning `apy_gain` more
+    /// over the holding horizon
+    pub fn expected_gain_lamports(&self, lamports: u64, apy_gain: f64) -> f64 {
+        lamports as f64 * apy_gain * self.holding_horizon_days / DAYS_PER_YEAR
+    }
+
+    /// Cost of a swap quoted `quoted_out` for a fair value of `fair_out`, in lamports
+    /// of a change of `lamports`, including the transaction fee
+    pub fn swap_cost_lamports(&self, lamports: u64, fair_out: u64, quoted_out: u64) -> u64 {
+        let loss = fair_out.saturating_sub(quoted_out);
+        let loss_lamports = if fair_out == 0 {
+            0
+        } else {
+            (lamports as u128 * loss as u128 / fair_out as u128) as u64
+        };
+        loss_lamports.saturating_add(self.fee_lamports)
+    }
+}
+
+/// Lamports weighted average of the APYs
+pub fn weighted_apy(weighted_apys: &[(u64, f64)]) -> f64 {
+    let total: u64 = weighted_apys.iter().map(|(weight, _)| weight).sum();
+    if total == 0 {
+        return 0.0;
+    }
+    weighted_apys
+        .iter()
+        .map(|(weight, apy)| *weight as f64 * apy)
+        .sum::<f64>()
+        / total as f64
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RebalanceDecisionKind {
+    /// The change pays for itself
+    Keep,
+    /// Only a part of the change pays for itself
+    Shrink { lamports: u64 },
+    /// The change does not pay for itself
+    Drop,
+}
+
+/// Why a netted change has been kept, shrunk or dropped
+#[derive(Debug, Clone, PartialEq)]
+pub struct RebalanceDecision {
+    /// The mint swapped from, wSOL for an increase through it
+    pub src_mint: String,
+    /// The mint swapped to, wSOL for a decrease through it
+    pub dst_mint: String,
+    pub lamports: u64,
+    pub apy_gain: f64,
+    pub expected_gain_lamports: f64,
+    pub cost_lamports: u64,
+    pub kind: RebalanceDecisionKind,
+    /// Why the change is dropped or shrunk other than its cost
+    pub reason: Option<String>,
+}
+
+impl RebalanceDecision {
+    pub fn new(
+        src_mint: &str,
+        dst_mint: &str,
+        lamports: u64,
+        apy_gain: f64,
+        expected_gain_lamports: f64,
+        cost_lamports: u64,
+        kind: RebalanceDecisionKind,
+    ) -> Self {
+        Self {
+            src_mint: src_mint.to_string(),
+            dst_mint: dst_mint.to_string(),
+            lamports,
+            apy_gain,
+            expected_gain_lamports,
+            cost_lamports,
+            kind,
+            reason: None,
+        }
+    }
+
+    pub fn with_reason(self, reason: &str) -> Self {
+        Self {
+            reason: Some(reason.to_string()),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RebalanceDecisions {
+    pub assets: Vec<RebalanceDecision>,
+}
+
+impl RebalanceDecisions {
+    pub fn new(assets: Vec<RebalanceDecision>) -> Self {
+        Self { assets }
+    }
+
+    pub fn get_decision(&self, src_mint: &str, dst_mint: &str) -> Option<&RebalanceDecision> {
+        self.assets
+            .iter()
+            .find(|asset| asset.src_mint.eq(src_mint) && asset.dst_mint.eq(dst_mint))
+    }
+}
+
+impl Display for RebalanceDecisions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "RebalanceDecisions:")?;
+        for asset in self.assets.iter() {
+            if let Some(reason) = &asset.reason {
+                writeln!(
+                    f,
+                    " - {} -> {}: {:?} of {} lamports, {}",
+                    asset.src_mint, asset.dst_mint, asset.kind, asset.lamports, reason
+                )?;
+                continue;
+            }
+            writeln!(
+                f,
+                " - {} -> {}: {:?} of {} lamports, apy gain {:.4}, expected gain {:.0} lamports, cost {} lamports",
+                asset.src_mint,
+                asset.dst_mint,
+                asset.kind,
+                asset.lamports,
+                asset.apy_gain,
+                asset.expected_gain_lamports,
+                asset.cost_lamports
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expected_gain_lamports() {
+        let options = RebalanceCostOptions {
+            holding_horizon_days: 73.0,
+            ..Default::default()
+        };
+        let gain = options.expected_gain_lamports(1_000_000_000, 0.01);
+        assert!((gain - 2_000_000.0).abs() < 1e-6);
+        assert!(options.expected_gain_lamports(1_000_000_000, -0.01) < 0.0);
+    }
+
+    #[test]
+    fn test_swap_cost_lamports() {
+        let options = RebalanceCostOptions {
+            fee_lamports: 5000,
+            ..Default::default()
+        };
+        // 1% below the fair value
+        assert_eq!(
+            options.swap_cost_lamports(1_000_000, 900_000, 891_000),
+            15_000
+        );
+        // quoted above the fair value only pays the fee
+        assert_eq!(
+            options.swap_cost_lamports(1_000_000, 900_000, 950_000),
+            5000
+        );
+        assert_eq!(options.swap_cost_lamports(1_000_000, 0, 0), 5000);
+    }
+
+    #[test]
+    fn test_weighted_apy() {
+        assert_eq!(weighted_apy(&[]), 0.0);
+        assert_eq!(weighted_apy(&[(0, 0.07)]), 0.0);
+        let apy = weighted_apy(&[(100, 0.06), (300, 0.08)]);
+        assert!((apy - 0.075).abs() < 1e-12);
+    }
+}
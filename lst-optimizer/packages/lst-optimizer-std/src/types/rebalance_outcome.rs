@@ -0,0 +1,224 @@
+use std::fmt::Display;
+
+use thiserror::Error;
+
+/// Why a rebalance did not go through
+#[derive(Debug, Clone, Error, PartialEq)]
+pub enum RebalanceFailure {
+    #[error("The loss guard rejected the swap: {0}")]
+    LossGuardRejected(String),
+
+    #[error("Transaction {0} failed: {1}")]
+    TransactionFailed(String, String),
+
+    #[error("Transaction {0} expired before landing")]
+    TransactionExpired(String),
+
+    #[error("Failed to send the transaction: {0}")]
+    SendFailed(String),
+
+    #[error("Stopped after {0} tranches: {1}")]
+    StoppedEarly(u16, String),
+
+    #[error("No route for the swap: {0}")]
+    NoRoute(String),
+
+    #[error("Unknown asset: {0}")]
+    UnknownAsset(String),
+
+    #[error("The delayed unstake failed: {0}")]
+    DelayedUnstakeFailed(String),
+
+    #[error("Failed to journal the rebalance: {0}")]
+    JournalFailed(String),
+
+    #[error("{0}")]
+    Other(String),
+}
+
+/// The mints a rebalance swapped between and the venues it went through
+#[derive(Debug, Clone, PartialEq)]
+pub struct RebalanceRoute {
+    pub src_mint: String,
+    pub dst_mint: String,
+    pub labels: Vec<String>,
+}
+
+impl RebalanceRoute {
+    pub fn new(src_mint: &str, dst_mint: &str) -> Self {
+        Self {
+            src_mint: src_mint.to_string(),
+            dst_mint: dst_mint.to_string(),
+            labels: vec![],
+        }
+    }
+
+    /// Add the labels not already in the route, the tranches of a swap may take several
+    pub fn add_labels(&mut self, labels: &[String]) {
+        for label in labels {
+            if !self.labels.contains(label) {
+                self.labels.push(label.clone());
+            }
+        }
+    }
+}
+
+/// What a rebalance did, the amounts are read from the landed transactions
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RebalanceOutcome {
+    /// None when the rebalance failed before a route was found
+    pub route: Option<RebalanceRoute>,
+    /// The landed transactions, in order
+    pub signatures: Vec<String>,
+    /// The src amount withdrawn from the pool reserves
+    pub withdrawn: u64,
+    /// The dst amount returned to the pool reserves
+    pub returned: u64,
+    /// SOL value of the returned amount minus the withdrawn one, by the calculators
+    pub sol_value_delta: i64,
+    /// Lamports paid in transaction fees, failed transactions included
+    pub fees: u64,
+    pub error: Option<RebalanceFailure>,
+}
+
+impl RebalanceOutcome {
+    pub fn new(route: RebalanceRoute) -> Self {
+        Self {
+            route: Some(route),
+            ..Default::default()
+        }
+    }
+
+    pub fn failed(error: RebalanceFailure) -> Self {
+        Self {
+            error: Some(error),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl Display for RebalanceOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match &self.error {
+            None => "ok".to_string(),
+            Some(error) => format!("failed: {}", error),
+        };
+        let venues = match &self.route {
+            Some(route) if !route.labels.is_empty() => route.labels.join(", "),
+            _ => "-".to_string(),
+        };
+        write!(
+            f,
+            "withdrawn {}, returned {}, sol value delta {}, fees {} via {} ({} txs): {}",
+            self.withdrawn,
+            self.returned,
+            self.sol_value_delta,
+            self.fees,
+            venues,
+            self.signatures.len(),
+            status
+        )
+    }
+}
+
+/// The outcomes of the changes of a rebalance cycle
+#[derive(Debug, Clone, Default)]
+pub struct RebalanceCycleReport {
+    pub outcomes: Vec<(String, RebalanceOutcome)>,
+}
+
+impl RebalanceCycleReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, change: &str, outcome: RebalanceOutcome) {
+        self.outcomes.push((change.to_string(), outcome));
+    }
+
+    pub fn get_landed(&self) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|(_, outcome)| outcome.is_success())
+            .count()
+    }
+
+    pub fn get_sol_value_delta(&self) -> i64 {
+        self.outcomes
+            .iter()
+            .map(|(_, outcome)| outcome.sol_value_delta)
+            .sum()
+    }
+
+    pub fn get_fees(&self) -> u64 {
+        self.outcomes.iter().map(|(_, outcome)| outcome.fees).sum()
+    }
+}
+
+impl Display for RebalanceCycleReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Rebalanced {} of {} changes, sol value delta {} lamports, fees {} lamports:",
+            self.get_landed(),
+            self.outcomes.len(),
+            self.get_sol_value_delta(),
+            self.get_fees()
+        )?;
+        for (change, outcome) in self.outcomes.iter() {
+            writeln!(f, " - {}: {}", change, outcome)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_labels() {
+        let mut route = RebalanceRoute::new("msol", "wsol");
+        route.add_labels(&["Orca".to_string(), "Raydium".to_string()]);
+        route.add_labels(&["Orca".to_string()]);
+        assert_eq!(route.labels, vec!["Orca", "Raydium"]);
+    }
+
+    #[test]
+    fn test_cycle_report() {
+        let mut route = RebalanceRoute::new("msol", "wsol");
+        route.add_labels(&["Orca".to_string()]);
+        let mut report = RebalanceCycleReport::new();
+        report.add(
+            "msol -> wSOL: 100 lamports",
+            RebalanceOutcome {
+                signatures: vec!["a".to_string(), "b".to_string()],
+                withdrawn: 80,
+                returned: 99,
+                sol_value_delta: -1,
+                fees: 10,
+                ..RebalanceOutcome::new(route)
+            },
+        );
+        report.add(
+            "wSOL -> jupsol: 50 lamports",
+            RebalanceOutcome {
+                fees: 5,
+                ..RebalanceOutcome::failed(RebalanceFailure::TransactionExpired("c".to_string()))
+            },
+        );
+
+        assert_eq!(report.get_landed(), 1);
+        assert_eq!(report.get_sol_value_delta(), -1);
+        assert_eq!(report.get_fees(), 15);
+        let lines: Vec<String> = report.to_string().lines().map(String::from).collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("Rebalanced 1 of 2 changes"));
+        assert!(lines[1].contains("via Orca (2 txs): ok"));
+        assert!(lines[2].ends_with("failed: Transaction c expired before landing"));
+    }
+}
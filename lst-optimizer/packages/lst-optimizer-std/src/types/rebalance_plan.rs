@@ -0,0 +1,166 @@
+use std::fmt::Display;
+
+use rust_decimal::Decimal;
+
+use super::amount_change::AmountChange;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimulationResult {
+    /// No transaction to simulate
+    Skipped,
+    Success {
+        units_consumed: Option<u64>,
+    },
+    Failure(String),
+}
+
+impl Display for SimulationResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SimulationResult::Skipped => write!(f, "-"),
+            SimulationResult::Success {
+                units_consumed: Some(units_consumed),
+            } => write!(f, "ok ({} CU)", units_consumed),
+            SimulationResult::Success {
+                units_consumed: None,
+            } => write!(f, "ok"),
+            SimulationResult::Failure(err) => write!(f, "failed: {}", err),
+        }
+    }
+}
+
+/// What the rebalance would do to an asset
+#[derive(Debug, Clone, PartialEq)]
+pub struct RebalancePlanAsset {
+    pub mint: String,
+    pub symbol: String,
+    pub current_bps: Decimal,
+    pub target_bps: Decimal,
+    pub current_lamports: u64,
+    pub target_lamports: u64,
+    pub change: Option<AmountChange>,
+}
+
+/// A netted change the rebalance would send and how its transactions simulate
+#[derive(Debug, Clone, PartialEq)]
+pub struct RebalancePlanSwap {
+    pub change: String,
+    pub quoted_out: Option<u64>,
+    /// The transaction setting up the accounts, sent before the rebalance one
+    pub prep_simulation: SimulationResult,
+    pub simulation: SimulationResult,
+}
+
+#[derive(Debug, Clone)]
+pub struct RebalancePlan {
+    pub assets: Vec<RebalancePlanAsset>,
+    pub swaps: Vec<RebalancePlanSwap>,
+}
+
+impl RebalancePlan {
+    pub fn new(assets: Vec<RebalancePlanAsset>, swaps: Vec<RebalancePlanSwap>) -> Self {
+        Self { assets, swaps }
+    }
+
+    pub fn get_asset_plan(&self, mint: &str) -> Option<&RebalancePlanAsset> {
+        self.assets.iter().find(|asset| asset.mint.eq(mint))
+    }
+}
+
+impl Display for RebalancePlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:<10} {:>10} {:>10} {:>20} {:>20} {:>30}",
+            "symbol", "cur bps", "tgt bps", "cur lamports", "tgt lamports", "change (lamports/lst)"
+        )?;
+        for asset in self.assets.iter() {
+            let change = match &asset.change {
+                Some(AmountChange::Increase {
+                    lamports,
+                    lst_amount,
+                }) => format!("+{}/{}", lamports, lst_amount),
+                Some(AmountChange::Decrease {
+                    lamports,
+                    lst_amount,
+                }) => format!("-{}/{}", lamports, lst_amount),
+                None => "-".to_string(),
+            };
+            writeln!(
+                f,
+                "{:<10} {:>10} {:>10} {:>20} {:>20} {:>30}",
+                asset.symbol,
+                asset.current_bps.round_dp(2),
+                asset.target_bps.round_dp(2),
+                asset.current_lamports,
+                asset.target_lamports,
+                change
+            )?;
+        }
+        for swap in self.swaps.iter() {
+            let quoted_out = match swap.quoted_out {
+                Some(quoted_out) => quoted_out.to_string(),
+                None => "-".to_string(),
+            };
+            writeln!(
+                f,
+                " - {}: quoted out {}, prep {}, rebalance {}",
+                swap.change, quoted_out, swap.prep_simulation, swap.simulation
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_rebalance_plan() {
+        let plan = RebalancePlan::new(
+            vec![
+                RebalancePlanAsset {
+                    mint: "jupsol".to_string(),
+                    symbol: "jupSOL".to_string(),
+                    current_bps: Decimal::from(2500),
+                    target_bps: Decimal::from(5000),
+                    current_lamports: 250,
+                    target_lamports: 500,
+                    change: Some(AmountChange::Increase {
+                        lamports: 250,
+                        lst_amount: 240,
+                    }),
+                },
+                RebalancePlanAsset {
+                    mint: "inf".to_string(),
+                    symbol: "INF".to_string(),
+                    current_bps: Decimal::from(5000),
+                    target_bps: Decimal::from(5000),
+                    current_lamports: 500,
+                    target_lamports: 500,
+                    change: None,
+                },
+            ],
+            vec![RebalancePlanSwap {
+                change: "msol -> jupsol: 250 lamports".to_string(),
+                quoted_out: Some(239),
+                prep_simulation: SimulationResult::Skipped,
+                simulation: SimulationResult::Success {
+                    units_consumed: Some(200_000),
+                },
+            }],
+        );
+        let lines: Vec<String> = plan.to_string().lines().map(String::from).collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with("jupSOL"));
+        assert!(lines[1].ends_with("+250/240"));
+        assert!(lines[2].starts_with("INF"));
+        assert!(lines[2].ends_with("-"));
+        assert_eq!(
+            lines[3],
+            " - msol -> jupsol: 250 lamports: quoted out 239, prep -, rebalance ok (200000 CU)"
+        );
+        assert_eq!(plan.get_asset_plan("inf").unwrap().change, None);
+    }
+}